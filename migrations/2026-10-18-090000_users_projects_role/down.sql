@@ -0,0 +1 @@
+alter table users_projects drop column role;
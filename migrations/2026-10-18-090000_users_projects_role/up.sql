@@ -0,0 +1,6 @@
+alter table users_projects
+    add column role varchar(20) default 'viewer' not null
+    check (role in ('owner', 'editor', 'viewer'));
+
+-- Until now the only members were creators of the projects.
+update users_projects set role = 'owner';
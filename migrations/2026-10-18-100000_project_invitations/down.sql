@@ -0,0 +1,4 @@
+drop table invitation_acceptances;
+
+drop trigger update_updated_at_trigger on project_invitations;
+drop table project_invitations;
@@ -0,0 +1,25 @@
+create table project_invitations (
+    id uuid default gen_random_uuid() primary key,
+    project_id uuid references projects (id) not null,
+    token_hash varchar(64) unique not null,
+    role varchar(20) not null check (role in ('owner', 'editor', 'viewer')),
+    -- null for invitations without uses limit
+    max_uses integer check (max_uses > 0),
+    use_count integer default 0 not null,
+    created_by uuid references users (id) not null,
+    expires_at timestamp not null,
+    revoked_at timestamp,
+    created_at timestamp default now() not null,
+    updated_at timestamp default now() not null
+);
+
+create trigger update_updated_at_trigger before
+update
+    on project_invitations for each row execute function update_updated_at();
+
+create table invitation_acceptances (
+    invitation_id uuid references project_invitations (id) not null,
+    user_id uuid references users (id) not null,
+    created_at timestamp default now() not null,
+    primary key (invitation_id, user_id)
+);
@@ -0,0 +1,3 @@
+drop table design_revisions;
+
+alter table designs drop column revision;
@@ -0,0 +1,15 @@
+alter table designs add column revision integer default 0 not null;
+
+create table design_revisions (
+    design_id uuid references designs (id) not null,
+    revision integer not null,
+    data jsonb not null,
+    author_id uuid references users (id),
+    -- set when the revision restores an older one
+    restored_from integer,
+    created_at timestamp default now() not null,
+    primary key (design_id, revision)
+);
+
+insert into design_revisions (design_id, revision, data, created_at)
+select id, revision, data, updated_at from designs;
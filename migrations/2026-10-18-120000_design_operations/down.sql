@@ -0,0 +1 @@
+alter table design_revisions drop column patch;
@@ -0,0 +1,3 @@
+-- JSON Patch which made the revision from the previous one,
+-- revisions saved before are not in the operation log.
+alter table design_revisions add column patch jsonb;
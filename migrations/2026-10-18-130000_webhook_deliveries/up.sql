@@ -0,0 +1,19 @@
+create table webhook_deliveries (
+    id uuid default gen_random_uuid() primary key,
+    -- X-GitHub-Delivery header of the delivery
+    delivery_id varchar(64) unique not null,
+    event varchar(50) not null,
+    payload jsonb not null,
+    -- set when the delivery is about a known repository
+    repository_id uuid references repositories (id),
+    status varchar(20) default 'received' not null check (
+        status in ('received', 'processed', 'ignored', 'conflict', 'failed')
+    ),
+    error text,
+    created_at timestamp default now() not null,
+    updated_at timestamp default now() not null
+);
+
+create trigger update_updated_at_trigger before
+update
+    on webhook_deliveries for each row execute function update_updated_at();
@@ -0,0 +1 @@
+drop table design_pull_requests;
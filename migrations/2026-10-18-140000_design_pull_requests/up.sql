@@ -0,0 +1,17 @@
+create table design_pull_requests (
+    id uuid default gen_random_uuid() primary key,
+    repository_id uuid references repositories (id) on delete cascade not null,
+    branch varchar(255) not null,
+    number integer not null,
+    html_url text not null,
+    state varchar(20) default 'open' not null check (state in ('open', 'closed', 'merged')),
+    -- blob SHA of the design file on the branch
+    design_file_sha varchar(50),
+    created_at timestamp default now() not null,
+    updated_at timestamp default now() not null,
+    unique (repository_id, number)
+);
+
+create trigger update_updated_at_trigger before
+update
+    on design_pull_requests for each row execute function update_updated_at();
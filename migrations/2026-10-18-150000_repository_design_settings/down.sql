@@ -0,0 +1,5 @@
+alter table repositories
+    drop column design_path,
+    drop column design_branch,
+    drop column design_pretty,
+    drop column design_trailing_newline;
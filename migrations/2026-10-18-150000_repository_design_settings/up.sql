@@ -0,0 +1,6 @@
+alter table repositories
+    add column design_path varchar(255) default 'design.json' not null,
+    -- the default branch of the repository when null
+    add column design_branch varchar(255),
+    add column design_pretty boolean default false not null,
+    add column design_trailing_newline boolean default false not null;
@@ -0,0 +1 @@
+alter table repositories drop column design_layout;
@@ -0,0 +1,3 @@
+alter table repositories
+    -- 'split' keeps the design as a directory of files at design_path
+    add column design_layout varchar(20) default 'file' not null check (design_layout in ('file', 'split'));
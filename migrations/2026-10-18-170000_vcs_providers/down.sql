@@ -0,0 +1,5 @@
+alter table users drop column provider;
+
+alter table repositories drop constraint repositories_provider_name_owner_is_organization_key;
+alter table repositories add unique (name, owner, is_organization);
+alter table repositories drop column provider;
@@ -0,0 +1,9 @@
+alter table repositories
+    add column provider varchar(20) default 'github' not null check (provider in ('github', 'gitlab'));
+-- The same owner and name could be taken on every provider.
+alter table repositories drop constraint repositories_name_owner_is_organization_key;
+alter table repositories add unique (provider, name, owner, is_organization);
+
+alter table users
+    -- Provider which has issued the access token, the user has signed in with it last
+    add column provider varchar(20) default 'github' not null check (provider in ('github', 'gitlab'));
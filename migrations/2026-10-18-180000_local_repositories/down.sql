@@ -0,0 +1,3 @@
+alter table repositories drop constraint repositories_provider_check;
+alter table repositories
+    add constraint repositories_provider_check check (provider in ('github', 'gitlab'));
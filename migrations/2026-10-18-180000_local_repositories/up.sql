@@ -0,0 +1,4 @@
+-- Local repositories are bare git repositories on the disk of the server, users don't sign in with them.
+alter table repositories drop constraint repositories_provider_check;
+alter table repositories
+    add constraint repositories_provider_check check (provider in ('github', 'gitlab', 'local'));
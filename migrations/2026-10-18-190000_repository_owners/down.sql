@@ -0,0 +1,6 @@
+alter table webhook_deliveries drop constraint webhook_deliveries_repository_id_fkey;
+alter table webhook_deliveries
+    add constraint webhook_deliveries_repository_id_fkey
+    foreign key (repository_id) references repositories (id);
+
+alter table repositories drop column created_by;
@@ -0,0 +1,8 @@
+-- User who has created or imported the repository, it is listed for them until it is linked to a project.
+alter table repositories add column created_by uuid references users (id) on delete set null;
+
+-- Deliveries are kept when their repository is deleted.
+alter table webhook_deliveries drop constraint webhook_deliveries_repository_id_fkey;
+alter table webhook_deliveries
+    add constraint webhook_deliveries_repository_id_fkey
+    foreign key (repository_id) references repositories (id) on delete set null;
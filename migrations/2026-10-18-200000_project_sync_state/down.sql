@@ -0,0 +1,5 @@
+alter table projects
+    drop column sync_state,
+    drop column pushed_revision,
+    drop column pushed_commit_sha,
+    drop column sync_error;
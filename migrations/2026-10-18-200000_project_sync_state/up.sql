@@ -0,0 +1,7 @@
+alter table projects
+    -- null until the design is saved to or pulled from the linked repository
+    add column sync_state varchar(20) check (sync_state in ('in_sync', 'local_ahead', 'remote_ahead', 'diverged', 'push_failed')),
+    -- design revision and commit of the last successful save to the repository
+    add column pushed_revision integer,
+    add column pushed_commit_sha varchar(50),
+    add column sync_error text;
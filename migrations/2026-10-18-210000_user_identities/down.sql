@@ -0,0 +1,13 @@
+alter table users add column access_token text unique default null;
+update users set access_token = user_identities.access_token
+from user_identities
+where user_identities.user_id = users.id and user_identities.provider = users.provider;
+
+delete from user_refresh_tokens
+using users
+where users.id = user_refresh_tokens.user_id and users.provider <> user_refresh_tokens.provider;
+alter table user_refresh_tokens drop constraint user_refresh_tokens_pkey;
+alter table user_refresh_tokens add primary key (user_id);
+alter table user_refresh_tokens drop column provider;
+
+drop table user_identities;
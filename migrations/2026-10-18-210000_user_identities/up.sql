@@ -0,0 +1,31 @@
+-- Accounts of the providers a user signs in with. An account signs in as the user
+-- it is linked to, accounts are linked to an existing user only by the user.
+create table user_identities (
+    user_id uuid references users (id) on delete cascade not null,
+    provider varchar(20) not null check (provider in ('github', 'gitlab')),
+    -- id of the account on the provider, null for the accounts linked before it was recorded
+    provider_user_id varchar(100),
+    access_token text,
+    created_at timestamp default now() not null,
+    updated_at timestamp default now() not null,
+    primary key (user_id, provider),
+    unique (provider, provider_user_id)
+);
+
+create trigger update_updated_at_trigger before
+update
+    on user_identities for each row execute function update_updated_at();
+
+insert into user_identities (user_id, provider, access_token)
+select id, provider, access_token from users where access_token is not null;
+
+-- A refresh token per provider the user has signed in with.
+alter table user_refresh_tokens
+    add column provider varchar(20) default 'github' not null check (provider in ('github', 'gitlab'));
+update user_refresh_tokens set provider = users.provider
+from users where users.id = user_refresh_tokens.user_id;
+alter table user_refresh_tokens alter column provider drop default;
+alter table user_refresh_tokens drop constraint user_refresh_tokens_pkey;
+alter table user_refresh_tokens add primary key (user_id, provider);
+
+alter table users drop column access_token;
@@ -1,4 +1,4 @@
-use crate::{models, routes};
+use crate::{models, routes, services};
 use utoipa::{
     openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
     Modify, OpenApi,
@@ -9,44 +9,106 @@ use utoipa::{
     paths(
         routes::designs::get_design,
         routes::designs::update_design,
+        routes::designs::patch_design,
+        routes::designs::validate_design,
+        routes::designs::merge_operations,
+        routes::designs::collab::issue_ticket,
+        routes::designs::collab::connect,
+        routes::designs::get_design_revisions,
+        routes::designs::get_design_revision,
+        routes::designs::restore_design_revision,
 
         routes::users::create_user,
         routes::users::find_user,
         routes::users::find_user_by_token,
         routes::users::get_user,
         routes::users::update_user,
-        routes::users::update_user_token,
         // routes::users::get_user_roles,
 
         routes::repositories::create_repo,
+        routes::repositories::get_repos,
+        routes::repositories::get_repo,
+        routes::repositories::update_repo,
+        routes::repositories::delete_repo,
+        routes::repositories::import_repo,
+        routes::repositories::get_pull_requests,
+        routes::repositories::update_design_settings,
         routes::repositories::save_repo_design,
+        routes::repositories::pull_repo_design,
+        routes::repositories::get_webhook_deliveries,
+        routes::repositories::replay_webhook_delivery,
+
+        routes::webhooks::github_webhook,
 
         routes::projects::create_project,
         routes::projects::find_project,
         routes::projects::get_project,
         routes::projects::get_user_projects,
         routes::projects::update_project,
+        routes::projects::get_project_members,
+        routes::projects::add_project_member,
+        routes::projects::update_project_member,
+        routes::projects::remove_project_member,
+
+        routes::invitations::create_invitation,
+        routes::invitations::get_project_invitations,
+        routes::invitations::revoke_invitation,
+        routes::invitations::accept_invitation,
 
+        routes::auth::get_jwks,
         routes::auth::github::generate_access_token,
+        routes::auth::gitlab::generate_access_token,
     ),
     components(
         schemas(
             models::designs::Design, 
+            models::designs::DesignRevision,
+            models::designs::DesignRevisionInfo,
+            services::validation::Violation,
+            routes::designs::InputOperations,
+            routes::designs::MergeResult,
+            routes::designs::collab::ConnectTicket,
 
             models::users::RoledUser, 
             // models::users::UserRole,
             models::users::User,
+            routes::users::NewUserInput,
             routes::users::UserInput,
 
             models::repositories::Repository,
+            models::repositories::Provider,
             routes::repositories::InputRepository,
             routes::repositories::SaveRepoDesign,
+            routes::repositories::ImportRepository,
+            routes::repositories::ImportedRepository,
+            routes::repositories::SavedRepoDesign,
+            models::repositories::DesignSettings,
+            routes::repositories::UpdateRepositorySettings,
+            models::pull_requests::DesignPullRequest,
+            models::pull_requests::PullRequestState,
+            routes::repositories::PullStatus,
+            services::merge::MergeConflict,
+            routes::repositories::PulledDesign,
+
+            models::webhooks::WebhookDelivery,
+            models::webhooks::WebhookStatus,
 
             models::projects::Project,
+            models::projects::ProjectRole,
+            models::projects::SyncState,
+            models::projects::ProjectMember,
             routes::projects::InputProject,
+            routes::projects::InputProjectMember,
+            routes::projects::InputMemberRole,
+
+            models::invitations::ProjectInvitation,
+            models::invitations::InvitationStatus,
+            routes::invitations::InputInvitation,
+            routes::invitations::CreatedInvitation,
+            routes::invitations::InvitationInfo,
             
-            routes::auth::github::AccessTokenQuery,
-            routes::auth::github::SaveAccessTokenResponse,
+            routes::auth::AccessTokenQuery,
+            routes::auth::SaveAccessTokenResponse,
         )
     ),
     tags(
@@ -54,7 +116,11 @@ use utoipa::{
         (name = "Users", description = "Users management endpoints."),
         (name = "Repositories", description = "Repositories management endpoints."),
         (name = "Projects", description = "Projects management endpoints."),
+        (name = "Invitations", description = "Project invitations management endpoints."),
+        (name = "Webhooks", description = "GitHub webhook endpoints."),
+        (name = "Auth", description = "Session tokens endpoints."),
         (name = "Auth Github", description = "Github Auth management endpoints."),
+        (name = "Auth Gitlab", description = "Gitlab Auth management endpoints."),
     ),
     modifiers(&SecurityAddon)
 )]
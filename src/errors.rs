@@ -1,12 +1,17 @@
 use actix_web::{
     error,
-    http::{header::ContentType, header::ToStrError, StatusCode},
+    http::{
+        header::{ContentType, ETag, EntityTag, ToStrError, RETRY_AFTER},
+        StatusCode,
+    },
     HttpResponse,
 };
 use diesel::result::DatabaseErrorKind::UniqueViolation;
 use diesel::result::Error::{DatabaseError, NotFound};
 use std::fmt;
 
+use crate::services::{merge::MergeConflict, validation::Violation};
+
 #[derive(Debug)]
 pub enum AppError {
     RecordAlreadyExists,
@@ -17,22 +22,54 @@ pub enum AppError {
     UuidParseError(uuid::Error),
     AuthError,
     HeaderParse(String),
-    JWKSFetchError,
     PermissionError,
+    LastOwnerError,
+    PreconditionFailed(i32),
+    /// Changes since the base revision of merged operations are not logged anymore,
+    /// with the current revision.
+    BaseRevisionPruned(i32),
+    UnsupportedMediaType(String),
+    PatchTestFailed(String),
+    PatchError(String),
+    DesignValidation(Vec<Violation>),
+    InvitationError(String),
     OutsideRequestError(String),
     UrlParse(String),
     JsonParse(String),
+    InvalidInput(String),
     UrlEncodedParse(String),
     InvalidHeaderValue(String),
     HexParse(String),
     CryptoError(String),
     GithubAuthError(String),
     GithubAPIError(String),
+    /// Unsuccessful response of GitHub with its status and message.
+    GithubStatusError(u16, String),
+    /// GitHub rate limit, with the number of seconds to wait.
+    GithubRateLimited(u64),
+    GitlabAuthError(String),
+    GitlabAPIError(String),
+    /// Unsuccessful response of GitLab with its status and message.
+    GitlabStatusError(u16, String),
+    LocalGitError(String),
+    /// An optional integration the request needs is not configured on the server.
+    NotConfigured(String),
+    /// The file in the repository has changed since its SHA was read.
+    RepoFileConflict(String),
+    /// The design and the design file have changed the same values, they couldn't be merged.
+    DesignConflict(Vec<MergeConflict>),
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse<'a> {
+    err: String,
+    violations: &'a [Violation],
 }
 
 #[derive(Debug, Serialize)]
-struct ErrorResponse {
+struct ConflictResponse<'a> {
     err: String,
+    conflicts: &'a [MergeConflict],
 }
 
 impl fmt::Display for AppError {
@@ -49,39 +86,105 @@ impl fmt::Display for AppError {
                 "Unauthorized request. Pass user access token in request header."
             ),
             AppError::HeaderParse(e) => write!(f, "Header parse error: {:?}", e),
-            AppError::JWKSFetchError => write!(f, "Could not fetch JWKS"),
             AppError::PermissionError => write!(
                 f,
                 "User authorized by token doesn't have needed access permission."
             ),
+            AppError::LastOwnerError => write!(f, "Project must have at least one owner."),
+            AppError::PreconditionFailed(revision) => write!(
+                f,
+                "Record has been modified, current revision is {}.",
+                revision
+            ),
+            AppError::BaseRevisionPruned(revision) => write!(
+                f,
+                "Changes since the base revision are not available anymore, current revision is {}. Reload the design.",
+                revision
+            ),
+            AppError::InvitationError(e) => write!(f, "Invitation error: {:?}", e),
+            AppError::UnsupportedMediaType(e) => write!(f, "Unsupported media type: {:?}", e),
+            AppError::PatchTestFailed(e) => write!(f, "Patch test failed: {:?}", e),
+            AppError::PatchError(e) => write!(f, "Patch could not be applied: {:?}", e),
+            AppError::DesignValidation(violations) => write!(
+                f,
+                "Design doesn't match the design model, {} violation(s) found.",
+                violations.len()
+            ),
             AppError::OutsideRequestError(e) => {
                 write!(f, "Outside HTTP Request failed. Error: {:?}", e)
             }
             AppError::UrlParse(e) => write!(f, "URL parse error: {:?}", e),
             AppError::JsonParse(e) => write!(f, "JSON parse error: {:?}", e),
+            AppError::InvalidInput(e) => write!(f, "Invalid input: {:?}", e),
             AppError::UrlEncodedParse(e) => write!(f, "URLEncoded type parse error: {:?}", e),
             AppError::InvalidHeaderValue(e) => write!(f, "Invalid header value, error: {:?}", e),
             AppError::HexParse(e) => write!(f, "Hex parse error: {:?}", e),
             AppError::CryptoError(e) => write!(f, "Crypto operation error: {:?}", e),
             AppError::GithubAuthError(e) => write!(f, "Github Auth error: {:?}", e),
             AppError::GithubAPIError(e) => write!(f, "Github API error: {:?}", e),
+            AppError::GithubStatusError(status, message) => {
+                write!(f, "Github API responded with {}: {:?}", status, message)
+            }
+            AppError::GithubRateLimited(seconds) => write!(
+                f,
+                "Github API rate limit exceeded, retry in {} seconds.",
+                seconds
+            ),
+            AppError::GitlabAuthError(e) => write!(f, "Gitlab Auth error: {:?}", e),
+            AppError::GitlabAPIError(e) => write!(f, "Gitlab API error: {:?}", e),
+            AppError::GitlabStatusError(status, message) => {
+                write!(f, "Gitlab API responded with {}: {:?}", status, message)
+            }
+            AppError::LocalGitError(e) => write!(f, "Local git error: {:?}", e),
+            AppError::NotConfigured(e) => write!(f, "Not configured: {}", e),
+            AppError::RepoFileConflict(e) => write!(f, "Repository file conflict: {:?}", e),
+            AppError::DesignConflict(conflicts) => write!(
+                f,
+                "Design couldn't be merged with the design file, {} conflict(s) found.",
+                conflicts.len()
+            ),
         }
     }
 }
 
 impl actix_web::ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
-        HttpResponse::build(self.status_code())
-            .insert_header(ContentType::plaintext())
-            .body(self.to_string())
+        let mut response = HttpResponse::build(self.status_code());
+
+        if let AppError::DesignValidation(violations) = self {
+            return response.json(ErrorResponse {
+                err: self.to_string(),
+                violations,
+            });
+        }
+
+        if let AppError::DesignConflict(conflicts) = self {
+            return response.json(ConflictResponse {
+                err: self.to_string(),
+                conflicts,
+            });
+        }
+
+        response.insert_header(ContentType::plaintext());
+
+        if let AppError::PreconditionFailed(revision) | AppError::BaseRevisionPruned(revision) = self {
+            response.insert_header(ETag(EntityTag::new_strong(revision.to_string())));
+        }
+
+        if let AppError::GithubRateLimited(seconds) = self {
+            response.insert_header((RETRY_AFTER, seconds.to_string()));
+        }
+
+        response.body(self.to_string())
     }
 
     fn status_code(&self) -> StatusCode {
         match self {
             AppError::RecordAlreadyExists => StatusCode::BAD_REQUEST,
-            AppError::RecordNotFound
-            | AppError::HeaderParse(_)
+            AppError::RecordNotFound => StatusCode::NOT_FOUND,
+            AppError::HeaderParse(_)
             | AppError::JsonParse(_)
+            | AppError::InvalidInput(_)
             | AppError::UrlEncodedParse(_)
             | AppError::InvalidHeaderValue(_) => StatusCode::BAD_REQUEST,
             AppError::DatabaseError(_)
@@ -92,11 +195,33 @@ impl actix_web::ResponseError for AppError {
             | AppError::CryptoError(_)
             | AppError::UrlParse(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::AuthError => StatusCode::UNAUTHORIZED,
-            AppError::JWKSFetchError => StatusCode::BAD_REQUEST,
             AppError::PermissionError => StatusCode::FORBIDDEN,
-            AppError::HexParse(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::LastOwnerError => StatusCode::CONFLICT,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::BaseRevisionPruned(_) => StatusCode::CONFLICT,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::PatchTestFailed(_) => StatusCode::CONFLICT,
+            AppError::PatchError(_) | AppError::DesignValidation(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+            AppError::InvitationError(_) => StatusCode::GONE,
+            AppError::HexParse(_) | AppError::LocalGitError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::RepoFileConflict(_) | AppError::DesignConflict(_) => StatusCode::CONFLICT,
             AppError::GithubAuthError(_)
-            | AppError::GithubAPIError(_) => StatusCode::BAD_GATEWAY,
+            | AppError::GithubAPIError(_)
+            | AppError::GitlabAuthError(_)
+            | AppError::GitlabAPIError(_) => StatusCode::BAD_GATEWAY,
+            // Client errors are caused by the request, e.g. an invalid value for GitHub.
+            // Authentication and access errors of the provider are about the credentials
+            // of the server and the user there, they aren't passed on as errors of this API.
+            AppError::GithubStatusError(status, _)
+            | AppError::GitlabStatusError(status, _) => StatusCode::from_u16(*status)
+                .ok()
+                .filter(StatusCode::is_client_error)
+                .filter(|status| !matches!(status.as_u16(), 401 | 403 | 404))
+                .unwrap_or(StatusCode::BAD_GATEWAY),
+            AppError::GithubRateLimited(_) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::NotConfigured(_) => StatusCode::SERVICE_UNAVAILABLE,
         }
     }
 }
@@ -171,6 +296,15 @@ impl From<hex::FromHexError> for AppError {
     }
 }
 
+impl From<json_patch::PatchError> for AppError {
+    fn from(e: json_patch::PatchError) -> Self {
+        match e.kind {
+            json_patch::PatchErrorKind::TestFailed => AppError::PatchTestFailed(e.to_string()),
+            _ => AppError::PatchError(e.to_string()),
+        }
+    }
+}
+
 impl From<ring::error::Unspecified> for AppError {
     fn from(e: ring::error::Unspecified) -> Self {
         AppError::CryptoError(e.to_string())
@@ -10,6 +10,12 @@ use diesel_migrations::{embed_migrations, EmbeddedMigrations, MigrationHarness};
 use utoipa::OpenApi;
 use utoipa_swagger_ui::SwaggerUi;
 
+use crate::models::designs::RevisionRetention;
+use crate::services::collab::DesignHub;
+use crate::services::github::WebhookSecret;
+use crate::services::session::SessionKeys;
+use crate::services::vcs::VcsProviders;
+
 mod apidoc;
 mod errors;
 mod models;
@@ -31,9 +37,15 @@ impl Server {
     }
 
     pub async fn run(&self, database_url: String) -> std::io::Result<()> {
-        // Middleware for checking our generated tokens from OAuth 2.0.
-        // Could be used in future
-        let _auth_middleware = HttpAuthentication::bearer(routes::validator);
+        // Middleware for checking our session tokens issued after OAuth 2.0.
+        let auth_middleware = HttpAuthentication::bearer(routes::validator);
+        let session_keys = web::Data::new(SessionKeys::new());
+        let revision_retention = web::Data::new(RevisionRetention::from_env());
+        // Shared by all workers, so editors on different workers see changes of each other.
+        let design_hub = web::Data::new(DesignHub::default());
+        let webhook_secret = web::Data::new(WebhookSecret::from_env());
+        // Shared, so the response cache and the connections are reused by all workers.
+        let vcs_providers = web::Data::new(VcsProviders::new().expect("Failed to create VCS provider clients"));
         let openapi = apidoc::ApiDoc::openapi();
         let manager = ConnectionManager::<PgConnection>::new(database_url);
         let pool = r2d2::Pool::builder()
@@ -47,24 +59,40 @@ impl Server {
         HttpServer::new(move || {
             let cors = Cors::default()
                 .allowed_origin("https://locahost:5173")
-                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH"])
+                .allowed_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
                 .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
                 .allowed_header(header::CONTENT_TYPE)
+                .allowed_header(header::IF_MATCH)
+                .expose_headers(vec![header::ETAG])
                 .max_age(3600);
 
             App::new()
-                // .wrap(auth_middleware.clone())
                 .wrap(cors)
                 .app_data(web::Data::new(pool.clone()))
-                .configure(routes::users::configure)
-                .configure(routes::projects::configure)
-                .configure(routes::designs::configure)
-                .configure(routes::repositories::configure)
+                .app_data(session_keys.clone())
+                .app_data(revision_retention.clone())
+                .app_data(design_hub.clone())
+                .app_data(webhook_secret.clone())
+                .app_data(vcs_providers.clone())
+                .configure(routes::auth::configure)
                 .configure(routes::auth::github::configure)
+                .configure(routes::auth::gitlab::configure)
+                .configure(routes::webhooks::configure)
+                .configure(routes::users::configure_github_token)
+                .configure(routes::designs::configure_collab)
                 .service(
                     SwaggerUi::new("/swagger-ui/{_:.*}")
                         .url("/api-docs/openapi.json", openapi.clone()),
                 )
+                .service(
+                    web::scope("")
+                        .wrap(auth_middleware.clone())
+                        .configure(routes::users::configure)
+                        .configure(routes::projects::configure)
+                        .configure(routes::designs::configure)
+                        .configure(routes::repositories::configure)
+                        .configure(routes::invitations::configure),
+                )
         })
         .bind(("0.0.0.0", self.port))?
         .run()
@@ -5,4 +5,7 @@ pub type Result<T> = std::result::Result<T, AppError>;
 pub(super) mod users;
 pub(super) mod projects;
 pub(super) mod repositories;
-pub(super) mod designs;
\ No newline at end of file
+pub(super) mod designs;
+pub(super) mod invitations;
+pub(super) mod webhooks;
+pub(super) mod pull_requests;
\ No newline at end of file
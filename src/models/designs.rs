@@ -1,11 +1,16 @@
 use crate::errors::AppError;
 use crate::models::Result;
 use crate::schema::*;
+use crate::services::{rebase, validation};
 use chrono::NaiveDateTime;
+use diesel::dsl::IntervalDsl;
 use diesel::prelude::*;
+use json_patch::{Patch, PatchOperation, ReplaceOperation};
 use utoipa::ToSchema;
 use uuid::Uuid;
 
+use std::env;
+
 #[derive(Insertable, Queryable, Selectable, Identifiable, Serialize, ToSchema, Debug, PartialEq)]
 #[diesel(table_name = designs)]
 #[serde(rename_all = "camelCase")]
@@ -13,18 +18,94 @@ use uuid::Uuid;
 pub struct Design {
     pub id: Uuid,
     pub data: serde_json::Value,
+    pub revision: i32,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
 }
 
-pub fn create_design(conn: &mut PgConnection) -> Result<Design> {
+/// Immutable snapshot of a design made by every update of the design.
+#[derive(Queryable, Selectable, Associations, Serialize, ToSchema, Debug, PartialEq)]
+#[diesel(belongs_to(Design))]
+#[diesel(table_name = design_revisions)]
+#[serde(rename_all = "camelCase")]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DesignRevision {
+    pub design_id: Uuid,
+    pub revision: i32,
+    pub data: serde_json::Value,
+    pub author_id: Option<Uuid>,
+    pub restored_from: Option<i32>,
+    pub created_at: NaiveDateTime,
+    /// JSON Patch which made the revision from the previous one.
+    pub patch: Option<serde_json::Value>,
+}
+
+/// Design revision without the design data.
+#[derive(Queryable, Selectable, Serialize, ToSchema, Debug, PartialEq)]
+#[diesel(table_name = design_revisions)]
+#[serde(rename_all = "camelCase")]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DesignRevisionInfo {
+    pub revision: i32,
+    /// `null` for revisions pushed to the repository.
+    pub author_id: Option<Uuid>,
+    pub restored_from: Option<i32>,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = design_revisions)]
+struct NewDesignRevision<'a> {
+    design_id: Uuid,
+    revision: i32,
+    data: &'a serde_json::Value,
+    author_id: Option<Uuid>,
+    restored_from: Option<i32>,
+    patch: Option<serde_json::Value>,
+}
+
+/// How long old design revisions are kept. The current revision is never removed.
+///
+/// `DESIGN_REVISIONS_KEEP` limits the number of kept revisions,
+/// `DESIGN_REVISIONS_KEEP_DAYS` limits their age. Both limits apply when both are set,
+/// all revisions are kept when none is set.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct RevisionRetention {
+    pub keep: Option<i32>,
+    pub keep_days: Option<i32>,
+}
+
+impl RevisionRetention {
+    pub fn from_env() -> Self {
+        let parse = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .map(|v| v.parse::<i32>().unwrap_or_else(|_| panic!("{name} must be a number")))
+                .filter(|v| *v > 0)
+        };
+
+        RevisionRetention {
+            keep: parse("DESIGN_REVISIONS_KEEP"),
+            keep_days: parse("DESIGN_REVISIONS_KEEP_DAYS"),
+        }
+    }
+}
+
+pub fn create_design(conn: &mut PgConnection, author: Uuid) -> Result<Design> {
     use crate::schema::designs::dsl::*;
 
-    diesel::insert_into(designs)
-        .default_values()
-        .returning(Design::as_returning())
-        .get_result::<Design>(conn)
-        .map_err(AppError::from)
+    conn.transaction(|conn| {
+        let design = diesel::insert_into(designs)
+            .default_values()
+            .returning(Design::as_returning())
+            .get_result::<Design>(conn)
+            .map_err(AppError::from)?;
+
+        insert_revision(conn, &design, Some(author), None, None)?;
+
+        Ok(design)
+    })
 }
 
 pub fn get_design(conn: &mut PgConnection, design_id: Uuid) -> Result<Design> {
@@ -37,17 +118,323 @@ pub fn get_design(conn: &mut PgConnection, design_id: Uuid) -> Result<Design> {
         .map_err(AppError::from)
 }
 
+/// Partial update of a design document.
+pub enum DesignPatch {
+    /// RFC 6902 JSON Patch.
+    Json(Patch),
+    /// RFC 7396 JSON Merge Patch.
+    Merge(serde_json::Value),
+}
+
+impl DesignPatch {
+    pub fn apply(&self, doc: &mut serde_json::Value) -> Result<()> {
+        match self {
+            DesignPatch::Json(patch) => json_patch::patch(doc, patch).map_err(AppError::from),
+            DesignPatch::Merge(patch) => {
+                json_patch::merge(doc, patch);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Replaces the design data and appends a new revision of the design.
+///
+/// When `expected_revisions` are provided, the design is updated only if
+/// its current revision is one of them. The change is logged as the JSON Patch
+/// between the designs, so operations made on older revisions could be merged with it.
+/// The revision has no author when it comes from a push to the repository.
 pub fn update_design(
     conn: &mut PgConnection,
     design_id: Uuid,
     design_data: serde_json::Value,
+    author: Option<Uuid>,
+    expected_revisions: Option<&[i32]>,
+    retention: RevisionRetention,
 ) -> Result<Design> {
+    conn.transaction(|conn| {
+        let design = lock_design(conn, design_id, expected_revisions)?;
+        let patch = json_patch::diff(&design.data, &design_data);
+
+        save_revision(conn, design_id, design_data, author, None, &patch, retention)
+    })
+}
+
+pub fn get_design_revisions(conn: &mut PgConnection, design: Uuid) -> Result<Vec<DesignRevisionInfo>> {
+    use crate::schema::design_revisions::dsl::*;
+
+    design_revisions
+        .filter(design_id.eq(design))
+        .order(revision.desc())
+        .select(DesignRevisionInfo::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_design_revision(conn: &mut PgConnection, design: Uuid, number: i32) -> Result<DesignRevision> {
+    use crate::schema::design_revisions::dsl::*;
+
+    design_revisions
+        .find((design, number))
+        .select(DesignRevision::as_select())
+        .first(conn)
+        .map_err(AppError::from)
+}
+
+/// Makes the data of an old revision the new head of the design.
+///
+/// Revisions saved before the design model validation was introduced
+/// could be invalid, such revisions are not restored.
+pub fn restore_design_revision(
+    conn: &mut PgConnection,
+    design: Uuid,
+    number: i32,
+    author: Uuid,
+    expected_revisions: Option<&[i32]>,
+    retention: RevisionRetention,
+) -> Result<Design> {
+    conn.transaction(|conn| {
+        let current = lock_design(conn, design, expected_revisions)?;
+        let old = get_design_revision(conn, design, number)?;
+        validation::validate_design(&old.data).map_err(AppError::DesignValidation)?;
+        let patch = json_patch::diff(&current.data, &old.data);
+
+        save_revision(conn, design, old.data, Some(author), Some(number), &patch, retention)
+    })
+}
+
+/// Applies the patch to the current design data and appends a new revision of the design.
+///
+/// Nothing is saved if any operation of the patch fails
+/// or the patched design doesn't match the design model.
+pub fn patch_design(
+    conn: &mut PgConnection,
+    design_id: Uuid,
+    patch: &DesignPatch,
+    author: Uuid,
+    expected_revisions: Option<&[i32]>,
+    retention: RevisionRetention,
+) -> Result<Design> {
+    conn.transaction(|conn| {
+        let mut design = lock_design(conn, design_id, expected_revisions)?;
+        let previous = design.data.clone();
+        patch.apply(&mut design.data)?;
+        validation::validate_design(&design.data).map_err(AppError::DesignValidation)?;
+
+        // Merge patches are logged as the JSON Patch of their changes.
+        let logged = match patch {
+            DesignPatch::Json(patch) => patch.clone(),
+            DesignPatch::Merge(_) => json_patch::diff(&previous, &design.data),
+        };
+
+        save_revision(conn, design_id, design.data, Some(author), None, &logged, retention)
+    })
+}
+
+/// Outcome of merging operations made on an older revision of a design.
+pub struct MergedOperations {
+    pub design: Design,
+    /// Operations applied to the design, `None` when nothing has been applied.
+    pub patch: Option<Patch>,
+    /// Indices of the patches which could not be merged.
+    pub rejected: Vec<usize>,
+}
+
+/// Merges patches made on top of the `base` revision into the current design.
+///
+/// The patches are rebased over the operations logged since the base revision
+/// and applied in order, each patch as a whole. Patches which conflict with the
+/// logged operations or can't be applied are rejected, the rest is saved as one revision.
+/// The patches after a rejected one are rebased without its changes.
+/// When the log since the base revision is not available anymore, e.g. it has been pruned,
+/// the patches couldn't be rebased and nothing is merged.
+pub fn merge_operations(
+    conn: &mut PgConnection,
+    design_id: Uuid,
+    base: i32,
+    patches: Vec<Patch>,
+    author: Uuid,
+    retention: RevisionRetention,
+) -> Result<MergedOperations> {
+    conn.transaction(|conn| {
+        let mut design = lock_design(conn, design_id, None)?;
+
+        if base > design.revision || base < 0 {
+            return Err(AppError::InvalidInput(format!(
+                "base revision {base} doesn't exist, the current revision is {}",
+                design.revision
+            )));
+        }
+
+        let Some((base_data, logged)) = operations_since(conn, design_id, base, design.revision)? else {
+            return Err(AppError::BaseRevisionPruned(design.revision));
+        };
+
+        let mut rebaser = rebase::Rebaser::new(&base_data, logged);
+        let mut applied: Vec<PatchOperation> = Vec::new();
+        let mut rejected = Vec::new();
+
+        // Each patch is rebased in the context of the patches applied and rejected before it.
+        for (index, patch) in patches.into_iter().enumerate() {
+            match rebaser.rebase(&patch) {
+                Some(rebased) if json_patch::patch(&mut design.data, &rebased.patch).is_ok() => {
+                    applied.extend(rebased.patch.0.iter().cloned());
+                    rebaser.apply(rebased);
+                }
+                _ => {
+                    rejected.push(index);
+                    rebaser.reject(&patch);
+                }
+            }
+        }
+
+        // Patches are applied atomically, so the design data is unchanged.
+        if applied.is_empty() {
+            return Ok(MergedOperations {
+                design,
+                patch: None,
+                rejected,
+            });
+        }
+
+        validation::validate_design(&design.data).map_err(AppError::DesignValidation)?;
+
+        let patch = Patch(applied);
+        let design = save_revision(conn, design_id, design.data, Some(author), None, &patch, retention)?;
+
+        Ok(MergedOperations {
+            design,
+            patch: Some(patch),
+            rejected,
+        })
+    })
+}
+
+/// JSON Patch replacing the whole design.
+pub fn replace_patch(design_data: &serde_json::Value) -> Patch {
+    Patch(vec![PatchOperation::Replace(ReplaceOperation {
+        path: String::new(),
+        value: design_data.clone(),
+    })])
+}
+
+// Data of the base revision and the operations logged after it,
+// `None` when some of them have been pruned or saved without the log.
+fn operations_since(
+    conn: &mut PgConnection,
+    design: Uuid,
+    base: i32,
+    current: i32,
+) -> Result<Option<(serde_json::Value, Vec<PatchOperation>)>> {
+    use crate::schema::design_revisions::dsl::*;
+
+    let base_data = design_revisions
+        .find((design, base))
+        .select(data)
+        .first::<serde_json::Value>(conn)
+        .optional()?;
+    let patches = design_revisions
+        .filter(design_id.eq(design))
+        .filter(revision.gt(base))
+        .order(revision.asc())
+        .select(patch)
+        .load::<Option<serde_json::Value>>(conn)?;
+
+    let (Some(base_data), true) = (base_data, patches.len() as i32 == current - base) else {
+        return Ok(None);
+    };
+
+    let mut operations = Vec::new();
+    for logged in patches {
+        let Some(logged) = logged else {
+            return Ok(None);
+        };
+        operations.extend(serde_json::from_value::<Patch>(logged)?.0);
+    }
+
+    Ok(Some((base_data, operations)))
+}
+
+// Locks the design row until the end of the transaction and checks its revision.
+fn lock_design(conn: &mut PgConnection, design_id: Uuid, expected_revisions: Option<&[i32]>) -> Result<Design> {
     use crate::schema::designs::dsl::*;
 
-    diesel::update(designs)
+    let design = designs
+        .find(design_id)
+        .select(Design::as_select())
+        .for_update()
+        .first(conn)
+        .map_err(AppError::from)?;
+
+    match expected_revisions {
+        Some(expected) if !expected.contains(&design.revision) => {
+            Err(AppError::PreconditionFailed(design.revision))
+        }
+        _ => Ok(design),
+    }
+}
+
+fn save_revision(
+    conn: &mut PgConnection,
+    design_id: Uuid,
+    design_data: serde_json::Value,
+    author: Option<Uuid>,
+    restored_from: Option<i32>,
+    patch: &Patch,
+    retention: RevisionRetention,
+) -> Result<Design> {
+    use crate::schema::designs::dsl::*;
+
+    let design = diesel::update(designs)
         .filter(id.eq(design_id))
-        .set(data.eq(design_data))
+        .set((data.eq(design_data), revision.eq(revision + 1)))
         .returning(Design::as_returning())
         .get_result(conn)
+        .map_err(AppError::from)?;
+
+    insert_revision(conn, &design, author, restored_from, Some(patch))?;
+    prune_revisions(conn, &design, retention)?;
+    crate::models::projects::record_design_change(conn, design.id)?;
+
+    Ok(design)
+}
+
+fn insert_revision(
+    conn: &mut PgConnection,
+    design: &Design,
+    author: Option<Uuid>,
+    restored_from: Option<i32>,
+    patch: Option<&Patch>,
+) -> Result<usize> {
+    let patch = patch.map(serde_json::to_value).transpose()?;
+
+    diesel::insert_into(design_revisions::table)
+        .values(NewDesignRevision {
+            design_id: design.id,
+            revision: design.revision,
+            data: &design.data,
+            author_id: author,
+            restored_from,
+            patch,
+        })
+        .execute(conn)
         .map_err(AppError::from)
 }
+
+fn prune_revisions(conn: &mut PgConnection, design: &Design, retention: RevisionRetention) -> Result<()> {
+    use crate::schema::design_revisions::dsl::*;
+
+    let design_filter = design_id.eq(design.id).and(revision.lt(design.revision));
+
+    if let Some(keep) = retention.keep {
+        diesel::delete(design_revisions.filter(design_filter.and(revision.le(design.revision - keep))))
+            .execute(conn)?;
+    }
+
+    if let Some(days) = retention.keep_days {
+        diesel::delete(design_revisions.filter(design_filter.and(created_at.lt(diesel::dsl::now - days.days()))))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,188 @@
+use crate::errors::AppError;
+use crate::models::{projects::{self, NewUserProject, Project, ProjectRole}, Result};
+use crate::schema::*;
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Serialize, ToSchema, Debug, PartialEq)]
+#[diesel(belongs_to(Project))]
+#[diesel(table_name = project_invitations)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectInvitation {
+    pub id: Uuid,
+    pub project_id: Uuid,
+    #[serde(skip)]
+    pub token_hash: String,
+    pub role: ProjectRole,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub created_by: Uuid,
+    pub expires_at: NaiveDateTime,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = project_invitations)]
+pub struct NewProjectInvitation<'a> {
+    pub project_id: Uuid,
+    pub token_hash: &'a str,
+    pub role: ProjectRole,
+    pub max_uses: Option<i32>,
+    pub created_by: Uuid,
+    pub expires_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = invitation_acceptances)]
+struct NewInvitationAcceptance {
+    invitation_id: Uuid,
+    user_id: Uuid,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum InvitationStatus {
+    Pending,
+    Accepted,
+    Expired,
+    Revoked,
+}
+
+impl ProjectInvitation {
+    pub fn status(&self, now: NaiveDateTime) -> InvitationStatus {
+        if self.revoked_at.is_some() {
+            return InvitationStatus::Revoked;
+        }
+
+        if self.max_uses.is_some_and(|max| self.use_count >= max) {
+            return InvitationStatus::Accepted;
+        }
+
+        if self.expires_at <= now {
+            return InvitationStatus::Expired;
+        }
+
+        InvitationStatus::Pending
+    }
+}
+
+pub fn create_invitation(
+    conn: &mut PgConnection,
+    new_invitation: NewProjectInvitation,
+) -> Result<ProjectInvitation> {
+    use crate::schema::project_invitations::dsl::*;
+
+    diesel::insert_into(project_invitations)
+        .values(&new_invitation)
+        .returning(ProjectInvitation::as_returning())
+        .get_result(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_project_invitations(conn: &mut PgConnection, project: Uuid) -> Result<Vec<ProjectInvitation>> {
+    use crate::schema::project_invitations::dsl::*;
+
+    project_invitations
+        .filter(project_id.eq(project))
+        .order(created_at.desc())
+        .select(ProjectInvitation::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+pub fn revoke_invitation(
+    conn: &mut PgConnection,
+    project: Uuid,
+    invitation_id: Uuid,
+) -> Result<ProjectInvitation> {
+    use crate::schema::project_invitations::dsl::*;
+
+    diesel::update(project_invitations)
+        .filter(id.eq(invitation_id))
+        .filter(project_id.eq(project))
+        .filter(revoked_at.is_null())
+        .set(revoked_at.eq(diesel::dsl::now))
+        .returning(ProjectInvitation::as_returning())
+        .get_result(conn)
+        .map_err(AppError::from)
+}
+
+/// Attaches the user to the project of the invitation and records the acceptance.
+///
+/// Accepting is idempotent: members of the project get the project as is, and users
+/// who have accepted the invitation and left the project are attached again
+/// without using the invitation up once more.
+pub fn accept_invitation(conn: &mut PgConnection, hash: &str, member_id: Uuid) -> Result<Project> {
+    use crate::schema::project_invitations::dsl::*;
+
+    conn.transaction(|conn| {
+        let invitation = project_invitations
+            .filter(token_hash.eq(hash))
+            .select(ProjectInvitation::as_select())
+            .for_update()
+            .first(conn)?;
+
+        let is_member = users_projects::table
+            .find((member_id, invitation.project_id))
+            .select(users_projects::is_active)
+            .first::<bool>(conn)
+            .optional()?
+            .unwrap_or(false);
+
+        if is_member {
+            return projects::find_project(conn, projects::ProjectKey::ID(invitation.project_id));
+        }
+
+        let accepted = invitation_acceptances::table
+            .find((invitation.id, member_id))
+            .select(invitation_acceptances::user_id)
+            .first::<Uuid>(conn)
+            .optional()?
+            .is_some();
+
+        match invitation.status(chrono::Utc::now().naive_utc()) {
+            InvitationStatus::Pending => {}
+            // The use of the user has been counted already.
+            InvitationStatus::Accepted if accepted => {}
+            InvitationStatus::Accepted => {
+                return Err(AppError::InvitationError("Invitation has been used up.".to_string()))
+            }
+            InvitationStatus::Expired => {
+                return Err(AppError::InvitationError("Invitation has expired.".to_string()))
+            }
+            InvitationStatus::Revoked => {
+                return Err(AppError::InvitationError("Invitation has been revoked.".to_string()))
+            }
+        }
+
+        projects::add_project_member(
+            conn,
+            NewUserProject {
+                user_id: member_id,
+                project_id: invitation.project_id,
+                is_active: true,
+                role: invitation.role,
+            },
+        )?;
+
+        if !accepted {
+            diesel::insert_into(invitation_acceptances::table)
+                .values(NewInvitationAcceptance {
+                    invitation_id: invitation.id,
+                    user_id: member_id,
+                })
+                .execute(conn)?;
+
+            diesel::update(project_invitations.find(invitation.id))
+                .set(use_count.eq(use_count + 1))
+                .execute(conn)?;
+        }
+
+        projects::find_project(conn, projects::ProjectKey::ID(invitation.project_id))
+    })
+}
@@ -1,12 +1,18 @@
 use crate::errors::AppError;
-use crate::models::{Result, users::User, repositories::Repository, designs::*};
+use crate::models::{Result, users::User, repositories::{Provider, Repository}, designs::*};
 use crate::schema::*;
 use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Varchar;
+use std::io::Write;
 use uuid::Uuid;
 use utoipa::ToSchema;
 
-#[derive(Queryable, Selectable, Identifiable, Serialize, Associations, ToSchema, Debug, PartialEq)]
+#[derive(Queryable, Selectable, Identifiable, Serialize, Associations, ToSchema, Clone, Debug, PartialEq)]
 #[diesel(belongs_to(Repository, foreign_key = repo_id))]
 #[diesel(belongs_to(Design))]
 #[diesel(table_name = projects)]
@@ -19,6 +25,15 @@ pub struct Project {
     pub design_id: Uuid,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Sync state of the design with the repository, `null` until the design is saved to or pulled from it.
+    pub sync_state: Option<SyncState>,
+    /// Design revision of the last successful save to or pull from the repository.
+    pub pushed_revision: Option<i32>,
+    /// Commit of the last successful save to or pull from the repository,
+    /// `null` when the pulled commit isn't known.
+    pub pushed_commit_sha: Option<String>,
+    /// Error of the last failed save to the repository.
+    pub sync_error: Option<String>,
 }
 
 #[derive(Insertable, AsChangeset)]
@@ -36,12 +51,112 @@ pub struct UpdateProject<'a> {
     pub repo_id: Option<Uuid>,
 }
 
-pub enum ProjectKey<'a> {
+pub enum ProjectKey {
     ID(Uuid),
-    Name(&'a str),
 }
 
-#[derive(Identifiable, Selectable, Queryable, Associations, Debug)]
+/// Role of a member in a project. Roles are ordered by the access they grant.
+#[derive(
+    AsExpression, FromSqlRow, Serialize, Deserialize, ToSchema,
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "lowercase")]
+pub enum ProjectRole {
+    Viewer,
+    Editor,
+    Owner,
+}
+
+impl ProjectRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProjectRole::Viewer => "viewer",
+            ProjectRole::Editor => "editor",
+            ProjectRole::Owner => "owner",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for ProjectRole {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for ProjectRole {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"viewer" => Ok(ProjectRole::Viewer),
+            b"editor" => Ok(ProjectRole::Editor),
+            b"owner" => Ok(ProjectRole::Owner),
+            _ => Err("Unrecognized project role".into()),
+        }
+    }
+}
+
+/// Sync state of the design of a project with the design file of its repository.
+#[derive(AsExpression, FromSqlRow, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "camelCase")]
+pub enum SyncState {
+    /// The design is the design file.
+    InSync,
+    /// The design has changed since the last save or pull.
+    LocalAhead,
+    /// The design file has changed since the last save or pull.
+    RemoteAhead,
+    /// Both the design and the design file have changed.
+    Diverged,
+    /// The last save has failed, see `syncError`.
+    PushFailed,
+}
+
+impl SyncState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SyncState::InSync => "in_sync",
+            SyncState::LocalAhead => "local_ahead",
+            SyncState::RemoteAhead => "remote_ahead",
+            SyncState::Diverged => "diverged",
+            SyncState::PushFailed => "push_failed",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for SyncState {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for SyncState {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"in_sync" => Ok(SyncState::InSync),
+            b"local_ahead" => Ok(SyncState::LocalAhead),
+            b"remote_ahead" => Ok(SyncState::RemoteAhead),
+            b"diverged" => Ok(SyncState::Diverged),
+            b"push_failed" => Ok(SyncState::PushFailed),
+            _ => Err("Unrecognized sync state".into()),
+        }
+    }
+}
+
+// Sync state columns, the ones which are `None` are cleared.
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = projects)]
+#[diesel(treat_none_as_null = true)]
+struct ProjectSync<'a> {
+    sync_state: Option<SyncState>,
+    pushed_revision: Option<i32>,
+    pushed_commit_sha: Option<&'a str>,
+    sync_error: Option<&'a str>,
+}
+
+#[derive(Identifiable, Selectable, Queryable, Associations, Clone, Debug)]
 #[diesel(belongs_to(User))]
 #[diesel(belongs_to(Project))]
 #[diesel(table_name = users_projects)]
@@ -50,6 +165,7 @@ pub struct UserProject {
     pub user_id: Uuid,
     pub project_id: Uuid,
     pub is_active: bool,
+    pub role: ProjectRole,
     pub created_at: NaiveDateTime,
 }
 
@@ -58,13 +174,23 @@ pub struct UserProject {
 pub struct NewUserProject {
     pub user_id: Uuid,
     pub project_id: Uuid,
+    pub is_active: bool,
+    pub role: ProjectRole,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ProjectMember {
+    pub user: User,
+    pub role: ProjectRole,
+    pub created_at: NaiveDateTime,
 }
 
 pub fn create_project(conn: &mut PgConnection, project_name: &str, repository_id: Option<Uuid>, user_id: Uuid) -> Result<Project> {
     use crate::schema::projects::dsl::*;
 
     conn.transaction(|conn| {
-        let design = crate::models::designs::create_design(conn)?;
+        let design = crate::models::designs::create_design(conn, user_id)?;
         let insert_project = NewProject{
             name: project_name,
             repo_id: repository_id,
@@ -77,7 +203,12 @@ pub fn create_project(conn: &mut PgConnection, project_name: &str, repository_id
             .get_result::<Project>(conn)
             .map_err(AppError::from)?;
 
-        register_user_project(conn, NewUserProject { user_id, project_id: project.id })?;
+        register_user_project(conn, NewUserProject {
+            user_id,
+            project_id: project.id,
+            is_active: true,
+            role: ProjectRole::Owner,
+        })?;
 
         Ok(project)
     })
@@ -87,11 +218,6 @@ pub fn find_project(conn: &mut PgConnection, key: ProjectKey) -> Result<Project>
     use crate::schema::projects::dsl::*;
 
     match key {
-        ProjectKey::Name(n) => projects
-            .filter(name.eq(n))
-            .select(Project::as_select())
-            .first(conn)
-            .map_err(AppError::from),
         ProjectKey::ID(uuid) => projects
             .find(uuid)
             .select(Project::as_select())
@@ -100,17 +226,18 @@ pub fn find_project(conn: &mut PgConnection, key: ProjectKey) -> Result<Project>
     }
 }
 
-pub fn get_user_projects(conn: &mut PgConnection, user_token: &str) -> Result<Vec<Project>> {
+pub fn get_user_projects(conn: &mut PgConnection, user_id: Uuid) -> Result<Vec<Project>> {
     use crate::schema::projects::dsl::*;
-    use crate::schema::users::dsl::*;
+    use crate::schema::users::dsl::users;
 
     conn.transaction(|conn| {
         let user = users
-            .filter(access_token.eq(user_token))
+            .find(user_id)
             .select(User::as_select())  
             .first::<User>(conn)?;
 
         UserProject::belonging_to(&user)
+            .filter(users_projects::is_active.eq(true))
             .inner_join(projects)
             .select(Project::as_select())
             .load(conn)
@@ -118,17 +245,193 @@ pub fn get_user_projects(conn: &mut PgConnection, user_token: &str) -> Result<Ve
     })
 }
 
+pub fn get_repo_projects(conn: &mut PgConnection, repository_id: Uuid) -> Result<Vec<Project>> {
+    use crate::schema::projects::dsl::*;
+
+    projects
+        .filter(repo_id.eq(repository_id))
+        .select(Project::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_user_memberships(conn: &mut PgConnection, member_id: Uuid) -> Result<Vec<(UserProject, Project)>> {
+    users_projects::table
+        .inner_join(projects::table)
+        .filter(users_projects::user_id.eq(member_id))
+        .filter(users_projects::is_active.eq(true))
+        .select((UserProject::as_select(), Project::as_select()))
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+/// A project of the repository with its member who can edit it and has signed in with the provider,
+/// and the access token of the member.
+pub fn find_repo_editor(
+    conn: &mut PgConnection,
+    repository_id: Uuid,
+    repo_provider: Provider,
+) -> Result<Option<(Project, User, String)>> {
+    users_projects::table
+        .inner_join(projects::table)
+        .inner_join(users::table.inner_join(user_identities::table))
+        .filter(projects::repo_id.eq(repository_id))
+        .filter(users_projects::is_active.eq(true))
+        .filter(users_projects::role.ne(ProjectRole::Viewer))
+        .filter(user_identities::provider.eq(repo_provider))
+        .filter(user_identities::access_token.is_not_null())
+        .order(users_projects::created_at.asc())
+        .select((
+            Project::as_select(),
+            User::as_select(),
+            user_identities::access_token.assume_not_null(),
+        ))
+        .first(conn)
+        .optional()
+        .map_err(AppError::from)
+}
+
+/// The sync state is cleared when the project is linked to another repository.
 pub fn update_project(conn: &mut PgConnection, project_id: Uuid, new_project: UpdateProject) -> Result<Project> {
     use crate::schema::projects::dsl::*;
 
+    conn.transaction(|conn| {
+        diesel::update(projects)
+            .filter(id.eq(project_id))
+            .filter(repo_id.is_distinct_from(new_project.repo_id))
+            .set(&ProjectSync::default())
+            .execute(conn)?;
+
+        diesel::update(projects)
+            .filter(id.eq(project_id))
+            .set(&new_project)
+            .returning(Project::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
+    })
+}
+
+/// Unlinks the projects from the repository and clears their sync state.
+pub fn unlink_repo(conn: &mut PgConnection, repository_id: Uuid) -> Result<usize> {
+    use crate::schema::projects::dsl::*;
+
     diesel::update(projects)
-        .filter(id.eq(project_id))
-        .set(&new_project)
-        .returning(Project::as_returning())
-        .get_result(conn)
+        .filter(repo_id.eq(repository_id))
+        .set((repo_id.eq(None::<Uuid>), &ProjectSync::default()))
+        .execute(conn)
         .map_err(AppError::from)
 }
 
+/// Projects which have been in sync with their repository get ahead of it when their design changes.
+pub fn record_design_change(conn: &mut PgConnection, design: Uuid) -> Result<()> {
+    use crate::schema::projects::dsl::*;
+
+    for (from, to) in [
+        (SyncState::InSync, SyncState::LocalAhead),
+        (SyncState::RemoteAhead, SyncState::Diverged),
+    ] {
+        diesel::update(projects)
+            .filter(design_id.eq(design))
+            .filter(sync_state.eq(from))
+            .set(sync_state.eq(to))
+            .execute(conn)?;
+    }
+
+    Ok(())
+}
+
+/// Updates the sync state of the projects of the repository after its design file
+/// has been saved or pulled. Projects with the design of the file are in sync,
+/// the others are behind the file when it has moved, and ahead of it otherwise.
+///
+/// The projects with the design of the file record their revision along with
+/// `commit_sha`, the commit of the file. When the commit isn't known, as for the files
+/// pulled from the branch, the recorded commit is kept unless the file has moved.
+pub fn record_repo_sync(
+    conn: &mut PgConnection,
+    repository_id: Uuid,
+    file_design: &serde_json::Value,
+    file_moved: bool,
+    commit_sha: Option<&str>,
+) -> Result<()> {
+    conn.transaction(|conn| {
+        for (project, design) in get_repo_designs(conn, repository_id)? {
+            let project_sync = match design.data == *file_design {
+                true => ProjectSync {
+                    sync_state: Some(SyncState::InSync),
+                    pushed_revision: Some(design.revision),
+                    pushed_commit_sha: commit_sha.or(project.pushed_commit_sha.as_deref().filter(|_| !file_moved)),
+                    sync_error: None,
+                },
+                false => ProjectSync {
+                    sync_state: Some(unsynced_state(project.sync_state, file_moved)),
+                    ..current_sync(&project)
+                },
+            };
+
+            diesel::update(projects::table.find(project.id))
+                .set(&project_sync)
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+/// Records the failed save of the design for the projects of the repository with the design.
+pub fn record_push_failure(
+    conn: &mut PgConnection,
+    repository_id: Uuid,
+    pushed_design: &serde_json::Value,
+    error: &str,
+) -> Result<()> {
+    conn.transaction(|conn| {
+        for (project, design) in get_repo_designs(conn, repository_id)? {
+            if design.data != *pushed_design {
+                continue;
+            }
+
+            diesel::update(projects::table.find(project.id))
+                .set((
+                    projects::sync_state.eq(SyncState::PushFailed),
+                    projects::sync_error.eq(error),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(())
+    })
+}
+
+fn get_repo_designs(conn: &mut PgConnection, repository_id: Uuid) -> Result<Vec<(Project, Design)>> {
+    projects::table
+        .inner_join(designs::table)
+        .filter(projects::repo_id.eq(repository_id))
+        .select((Project::as_select(), Design::as_select()))
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+fn current_sync(project: &Project) -> ProjectSync<'_> {
+    ProjectSync {
+        sync_state: project.sync_state,
+        pushed_revision: project.pushed_revision,
+        pushed_commit_sha: project.pushed_commit_sha.as_deref(),
+        sync_error: project.sync_error.as_deref(),
+    }
+}
+
+// State of a project whose design is not the design file. Changes of the design
+// since the last sync have made the project ahead of the file, unless it is unknown.
+fn unsynced_state(state: Option<SyncState>, file_moved: bool) -> SyncState {
+    match (state, file_moved) {
+        (Some(SyncState::InSync | SyncState::RemoteAhead), true) => SyncState::RemoteAhead,
+        (_, true) => SyncState::Diverged,
+        (None | Some(SyncState::InSync), false) => SyncState::LocalAhead,
+        (Some(state), false) => state,
+    }
+}
+
 fn register_user_project(conn: &mut PgConnection, user_project: NewUserProject) -> Result<usize> {
     use crate::schema::users_projects::dsl::*;
 
@@ -137,3 +440,115 @@ fn register_user_project(conn: &mut PgConnection, user_project: NewUserProject)
         .execute(conn)
         .map_err(AppError::from)
 }
+
+
+pub fn get_project_members(conn: &mut PgConnection, project: Uuid) -> Result<Vec<ProjectMember>> {
+    users_projects::table
+        .inner_join(users::table)
+        .filter(users_projects::project_id.eq(project))
+        .filter(users_projects::is_active.eq(true))
+        .order(users_projects::created_at.asc())
+        .select((User::as_select(), UserProject::as_select()))
+        .load::<(User, UserProject)>(conn)
+        .map(|rows| {
+            rows.into_iter()
+                .map(|(user, membership)| ProjectMember {
+                    user,
+                    role: membership.role,
+                    created_at: membership.created_at,
+                })
+                .collect()
+        })
+        .map_err(AppError::from)
+}
+
+/// The user must exist, former members are activated again with the new role.
+pub fn add_project_member(conn: &mut PgConnection, member: NewUserProject) -> Result<UserProject> {
+    conn.transaction(|conn| {
+        users::table
+            .find(member.user_id)
+            .select(users::id)
+            .first::<Uuid>(conn)?;
+
+        if find_membership(conn, member.project_id, member.user_id)?.is_some_and(|m| m.is_active) {
+            return Err(AppError::RecordAlreadyExists);
+        }
+
+        diesel::insert_into(users_projects::table)
+            .values(&member)
+            .on_conflict((users_projects::user_id, users_projects::project_id))
+            .do_update()
+            .set(&member)
+            .returning(UserProject::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
+    })
+}
+
+pub fn update_member_role(
+    conn: &mut PgConnection,
+    project: Uuid,
+    member: Uuid,
+    new_role: ProjectRole,
+) -> Result<UserProject> {
+    conn.transaction(|conn| {
+        let membership = find_membership(conn, project, member)?
+            .filter(|m| m.is_active)
+            .ok_or(AppError::RecordNotFound)?;
+
+        if membership.role == ProjectRole::Owner && new_role != ProjectRole::Owner {
+            ensure_other_owner(conn, project, member)?;
+        }
+
+        diesel::update(users_projects::table.find((member, project)))
+            .set(users_projects::role.eq(new_role))
+            .returning(UserProject::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
+    })
+}
+
+pub fn remove_project_member(conn: &mut PgConnection, project: Uuid, member: Uuid) -> Result<()> {
+    conn.transaction(|conn| {
+        let membership = find_membership(conn, project, member)?
+            .filter(|m| m.is_active)
+            .ok_or(AppError::RecordNotFound)?;
+
+        if membership.role == ProjectRole::Owner {
+            ensure_other_owner(conn, project, member)?;
+        }
+
+        diesel::update(users_projects::table.find((member, project)))
+            .set(users_projects::is_active.eq(false))
+            .execute(conn)
+            .map(|_| ())
+            .map_err(AppError::from)
+    })
+}
+
+fn find_membership(conn: &mut PgConnection, project: Uuid, member: Uuid) -> Result<Option<UserProject>> {
+    users_projects::table
+        .find((member, project))
+        .select(UserProject::as_select())
+        .for_update()
+        .first(conn)
+        .optional()
+        .map_err(AppError::from)
+}
+
+// Owners rows are locked, so concurrent requests can't remove all owners at once.
+fn ensure_other_owner(conn: &mut PgConnection, project: Uuid, member: Uuid) -> Result<()> {
+    let owners: Vec<Uuid> = users_projects::table
+        .filter(users_projects::project_id.eq(project))
+        .filter(users_projects::is_active.eq(true))
+        .filter(users_projects::role.eq(ProjectRole::Owner))
+        .select(users_projects::user_id)
+        .for_update()
+        .load(conn)?;
+
+    if owners.iter().any(|owner| *owner != member) {
+        return Ok(());
+    }
+
+    Err(AppError::LastOwnerError)
+}
@@ -0,0 +1,151 @@
+use crate::errors::AppError;
+use crate::models::{repositories::Repository, Result};
+use crate::schema::*;
+use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Varchar;
+use std::io::Write;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// Pull request opened on GitHub for design changes saved to a branch.
+#[derive(Queryable, Selectable, Identifiable, Associations, Serialize, ToSchema, Debug, PartialEq)]
+#[diesel(belongs_to(Repository))]
+#[diesel(table_name = design_pull_requests)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct DesignPullRequest {
+    pub id: Uuid,
+    pub repository_id: Uuid,
+    pub branch: String,
+    pub number: i32,
+    pub html_url: String,
+    pub state: PullRequestState,
+    /// Blob SHA of the design file on the branch.
+    pub design_file_sha: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = design_pull_requests)]
+pub struct NewDesignPullRequest<'a> {
+    pub repository_id: Uuid,
+    pub branch: &'a str,
+    pub number: i32,
+    pub html_url: &'a str,
+    pub state: PullRequestState,
+    pub design_file_sha: Option<&'a str>,
+}
+
+#[derive(AsExpression, FromSqlRow, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "lowercase")]
+pub enum PullRequestState {
+    Open,
+    Closed,
+    Merged,
+}
+
+impl PullRequestState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PullRequestState::Open => "open",
+            PullRequestState::Closed => "closed",
+            PullRequestState::Merged => "merged",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for PullRequestState {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for PullRequestState {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"open" => Ok(PullRequestState::Open),
+            b"closed" => Ok(PullRequestState::Closed),
+            b"merged" => Ok(PullRequestState::Merged),
+            _ => Err("Unrecognized pull request state".into()),
+        }
+    }
+}
+
+/// Records the pull request, or updates the record of the same pull request.
+pub fn save_pull_request(
+    conn: &mut PgConnection,
+    new_pull_request: NewDesignPullRequest,
+) -> Result<DesignPullRequest> {
+    use crate::schema::design_pull_requests::dsl::*;
+
+    diesel::insert_into(design_pull_requests)
+        .values(&new_pull_request)
+        .on_conflict((repository_id, number))
+        .do_update()
+        .set((
+            branch.eq(new_pull_request.branch),
+            html_url.eq(new_pull_request.html_url),
+            state.eq(new_pull_request.state),
+            design_file_sha.eq(new_pull_request.design_file_sha),
+        ))
+        .returning(DesignPullRequest::as_returning())
+        .get_result(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_open_pull_requests(conn: &mut PgConnection, repo_id: Uuid) -> Result<Vec<DesignPullRequest>> {
+    use crate::schema::design_pull_requests::dsl::*;
+
+    design_pull_requests
+        .filter(repository_id.eq(repo_id))
+        .filter(state.eq(PullRequestState::Open))
+        .order(created_at.desc())
+        .select(DesignPullRequest::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+/// Last tracked pull request from the branch, whatever its state.
+pub fn find_branch_pull_request(
+    conn: &mut PgConnection,
+    repo_id: Uuid,
+    branch_name: &str,
+) -> Result<Option<DesignPullRequest>> {
+    use crate::schema::design_pull_requests::dsl::*;
+
+    design_pull_requests
+        .filter(repository_id.eq(repo_id))
+        .filter(branch.eq(branch_name))
+        .order(updated_at.desc())
+        .select(DesignPullRequest::as_select())
+        .first(conn)
+        .optional()
+        .map_err(AppError::from)
+}
+
+/// Updates the state of a tracked pull request, returns `None` if it is not tracked.
+pub fn update_pull_request_state(
+    conn: &mut PgConnection,
+    repo_id: Uuid,
+    pr_number: i32,
+    new_state: PullRequestState,
+) -> Result<Option<DesignPullRequest>> {
+    use crate::schema::design_pull_requests::dsl::*;
+
+    diesel::update(design_pull_requests)
+        .filter(repository_id.eq(repo_id))
+        .filter(number.eq(pr_number))
+        .set(state.eq(new_state))
+        .returning(DesignPullRequest::as_returning())
+        .get_result(conn)
+        .optional()
+        .map_err(AppError::from)
+}
@@ -2,8 +2,14 @@ use crate::errors::AppError;
 use crate::models::Result;
 use crate::schema::*;
 use chrono::NaiveDateTime;
-use diesel::dsl::exists;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::dsl::{exists, not};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Varchar;
+use std::io::Write;
 use utoipa::ToSchema;
 use uuid::Uuid;
 
@@ -20,9 +26,145 @@ pub struct Repository {
     pub html_url: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Path of the design file, or of the design directory for the split layout.
+    /// `design_file_sha` is the SHA of the file, or of the tree of the directory, at this path.
+    pub design_path: String,
+    /// Branch of the design file, the default branch of the repository when `null`.
+    pub design_branch: Option<String>,
+    /// Whether the design file is pretty-printed.
+    pub design_pretty: bool,
+    /// Whether the design file ends with a newline.
+    pub design_trailing_newline: bool,
+    pub design_layout: DesignLayout,
+    pub provider: Provider,
+    /// User who has created or imported the repository.
+    pub created_by: Option<Uuid>,
 }
 
-#[derive(Deserialize, Debug)]
+/// Hosting service of a repository.
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    GitHub,
+    GitLab,
+    /// Bare git repository on the disk of the server, per repository record.
+    /// Its git history isn't served, the design revisions are the history of the designs.
+    Local,
+}
+
+impl Provider {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Provider::GitHub => "github",
+            Provider::GitLab => "gitlab",
+            Provider::Local => "local",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for Provider {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for Provider {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"github" => Ok(Provider::GitHub),
+            b"gitlab" => Ok(Provider::GitLab),
+            b"local" => Ok(Provider::Local),
+            _ => Err("Unrecognized provider".into()),
+        }
+    }
+}
+
+/// How the design is stored in the repository.
+#[derive(AsExpression, FromSqlRow, Serialize, Deserialize, ToSchema, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "lowercase")]
+pub enum DesignLayout {
+    /// The design is a single JSON file at the design path.
+    #[default]
+    File,
+    /// The design is a directory at the design path with `index.json`
+    /// and a file per entity of the design.
+    Split,
+}
+
+impl DesignLayout {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DesignLayout::File => "file",
+            DesignLayout::Split => "split",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for DesignLayout {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for DesignLayout {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"file" => Ok(DesignLayout::File),
+            b"split" => Ok(DesignLayout::Split),
+            _ => Err("Unrecognized design layout".into()),
+        }
+    }
+}
+
+impl Repository {
+    pub fn repo_owner(&self) -> RepositoryOwner {
+        RepositoryOwner {
+            name: self.name.clone(),
+            owner: self.owner.clone(),
+            is_organization: self.is_organization,
+        }
+    }
+
+    /// Whether the file of the repository is the design file or one of the design files.
+    pub fn is_design_path(&self, path: &str) -> bool {
+        match self.design_layout {
+            DesignLayout::File => path == self.design_path,
+            DesignLayout::Split => path
+                .strip_prefix(self.design_path.as_str())
+                .is_some_and(|rest| rest.starts_with('/')),
+        }
+    }
+
+    pub fn design_settings(&self) -> DesignSettings {
+        DesignSettings {
+            path: self.design_path.clone(),
+            branch: self.design_branch.clone(),
+            pretty: self.design_pretty,
+            trailing_newline: self.design_trailing_newline,
+            layout: self.design_layout,
+        }
+    }
+
+    /// Content of the design file with the design, formatted as set for the repository.
+    pub fn encode_design(&self, design: &serde_json::Value) -> Result<Vec<u8>> {
+        let mut content = match self.design_pretty {
+            true => serde_json::to_vec_pretty(design)?,
+            false => serde_json::to_vec(design)?,
+        };
+        if self.design_trailing_newline {
+            content.push(b'\n');
+        }
+
+        Ok(content)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
 pub struct RepositoryOwner {
     pub name: String,
     pub owner: String,
@@ -37,6 +179,8 @@ pub struct NewRepository<'a> {
     pub is_organization: bool,
     pub design_file_sha: Option<&'a str>,
     pub html_url: &'a str,
+    pub provider: Provider,
+    pub created_by: Option<Uuid>,
 }
 
 
@@ -51,9 +195,30 @@ pub struct UpdateRepository<'a> {
 }
 
 
+#[derive(Deserialize, AsChangeset, ToSchema, Debug)]
+#[diesel(table_name = repositories, treat_none_as_null = true)]
+#[serde(rename_all = "camelCase")]
+pub struct DesignSettings {
+    /// Path of the design file in the repository, e.g. `docs/design/design.json`,
+    /// or of the design directory for the split layout.
+    #[diesel(column_name = design_path)]
+    pub path: String,
+    /// Branch of the design file, a valid git branch name. The default branch of the repository is used when omitted.
+    #[diesel(column_name = design_branch)]
+    pub branch: Option<String>,
+    #[diesel(column_name = design_pretty)]
+    pub pretty: bool,
+    #[diesel(column_name = design_trailing_newline)]
+    pub trailing_newline: bool,
+    /// Files of the split layout are always pretty-printed with sorted keys and a trailing newline.
+    #[serde(default)]
+    #[diesel(column_name = design_layout)]
+    pub layout: DesignLayout,
+}
+
 pub enum RepositoryKey {
     ID(Uuid),
-    Owner(RepositoryOwner),
+    Owner(Provider, RepositoryOwner),
 }
 
 pub fn create_repo(conn: &mut PgConnection, new_repo: NewRepository) -> Result<Repository> {
@@ -66,14 +231,19 @@ pub fn create_repo(conn: &mut PgConnection, new_repo: NewRepository) -> Result<R
         .map_err(AppError::from)
 }
 
-pub fn is_repo_exist(conn: &mut PgConnection, repo_owner: RepositoryOwner) -> Result<bool> {
+pub fn is_repo_exist(
+    conn: &mut PgConnection,
+    repo_provider: Provider,
+    repo_owner: RepositoryOwner,
+) -> Result<bool> {
     use crate::schema::repositories::dsl::*;
 
     diesel::select(exists(
         repositories.filter(
             name.eq(repo_owner.name)
                 .and(owner.eq(repo_owner.owner))
-                .and(is_organization.eq(repo_owner.is_organization)),
+                .and(is_organization.eq(repo_owner.is_organization))
+                .and(provider.eq(repo_provider)),
         ),
     ))
     .get_result::<bool>(conn)
@@ -84,12 +254,13 @@ pub fn find_repo(conn: &mut PgConnection, key: RepositoryKey) -> Result<Reposito
     use crate::schema::repositories::dsl::*;
 
     conn.transaction(|conn| match key {
-        RepositoryKey::Owner(repo_owner) => repositories
+        RepositoryKey::Owner(repo_provider, repo_owner) => repositories
             .filter(
                 owner
                     .eq(repo_owner.owner)
                     .and(name.eq(repo_owner.name))
-                    .and(is_organization.eq(repo_owner.is_organization)),
+                    .and(is_organization.eq(repo_owner.is_organization))
+                    .and(provider.eq(repo_provider)),
             )
             .select(Repository::as_select())
             .first(conn)
@@ -102,6 +273,33 @@ pub fn find_repo(conn: &mut PgConnection, key: RepositoryKey) -> Result<Reposito
     })
 }
 
+/// Repositories linked to the projects, and the ones created by the user which are not
+/// linked to any project, from the newest one.
+pub fn get_user_repos(conn: &mut PgConnection, user_id: Uuid, repo_ids: Vec<Uuid>) -> Result<Vec<Repository>> {
+    use crate::schema::repositories::dsl::*;
+
+    let unlinked = not(exists(projects::table.filter(projects::repo_id.eq(id.nullable()))));
+
+    repositories
+        .filter(id.eq_any(repo_ids).or(created_by.eq(user_id).and(unlinked)))
+        .order(created_at.desc())
+        .select(Repository::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+/// Unlinks the repository from its projects and deletes it with its pull requests.
+pub fn delete_repo(conn: &mut PgConnection, repo_id: Uuid) -> Result<Repository> {
+    conn.transaction(|conn| {
+        crate::models::projects::unlink_repo(conn, repo_id)?;
+
+        diesel::delete(repositories::table.find(repo_id))
+            .returning(Repository::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
+    })
+}
+
 pub fn update_repo(
     conn: &mut PgConnection,
     repo_id: Uuid,
@@ -116,3 +314,32 @@ pub fn update_repo(
         .get_result(conn)
         .map_err(AppError::from)
 }
+
+/// Updates the design file settings. The SHA of the design file is reset
+/// when the file is moved to another path, branch or layout, it is not tracked yet.
+/// SHAs of the previous locations are not kept.
+pub fn update_design_settings(
+    conn: &mut PgConnection,
+    repo_id: Uuid,
+    settings: DesignSettings,
+) -> Result<Repository> {
+    use crate::schema::repositories::dsl::*;
+
+    conn.transaction(|conn| {
+        let current = repositories
+            .find(repo_id)
+            .select(Repository::as_select())
+            .for_update()
+            .first(conn)?;
+        let moved = current.design_path != settings.path
+            || current.design_branch != settings.branch
+            || current.design_layout != settings.layout;
+        let sha = if moved { None } else { current.design_file_sha };
+
+        diesel::update(repositories.find(repo_id))
+            .set((&settings, design_file_sha.eq(sha)))
+            .returning(Repository::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
+    })
+}
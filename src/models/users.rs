@@ -1,5 +1,5 @@
 use crate::errors::AppError;
-use crate::models::Result;
+use crate::models::{repositories::Provider, Result};
 use crate::schema::*;
 use crate::services::encrypt::{EncryptResponse, Aes256Gcm};
 use chrono::NaiveDateTime;
@@ -11,7 +11,7 @@ use uuid::Uuid;
 #[derive(
     Queryable, Selectable, Identifiable,
     // Associations, 
-    Serialize, ToSchema, Debug, PartialEq,
+    Serialize, ToSchema, Clone, Debug, PartialEq,
 )]
 // #[diesel(belongs_to(UserRole, foreign_key = role_id))]
 #[diesel(table_name = users)]
@@ -22,9 +22,10 @@ pub struct User {
     pub name: String,
     // pub role_id: Uuid,
     pub email: String,
-    pub access_token: Option<String>,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Provider the user has signed in with last, the session is refreshed by it.
+    pub provider: Provider,
 }
 
 #[derive(Insertable, Deserialize, AsChangeset)]
@@ -33,13 +34,43 @@ pub struct NewUser {
     pub name: String,
     // pub role_id: Uuid,
     pub email: String,
+    pub provider: Option<Provider>,
+}
+
+/// Account of a provider linked to the user.
+#[derive(Queryable, Selectable, Identifiable, Associations, Debug, PartialEq)]
+#[diesel(belongs_to(User, foreign_key = user_id), primary_key(user_id, provider))]
+#[diesel(table_name = user_identities)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct UserIdentity {
+    pub user_id: Uuid,
+    pub provider: Provider,
+    pub provider_user_id: Option<String>,
+    // Access token of the provider, it never leaves the server.
     pub access_token: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+/// Account of a provider the user has signed in with.
+pub struct ProviderAccount {
+    pub provider: Provider,
+    /// Id of the account on the provider, logins and emails could change.
+    pub id: String,
+}
+
+#[derive(Insertable, AsChangeset)]
+#[diesel(table_name = user_identities)]
+struct NewUserIdentity<'a> {
+    user_id: Uuid,
+    provider: Provider,
+    provider_user_id: &'a str,
+    access_token: Option<&'a str>,
 }
 
 pub enum UserKey<'a> {
     ID(Uuid),
     Name(&'a str),
-    Token(&'a str),
 }
 
 // #[derive(Queryable, Selectable, Identifiable, Serialize, ToSchema, Debug, PartialEq)]
@@ -63,7 +94,7 @@ pub struct RoledUser {
 #[derive(
     Queryable, Selectable, Identifiable, Associations, Serialize, ToSchema, Debug, PartialEq,
 )]
-#[diesel(belongs_to(User, foreign_key = user_id), primary_key(user_id))]
+#[diesel(belongs_to(User, foreign_key = user_id), primary_key(user_id, provider))]
 #[diesel(table_name = user_refresh_tokens)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct UserRefreshToken {
@@ -75,6 +106,7 @@ pub struct UserRefreshToken {
     pub token_type: String,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    pub provider: Provider,
 }
 
 #[derive(Insertable, Deserialize, AsChangeset)]
@@ -86,10 +118,13 @@ struct NewUserRefreshToken {
     pub refresh_token_expires_in: i32,
     pub scope: String,
     pub token_type: String,
+    pub provider: Provider,
 }
 
 pub struct TokenData {
     pub access_token: String,
+    // Access tokens are refreshed when the session is, so their expiry isn't tracked.
+    #[allow(dead_code)]
     pub expires_in: i32,
     pub refresh_token: String,
     pub refresh_token_expires_in: i32,
@@ -97,7 +132,9 @@ pub struct TokenData {
     pub token_type: String,
 }
 
-pub fn create_user(conn: &mut PgConnection, new_user: NewUser) -> Result<RoledUser> {
+/// Creates a user with the account of the provider, the tokens of the account
+/// are saved when the user signs in with it.
+pub fn create_user(conn: &mut PgConnection, new_user: NewUser, account: ProviderAccount) -> Result<RoledUser> {
     use crate::schema::users::dsl::*;
 
     conn.transaction(|conn| {
@@ -107,6 +144,15 @@ pub fn create_user(conn: &mut PgConnection, new_user: NewUser) -> Result<RoledUs
             .get_result::<User>(conn)
             .map_err(AppError::from)?;
 
+        diesel::insert_into(user_identities::table)
+            .values(NewUserIdentity {
+                user_id: user.id,
+                provider: account.provider,
+                provider_user_id: &account.id,
+                access_token: None,
+            })
+            .execute(conn)?;
+
         // let role = find_role(conn, user.role_id)?;
         Ok(RoledUser { user/*, role*/ })
     })
@@ -117,11 +163,6 @@ pub fn find_user(conn: &mut PgConnection, key: UserKey) -> Result<RoledUser> {
 
     conn.transaction(|conn| {
         let user: User = match key {
-            UserKey::Token(token) => users
-                .filter(access_token.eq(token))
-                .select(User::as_select())
-                .first(conn)
-                .map_err(AppError::from),
             UserKey::Name(n) => users
                 .filter(name.eq(n))
                 .select(User::as_select())
@@ -139,29 +180,13 @@ pub fn find_user(conn: &mut PgConnection, key: UserKey) -> Result<RoledUser> {
     })
 }
 
-pub fn update_user(conn: &mut PgConnection, token: &str, new_user: NewUser) -> Result<RoledUser> {
-    use crate::schema::users::dsl::*;
-
-    conn.transaction(|conn| {
-        let user = diesel::update(users)
-            .filter(access_token.eq(token))
-            .set(&new_user)
-            .returning(User::as_returning())
-            .get_result(conn)
-            .map_err(AppError::from)?;
-
-        // let role = find_role(conn, user.role_id)?;
-        Ok(RoledUser { user/*, role*/ })
-    })
-}
-
-pub fn update_user_token(conn: &mut PgConnection, user_id: Uuid, token: &str) -> Result<RoledUser> {
+pub fn update_user(conn: &mut PgConnection, user_id: Uuid, new_user: NewUser) -> Result<RoledUser> {
     use crate::schema::users::dsl::*;
 
     conn.transaction(|conn| {
         let user = diesel::update(users)
             .filter(id.eq(user_id))
-            .set(access_token.eq(token))
+            .set(&new_user)
             .returning(User::as_returning())
             .get_result(conn)
             .map_err(AppError::from)?;
@@ -190,57 +215,131 @@ pub fn update_user_token(conn: &mut PgConnection, user_id: Uuid, token: &str) ->
 //         .map_err(AppError::from)
 // }
 
+/// Saves the tokens of the provider account and returns the user it signs in as.
+///
+/// The account signs in as the user it is linked to. With `session_user` the account
+/// is linked to the signed in user, unless it is linked to another user. Otherwise
+/// a new user is created with the account, which fails when a user has the email,
+/// so accounts are never linked to a user by their email.
 pub fn save_user_token_data(
     conn: &mut PgConnection,
-    mut user: NewUser,
+    session_user: Option<Uuid>,
+    user: NewUser,
+    account: ProviderAccount,
     token_data: TokenData,
-) -> Result<()> {
+) -> Result<User> {
     use crate::schema::user_refresh_tokens::dsl::*;
     use crate::schema::users::dsl::*;
 
     let aes_256_gcm = Aes256Gcm::new();
     let data = token_data.refresh_token.as_bytes().to_vec();
     let encrypt_response: EncryptResponse = aes_256_gcm.encrypt(data, [0u8, 0].to_vec())?;
+    let token_provider = account.provider;
+
+    conn.transaction(|conn| {
+        let linked_user = find_account_user(conn, &account, &user.email)?;
+
+        let account_user = match (linked_user, session_user) {
+            (Some(linked_user), Some(session_user)) if linked_user != session_user => {
+                return Err(AppError::InvalidInput(format!(
+                    "The {} account is linked to another user",
+                    token_provider.as_str()
+                )));
+            }
+            (Some(linked_user), _) => linked_user,
+            (None, Some(session_user)) => session_user,
+            (None, None) => diesel::insert_into(users)
+                .values(&user)
+                .returning(id)
+                .get_result::<Uuid>(conn)
+                .map_err(|e| match AppError::from(e) {
+                    AppError::RecordAlreadyExists => AppError::InvalidInput(format!(
+                        "A user with the email {} exists. Sign in as the user to link the {} account",
+                        user.email,
+                        token_provider.as_str()
+                    )),
+                    e => e,
+                })?,
+        };
 
-    user.access_token = Some(token_data.access_token.clone());
+        let identity = NewUserIdentity {
+            user_id: account_user,
+            provider: token_provider,
+            provider_user_id: &account.id,
+            access_token: Some(&token_data.access_token),
+        };
 
-    conn.transaction(|conn| {         
-        let user = diesel::insert_into(users)
-            .values(&user)
-            .on_conflict(email)
+        diesel::insert_into(user_identities::table)
+            .values(&identity)
+            .on_conflict((user_identities::user_id, user_identities::provider))
             .do_update()
-            .set(access_token.eq(token_data.access_token))
-            .returning(User::as_returning())
-            .get_result::<User>(conn)
-            .map_err(AppError::from)?;
+            .set(&identity)
+            .execute(conn)?;
 
         let new_token_data = NewUserRefreshToken {
-            user_id: user.id,
+            user_id: account_user,
             refresh_token_cypher: encrypt_response.cypher,
             cypher_nonce: encrypt_response.nonce.to_vec(),
             refresh_token_expires_in: token_data.refresh_token_expires_in,
             scope: token_data.scope,
             token_type: token_data.token_type,
+            provider: token_provider,
         };
 
         diesel::insert_into(user_refresh_tokens)
             .values(&new_token_data)
-            .on_conflict(user_id)
+            .on_conflict((user_id, crate::schema::user_refresh_tokens::provider))
             .do_update()
             .set(&new_token_data)
-            .returning(UserRefreshToken::as_returning())
-            .get_result::<UserRefreshToken>(conn)
-            .map_err(AppError::from)?;            
+            .execute(conn)?;
 
-        Ok(())
+        diesel::update(users.find(account_user))
+            .set(crate::schema::users::provider.eq(token_provider))
+            .returning(User::as_returning())
+            .get_result(conn)
+            .map_err(AppError::from)
     })
 }
 
-pub fn get_user_refresh_token(conn: &mut PgConnection, id: Uuid) -> Result<String> {
+// User the account is linked to. Accounts linked before their ids were recorded
+// are matched by the email of their user, with the same provider only.
+fn find_account_user(conn: &mut PgConnection, account: &ProviderAccount, user_email: &str) -> Result<Option<Uuid>> {
+    let linked = user_identities::table
+        .filter(user_identities::provider.eq(account.provider))
+        .filter(user_identities::provider_user_id.eq(&account.id))
+        .select(user_identities::user_id)
+        .first(conn)
+        .optional()?;
+
+    if linked.is_some() {
+        return Ok(linked);
+    }
+
+    user_identities::table
+        .inner_join(users::table)
+        .filter(user_identities::provider.eq(account.provider))
+        .filter(user_identities::provider_user_id.is_null())
+        .filter(users::email.eq(user_email))
+        .select(user_identities::user_id)
+        .first(conn)
+        .optional()
+        .map_err(AppError::from)
+}
+
+/// Access tokens of the providers the user has signed in with.
+pub fn get_user_identities(conn: &mut PgConnection, identity_user: Uuid) -> Result<Vec<UserIdentity>> {
+    user_identities::table
+        .filter(user_identities::user_id.eq(identity_user))
+        .select(UserIdentity::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_user_refresh_token(conn: &mut PgConnection, id: Uuid, token_provider: Provider) -> Result<String> {
     use crate::schema::user_refresh_tokens::dsl::*;
 
     let data = user_refresh_tokens
-        .find(id)
+        .find((id, token_provider))
         .select(UserRefreshToken::as_select())
         .first(conn)
         .map_err(AppError::from)?;
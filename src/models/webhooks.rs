@@ -0,0 +1,159 @@
+use crate::errors::AppError;
+use crate::models::{repositories::Repository, Result};
+use crate::schema::*;
+use chrono::NaiveDateTime;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::prelude::*;
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Varchar;
+use std::io::Write;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+#[derive(Queryable, Selectable, Identifiable, Associations, Serialize, ToSchema, Debug, PartialEq)]
+#[diesel(belongs_to(Repository))]
+#[diesel(table_name = webhook_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: Uuid,
+    pub delivery_id: String,
+    pub event: String,
+    #[serde(skip)]
+    pub payload: serde_json::Value,
+    pub repository_id: Option<Uuid>,
+    pub status: WebhookStatus,
+    pub error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = webhook_deliveries)]
+pub struct NewWebhookDelivery<'a> {
+    pub delivery_id: &'a str,
+    pub event: &'a str,
+    pub payload: serde_json::Value,
+}
+
+/// Outcome of processing a webhook delivery.
+#[derive(AsExpression, FromSqlRow, Serialize, ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+#[diesel(sql_type = Varchar)]
+#[serde(rename_all = "lowercase")]
+pub enum WebhookStatus {
+    Received,
+    /// The design or the design pull request has been updated, or the design is up to date.
+    Processed,
+    /// The delivery is not about the design file or a design pull request of a known repository.
+    Ignored,
+    /// The design has changed since the last sync, it is not updated.
+    Conflict,
+    Failed,
+}
+
+impl WebhookStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookStatus::Received => "received",
+            WebhookStatus::Processed => "processed",
+            WebhookStatus::Ignored => "ignored",
+            WebhookStatus::Conflict => "conflict",
+            WebhookStatus::Failed => "failed",
+        }
+    }
+}
+
+impl ToSql<Varchar, Pg> for WebhookStatus {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Varchar, Pg> for WebhookStatus {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match bytes.as_bytes() {
+            b"received" => Ok(WebhookStatus::Received),
+            b"processed" => Ok(WebhookStatus::Processed),
+            b"ignored" => Ok(WebhookStatus::Ignored),
+            b"conflict" => Ok(WebhookStatus::Conflict),
+            b"failed" => Ok(WebhookStatus::Failed),
+            _ => Err("Unrecognized webhook status".into()),
+        }
+    }
+}
+
+/// Records the delivery, returns `None` for a delivery recorded already.
+/// Failed deliveries are returned again, so redelivery retries them.
+pub fn record_delivery(
+    conn: &mut PgConnection,
+    new_delivery: NewWebhookDelivery,
+) -> Result<Option<WebhookDelivery>> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    conn.transaction(|conn| {
+        let inserted = diesel::insert_into(webhook_deliveries)
+            .values(&new_delivery)
+            .on_conflict(delivery_id)
+            .do_nothing()
+            .returning(WebhookDelivery::as_returning())
+            .get_result(conn)
+            .optional()?;
+
+        if inserted.is_some() {
+            return Ok(inserted);
+        }
+
+        let recorded = webhook_deliveries
+            .filter(delivery_id.eq(new_delivery.delivery_id))
+            .select(WebhookDelivery::as_select())
+            .for_update()
+            .first(conn)?;
+
+        Ok((recorded.status == WebhookStatus::Failed).then_some(recorded))
+    })
+}
+
+pub fn update_delivery_status(
+    conn: &mut PgConnection,
+    id_: Uuid,
+    new_status: WebhookStatus,
+    repo_id: Option<Uuid>,
+    new_error: Option<String>,
+) -> Result<WebhookDelivery> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    diesel::update(webhook_deliveries.find(id_))
+        .set((
+            status.eq(new_status),
+            repository_id.eq(repo_id),
+            error.eq(new_error),
+        ))
+        .returning(WebhookDelivery::as_returning())
+        .get_result(conn)
+        .map_err(AppError::from)
+}
+
+pub fn get_repo_deliveries(conn: &mut PgConnection, repo_id: Uuid) -> Result<Vec<WebhookDelivery>> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    webhook_deliveries
+        .filter(repository_id.eq(repo_id))
+        .order(created_at.desc())
+        .select(WebhookDelivery::as_select())
+        .load(conn)
+        .map_err(AppError::from)
+}
+
+pub fn find_repo_delivery(conn: &mut PgConnection, repo_id: Uuid, id_: Uuid) -> Result<WebhookDelivery> {
+    use crate::schema::webhook_deliveries::dsl::*;
+
+    webhook_deliveries
+        .find(id_)
+        .filter(repository_id.eq(repo_id))
+        .select(WebhookDelivery::as_select())
+        .first(conn)
+        .map_err(AppError::from)
+}
@@ -1,37 +1,314 @@
-use crate::{errors::AppError, models::Result};
-use alcoholic_jwt::{token_kid, validate, Validation, JWKS};
+use crate::{
+    errors::AppError,
+    models::{
+        projects::{self, Project, ProjectRole, UserProject},
+        repositories::Provider,
+        users::{self, User, UserKey},
+        Result,
+    },
+    routes::success,
+    services::{
+        session::{Claims, SessionKeys},
+        vcs::VcsProviders,
+    },
+    DbPool,
+};
+use actix_web::{
+    dev::Payload,
+    http::header::{Header, AUTHORIZATION},
+    web, FromRequest, HttpMessage, HttpRequest, Responder,
+};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
+use diesel::PgConnection;
+use std::collections::HashMap;
+use std::future::{ready, Future};
+use std::pin::Pin;
+use utoipa::ToSchema;
+use uuid::Uuid;
 
 pub mod github;
+pub mod gitlab;
 
-#[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    sub: String,
-    company: String,
-    exp: usize,
-}
-
-pub async fn validate_token(token: &str) -> Result<bool> {
-    let authority = std::env::var("AUTHORITY").expect("AUTHORITY must be set");
-    let jwks = fetch_jwks(&format!(
-        "{}{}",
-        authority.as_str(),
-        ".well-known/jwks.json"
-    ))
-    .await
-    .expect("failed to fetch jwks");
-    let validations = vec![Validation::Issuer(authority), Validation::SubjectPresent];
-    let kid = match token_kid(token) {
-        Ok(res) => res.expect("failed to decode kid"),
-        Err(_) => return Err(AppError::JWKSFetchError),
-    };
-    let jwk = jwks.find(&kid).expect("Specified key not found in set");
-    let res = validate(token, jwk, validations);
-    Ok(res.is_ok())
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(untagged)]
+#[serde(rename_all = "camelCase")]
+pub enum AccessTokenQuery {
+    Code { code: String },
+    AccessToken { access_token: String },
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SaveAccessTokenResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Actions on a project a user could be authorized for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ProjectPermission {
+    View,
+    Edit,
+    Manage,
+}
+
+impl ProjectPermission {
+    fn min_role(&self) -> ProjectRole {
+        match self {
+            ProjectPermission::View => ProjectRole::Viewer,
+            ProjectPermission::Edit => ProjectRole::Editor,
+            ProjectPermission::Manage => ProjectRole::Owner,
+        }
+    }
+}
+
+/// User authenticated by the session token of the request.
+///
+/// Could be declared as a handler argument. The user and the projects
+/// the user is an active member of are loaded once per request.
+#[derive(Clone, Debug)]
+pub struct AuthenticatedUser {
+    pub user: User,
+    memberships: HashMap<Uuid, (UserProject, Project)>,
+    /// Access tokens of the providers the user has signed in with.
+    tokens: HashMap<Provider, String>,
+}
+
+impl AuthenticatedUser {
+    pub fn id(&self) -> Uuid {
+        self.user.id
+    }
+
+    pub fn github_token(&self) -> Result<&str> {
+        self.vcs_token(Provider::GitHub)
+    }
+
+    /// Access token of the provider, the user must have signed in with it.
+    /// Local repositories are accessed by the server itself, their token is empty.
+    pub fn vcs_token(&self, provider: Provider) -> Result<&str> {
+        if provider == Provider::Local {
+            return Ok("");
+        }
+
+        self.tokens.get(&provider).map(String::as_str).ok_or_else(|| {
+            AppError::InvalidInput(format!(
+                "Sign in with {} to access its repositories",
+                provider.as_str()
+            ))
+        })
+    }
+
+    pub fn projects(&self) -> impl Iterator<Item = &Project> {
+        self.memberships.values().map(|(_, project)| project)
+    }
+
+    pub fn role(&self, project_id: Uuid) -> Option<ProjectRole> {
+        self.memberships.get(&project_id).map(|(membership, _)| membership.role)
+    }
+
+    pub fn can(&self, project_id: Uuid, permission: ProjectPermission) -> bool {
+        self.role(project_id)
+            .is_some_and(|role| role >= permission.min_role())
+    }
+
+    pub fn require_project(&self, project_id: Uuid, permission: ProjectPermission) -> Result<&Project> {
+        match self.memberships.get(&project_id) {
+            Some((_, project)) if self.can(project_id, permission) => Ok(project),
+            _ => Err(AppError::PermissionError),
+        }
+    }
+
+    pub fn require_design(&self, design_id: Uuid, permission: ProjectPermission) -> Result<&Project> {
+        self.projects()
+            .find(|p| p.design_id == design_id && self.can(p.id, permission))
+            .ok_or(AppError::PermissionError)
+    }
+
+    pub fn require_repo(&self, repo_id: Uuid, permission: ProjectPermission) -> Result<&Project> {
+        self.projects()
+            .find(|p| p.repo_id == Some(repo_id) && self.can(p.id, permission))
+            .ok_or(AppError::PermissionError)
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = AppError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(user) = req.extensions().get::<AuthenticatedUser>() {
+            return Box::pin(ready(Ok(user.clone())));
+        }
+
+        let claims = match bearer_claims(req) {
+            Ok(claims) => claims,
+            Err(e) => return Box::pin(ready(Err(e))),
+        };
+        let pool = match req.app_data::<web::Data<DbPool>>() {
+            Some(pool) => pool.clone(),
+            None => return Box::pin(ready(Err(AppError::AuthError))),
+        };
+        let req = req.clone();
+
+        Box::pin(async move {
+            let user = web::block(move || load_user(&pool, claims.sub)).await??;
+            req.extensions_mut().insert(user.clone());
+
+            Ok(user)
+        })
+    }
+}
+
+fn load_user(pool: &DbPool, user_id: Uuid) -> Result<AuthenticatedUser> {
+    let mut conn = pool.get()?;
+    let roled_user = users::find_user(&mut conn, users::UserKey::ID(user_id)).map_err(|e| match e {
+        AppError::RecordNotFound => AppError::AuthError,
+        e => e,
+    })?;
+    let memberships = projects::get_user_memberships(&mut conn, user_id)?
+        .into_iter()
+        .map(|(membership, project)| (project.id, (membership, project)))
+        .collect();
+    let tokens = users::get_user_identities(&mut conn, user_id)?
+        .into_iter()
+        .filter_map(|identity| Some((identity.provider, identity.access_token?)))
+        .collect();
+
+    Ok(AuthenticatedUser {
+        user: roled_user.user,
+        memberships,
+        tokens,
+    })
+}
+
+/// Claims of the session token in the `Authorization` header of the request.
+pub fn bearer_claims(req: &HttpRequest) -> Result<Claims> {
+    if let Some(claims) = request_claims(req) {
+        return Ok(claims);
+    }
+
+    let keys = req
+        .app_data::<web::Data<SessionKeys>>()
+        .ok_or(AppError::AuthError)?;
+    let authorization = Authorization::<Bearer>::parse(req).map_err(|_| AppError::AuthError)?;
+
+    validate_token(keys, authorization.as_ref().token())
+}
+
+/// Checks the permission of the user on the design with the memberships stored now,
+/// for connections which outlive the request the user has been authenticated with.
+pub fn require_design_permission(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+    design_id: Uuid,
+    permission: ProjectPermission,
+) -> Result<()> {
+    projects::get_user_memberships(conn, user_id)?
+        .into_iter()
+        .any(|(membership, project)| project.design_id == design_id && membership.role >= permission.min_role())
+        .then_some(())
+        .ok_or(AppError::PermissionError)
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/.well-known/jwks.json").route(web::get().to(get_jwks)));
+}
+
+pub fn validate_token(keys: &SessionKeys, token: &str) -> Result<Claims> {
+    keys.verify(token)
+}
+
+/// Claims of the session token validated by the authentication middleware.
+pub fn request_claims(req: &HttpRequest) -> Option<Claims> {
+    req.extensions().get::<Claims>().cloned()
+}
+
+/// Get JSON Web Key Set
+///
+/// Public keys used to verify session tokens issued by the server.
+#[utoipa::path(
+    get,
+    path = "/.well-known/jwks.json",
+    tag = "Auth",
+    responses(
+        (status = OK, description = "JSON Web Key Set", content_type = "application/json"),
+    )
+)]
+async fn get_jwks(keys: web::Data<SessionKeys>) -> impl Responder {
+    success(keys.jwks())
 }
 
-async fn fetch_jwks(uri: &str) -> core::result::Result<JWKS, reqwest::Error> {
-    reqwest::get(uri)
-    .await?
-    .json::<JWKS>()
-    .await
-}
\ No newline at end of file
+/// Exchanges the code for the tokens of the provider, or refreshes the tokens
+/// of the session user with the provider which has issued them,
+/// and issues a session token for the user. Tokens of another provider aren't refreshed.
+///
+/// A code exchanged with a valid session token in the `Authorization` header
+/// links the account of the provider to the session user.
+async fn exchange_access_token(
+    req: &HttpRequest,
+    providers: &VcsProviders,
+    provider: Provider,
+    query: AccessTokenQuery,
+    pool: web::Data<DbPool>,
+    session_keys: web::Data<SessionKeys>,
+) -> Result<impl Responder> {
+    let (vcs, token_data, session_user) = match query {
+        AccessTokenQuery::Code { code } => {
+            let session_user = match Authorization::<Bearer>::parse(req) {
+                Ok(authorization) => Some(validate_token(&session_keys, authorization.as_ref().token())?.sub),
+                Err(_) if req.headers().contains_key(AUTHORIZATION) => return Err(AppError::AuthError),
+                Err(_) => None,
+            };
+            let vcs = providers.get(provider);
+            (vcs, vcs.generate_access_token(code).await?, session_user)
+        }
+        AccessTokenQuery::AccessToken { access_token } => {
+            let claims = session_keys.verify_allow_expired(&access_token)?;
+            let pool = pool.clone();
+            let refresh_token = web::block(move || {
+                let mut conn = pool.get()?;
+                let user = users::find_user(&mut conn, UserKey::ID(claims.sub))?.user;
+
+                if user.provider != provider {
+                    return Err(AppError::InvalidInput(format!(
+                        "Tokens of the user are issued by {}, they could only be refreshed with it",
+                        user.provider.as_str()
+                    )));
+                }
+
+                users::get_user_refresh_token(&mut conn, user.id, user.provider)
+            })
+            .await??;
+
+            let vcs = providers.get(provider);
+            (vcs, vcs.refresh_access_token(refresh_token).await?, Some(claims.sub))
+        }
+    };
+
+    let user = vcs.get_user(&token_data.access_token).await?;
+    let email = vcs.get_primary_email(&token_data.access_token).await?;
+
+    let new_user = users::NewUser {
+        name: user.name.unwrap_or(user.login),
+        email,
+        provider: Some(vcs.provider()),
+    };
+    let account = users::ProviderAccount {
+        provider: vcs.provider(),
+        id: user.id,
+    };
+
+    let user = web::block(move || {
+        let mut conn = pool.get()?;
+
+        users::save_user_token_data(&mut conn, session_user, new_user, account, token_data)
+    })
+    .await??;
+
+    let session = session_keys.issue(user.id)?;
+
+    Ok(success(SaveAccessTokenResponse {
+        access_token: session.token,
+        expires_in: session.expires_in,
+    }))
+}
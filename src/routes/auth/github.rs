@@ -1,128 +1,10 @@
 use crate::{
-    errors::AppError,
-    models::{users, Result},
-    routes::success,
-    services::github::GitHubAPI,
+    models::{repositories::Provider, Result},
+    routes::auth::{exchange_access_token, AccessTokenQuery},
+    services::{session::SessionKeys, vcs::VcsProviders},
     DbPool,
 };
-use actix_web::{web, Responder};
-use reqwest::*;
-use utoipa::ToSchema;
-use std::env;
-
-struct GitHubAuth {
-    client: Client,
-    base_url: Url,
-    client_id: String,
-    client_secret: String,
-}
-
-#[derive(Deserialize, ToSchema, Debug)]
-#[serde(untagged)]
-#[serde(rename_all = "camelCase")]
-pub enum AccessTokenQuery {
-    Code { code: String },
-    AccessToken { access_token: String },
-}
-
-#[derive(Serialize, Default, Debug)]
-struct GenerateAccessTokenParams {
-    client_id: String,
-    client_secret: String,
-    code: String,
-    redirect_uri: Option<String>,
-    repository_id: Option<String>,
-}
-
-#[derive(Serialize, Default, Debug)]
-struct RefreshAccessTokenParams {
-    client_id: String,
-    client_secret: String,
-    grant_type: String,
-    refresh_token: String,
-}
-
-#[derive(Deserialize, Clone, Debug)]
-struct GenerateAccessTokenResponse {
-    access_token: String,
-    expires_in: i32,
-    refresh_token: String,
-    refresh_token_expires_in: i32,
-    scope: String,
-    token_type: String,
-}
-
-#[derive(Serialize, ToSchema, Debug)]
-#[serde(rename_all = "camelCase")]
-pub struct SaveAccessTokenResponse {
-    access_token: String,
-    expires_in: i32,
-}
-
-impl GitHubAuth {
-    pub fn new() -> Result<Self> {
-        let client_id = env::var("GITHUB_CLIENT_ID").expect("GITHUB_CLIENT_ID must be present.");
-        let client_secret =
-            env::var("GITHUB_CLIENT_SECRET").expect("GITHUB_CLIENT_SECRET must be present.");
-        let base_url = Url::parse("https://github.com")?;
-        let client = reqwest::Client::builder().build()?;
-
-        Ok(GitHubAuth {
-            client,
-            base_url,
-            client_id,
-            client_secret,
-        })
-    }
-
-    pub async fn generate_access_token(
-        &self,
-        params: GenerateAccessTokenParams,
-    ) -> Result<GenerateAccessTokenResponse> {
-        let mut url = self.base_url.clone();
-        url.set_path("/login/oauth/access_token");
-
-        let response = self
-            .client
-            .post(url)
-            .query(&params)
-            .send()
-            .await
-            .map_err(AppError::from)?;
-
-        let body = response.text().await.map_err(AppError::from)?;
-
-        if let Ok(success) = serde_urlencoded::from_str::<GenerateAccessTokenResponse>(&body) {
-            return Ok(success);
-        }
-
-        Err(AppError::GithubAuthError(body))
-    }
-
-    pub async fn refresh_access_token(
-        &self,
-        params: RefreshAccessTokenParams,
-    ) -> Result<GenerateAccessTokenResponse> {
-        let mut url = self.base_url.clone();
-        url.set_path("/login/oauth/access_token");
-
-        let response = self
-            .client
-            .post(url)
-            .query(&params)
-            .send()
-            .await
-            .map_err(AppError::from)?;
-
-        let body = response.text().await.map_err(AppError::from)?;
-
-        if let Ok(success) = serde_urlencoded::from_str::<GenerateAccessTokenResponse>(&body) {
-            return Ok(success);
-        }
-
-        Err(AppError::GithubAuthError(body))
-    }
-}
+use actix_web::{web, HttpRequest, Responder};
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
@@ -138,6 +20,12 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 /// ONLY one query parameter should be provided.
 /// Either code to generate new tokens or access token
 /// to refresh existing tokens.
+///
+/// Returned access token is a session token signed by the server.
+/// Its public keys are available at `/.well-known/jwks.json`.
+/// Github tokens are kept on the server and are never returned.
+/// Tokens are refreshed by the provider the user has signed in with,
+/// only through the route of that provider.
 #[utoipa::path(
     post,
     context_path = "/auth/github",
@@ -152,7 +40,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         (
             "access_token" = Option<String>,
             Query,
-            description = "Old Access Token to refresh tokens.",
+            description = "Old session access token (could be expired) to refresh tokens.",
         ),
     ),
     responses(
@@ -162,89 +50,21 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
             content_type = "application/json",
             body = SaveAccessTokenResponse,
             example = json!({
-                "access_token" : "gnu_token",
-                "expires_in" : 28800,
+                "accessToken" : "eyJ0eXAiOiJKV1QiLCJhbGciOiJFZERTQSJ9...",
+                "expiresIn" : 3600,
             })
         ),
-        (status = BAD_REQUEST, description = "There is no user connected to provided access_token."),
+        (status = BAD_REQUEST, description = "There is no user connected to provided access_token, or the user has signed in with another provider."),
+        (status = UNAUTHORIZED, description = "Provided access_token is not a valid session token."),
         (status = 502, body = String, content_type = "text/plain", description = "Github AUTH API request failed.")
     ),
 )]
 async fn generate_access_token(
+    req: HttpRequest,
     query: web::Query<AccessTokenQuery>,
     pool: web::Data<DbPool>,
+    session_keys: web::Data<SessionKeys>,
+    providers: web::Data<VcsProviders>,
 ) -> Result<impl Responder> {
-    let github_auth = GitHubAuth::new()?;
-    let query = query.into_inner();
-
-    let response = match query {
-        AccessTokenQuery::Code { code } => github_auth
-            .generate_access_token(GenerateAccessTokenParams {
-                client_id: github_auth.client_id.clone(),
-                client_secret: github_auth.client_secret.clone(),
-                code,
-                ..Default::default()
-            })
-            .await
-            .map_err(AppError::from)?,
-        AccessTokenQuery::AccessToken { access_token } => {
-            let pool = pool.clone();
-            let refresh_token = web::block(move || {
-                let mut conn = pool.get()?;
-                let roled_user = users::find_user(&mut conn, users::UserKey::Token(&access_token))?;
-
-                users::get_user_refresh_token(&mut conn, roled_user.user.id)
-            })
-            .await??;
-
-            github_auth
-                .refresh_access_token(RefreshAccessTokenParams {
-                    client_id: github_auth.client_id.clone(),
-                    client_secret: github_auth.client_secret.clone(),
-                    grant_type: "refresh_token".to_string(),
-                    refresh_token,
-                })
-                .await
-                .map_err(AppError::from)?
-        }
-    };
-
-    save_access_token_response(response, pool).await
-}
-
-async fn save_access_token_response(
-    response: GenerateAccessTokenResponse,
-    pool: web::Data<DbPool>,
-) -> Result<impl Responder> {
-    let github_api = GitHubAPI::new()?;
-    let user = github_api.get_auth_user(&response.access_token).await?;
-    let primary_email = github_api
-        .get_user_primary_email(&response.access_token)
-        .await?;
-
-    let new_user = users::NewUser {
-        name: user.name.unwrap_or(user.login),
-        email: primary_email.email.clone(),
-        access_token: None,
-    };
-    let save_token = users::TokenData {
-        access_token: response.access_token.clone(),
-        expires_in: response.expires_in,
-        refresh_token: response.refresh_token.clone(),
-        refresh_token_expires_in: response.refresh_token_expires_in,
-        scope: response.scope.clone(),
-        token_type: response.token_type.clone(),
-    };
-
-    web::block(move || {
-        let mut conn = pool.get()?;
-
-        users::save_user_token_data(&mut conn, new_user, save_token)
-    })
-    .await??;
-
-    Ok(success(SaveAccessTokenResponse { 
-        access_token: response.access_token, 
-        expires_in: response.expires_in 
-    }))
+    exchange_access_token(&req, &providers, Provider::GitHub, query.into_inner(), pool, session_keys).await
 }
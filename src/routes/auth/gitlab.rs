@@ -0,0 +1,73 @@
+use crate::{
+    models::{repositories::Provider, Result},
+    routes::auth::{exchange_access_token, AccessTokenQuery},
+    services::{session::SessionKeys, vcs::VcsProviders},
+    DbPool,
+};
+use actix_web::{web, HttpRequest, Responder};
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/auth/gitlab")
+            .service(web::resource("/access_token").route(web::post().to(generate_access_token))),
+    );
+}
+
+/// Generate user access token
+/// 
+/// IMPORTANT!!!
+/// 
+/// ONLY one query parameter should be provided.
+/// Either code to generate new tokens or access token
+/// to refresh existing tokens.
+///
+/// Returned access token is a session token signed by the server.
+/// Its public keys are available at `/.well-known/jwks.json`.
+/// Gitlab tokens are kept on the server and are never returned.
+/// Tokens are refreshed by the provider the user has signed in with,
+/// only through the route of that provider.
+///
+/// The code is exchanged with `GITLAB_REDIRECT_URI` as the redirect URI,
+/// it must be the one the authorization has been requested with.
+#[utoipa::path(
+    post,
+    context_path = "/auth/gitlab",
+    path = "/access_token",
+    tag = "Auth Gitlab",
+    params(
+        (
+            "code" = Option<String>,
+            Query,
+            description = "Code to request tokens.",
+        ),
+        (
+            "access_token" = Option<String>,
+            Query,
+            description = "Old session access token (could be expired) to refresh tokens.",
+        ),
+    ),
+    responses(
+        (
+            status = OK, 
+            description = "Return a new access token and expiration time in seconds for the user",
+            content_type = "application/json",
+            body = SaveAccessTokenResponse,
+            example = json!({
+                "accessToken" : "eyJ0eXAiOiJKV1QiLCJhbGciOiJFZERTQSJ9...",
+                "expiresIn" : 3600,
+            })
+        ),
+        (status = BAD_REQUEST, description = "There is no user connected to provided access_token, or the user has signed in with another provider."),
+        (status = UNAUTHORIZED, description = "Provided access_token is not a valid session token."),
+        (status = 502, body = String, content_type = "text/plain", description = "Gitlab AUTH API request failed.")
+    ),
+)]
+async fn generate_access_token(
+    req: HttpRequest,
+    query: web::Query<AccessTokenQuery>,
+    pool: web::Data<DbPool>,
+    session_keys: web::Data<SessionKeys>,
+    providers: web::Data<VcsProviders>,
+) -> Result<impl Responder> {
+    exchange_access_token(&req, &providers, Provider::GitLab, query.into_inner(), pool, session_keys).await
+}
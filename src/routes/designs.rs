@@ -1,38 +1,93 @@
 use crate::{
     errors::AppError,
-    models::{designs, projects, Result},
-    routes::success,
+    models::{
+        designs::{self, Design, DesignPatch, RevisionRetention},
+        Result,
+    },
+    routes::{
+        auth::{AuthenticatedUser, ProjectPermission},
+        success,
+    },
+    services::{collab::DesignHub, validation},
     DbPool,
 };
-use actix_web::{ web, HttpRequest, Responder};
-use utoipa::{self};
+use actix_web::{
+    http::header::{ETag, EntityTag, Header, IfMatch, IF_MATCH},
+    web, HttpMessage, HttpRequest, HttpResponse, Responder,
+};
+use json_patch::Patch;
+use utoipa::{self, ToSchema};
 use uuid::*;
 
-use super::parse_auth_token;
+pub mod collab;
+
+const JSON_PATCH_CONTENT_TYPE: &str = "application/json-patch+json";
+const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InputOperations {
+    /// Revision of the design the operations have been made on.
+    base_revision: i32,
+    /// JSON Patches (RFC 6902) in the order they have been made.
+    #[schema(value_type = Vec<Vec<Object>>)]
+    operations: Vec<Patch>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeResult {
+    design: Design,
+    /// Indices of the operations which could not be merged.
+    rejected: Vec<usize>,
+}
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
-        web::scope("/designs").service(
-            web::resource("/{id}")
-                .route(web::get().to(get_design))
-                .route(web::patch().to(update_design)),
-        ),
+        web::scope("/designs")
+            .service(web::resource("/validate").route(web::post().to(validate_design)))
+            .service(
+                web::resource("/{id}")
+                    .route(web::get().to(get_design))
+                    .route(web::put().to(update_design))
+                    .route(web::patch().to(patch_design)),
+            )
+            .service(web::resource("/{id}/operations").route(web::post().to(merge_operations)))
+            .service(web::resource("/{id}/ws/ticket").route(web::post().to(collab::issue_ticket)))
+            .service(web::resource("/{id}/revisions").route(web::get().to(get_design_revisions)))
+            .service(
+                web::resource("/{id}/revisions/{revision}")
+                    .route(web::get().to(get_design_revision)),
+            )
+            .service(
+                web::resource("/{id}/revisions/{revision}/restore")
+                    .route(web::post().to(restore_design_revision)),
+            ),
     );
 }
 
+/// The collaborative editing connection authenticates the user itself,
+/// with the session token or a ticket, so it is configured out of the scope
+/// of the authentication middleware.
+pub fn configure_collab(cfg: &mut web::ServiceConfig) {
+    cfg.service(web::resource("/designs/{id}/ws").route(web::get().to(collab::connect)));
+}
+
 /// Get a design 
 /// 
 /// A User Bearer access token should be provided to create a record. 
 /// The access token provided must be associated with a user account.
 /// 
 /// The authenticated user must have access to design's project.
+///
+/// ETag header of the response contains the revision of the design.
 #[utoipa::path(
     get,
     context_path = "/designs",
     path = "/{id}",
     tag = "Designs",
     responses(
-        (status = OK, body = Design),
+        (status = OK, body = Design, headers(("ETag" = String, description = "Revision of the design"))),
         (status = NOT_FOUND),
         (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
@@ -47,22 +102,18 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 async fn get_design(
     id: web::Path<Uuid>,
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token: String = parse_auth_token(req)?;
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::View)?;
 
     web::block(move || {
         let mut conn = pool.get()?;
-        let projects: Vec<projects::Project> = projects::get_user_projects(&mut conn, &token)?;
-
-        if !projects.iter().any(|p| p.design_id.eq(&id)) {
-            return Err(AppError::PermissionError);
-        }
 
-        designs::get_design(&mut conn, id.into_inner())
+        designs::get_design(&mut conn, id)
     })
     .await?
-    .map(success)
+    .map(design_response)
 }
 
 /// Update a design 
@@ -71,13 +122,26 @@ async fn get_design(
 /// The access token provided must be associated with a user account.
 /// 
 /// The authenticated user must have access to design's project.
+///
+/// Replaces the whole design data.
+/// The design must match the design model.
+///
+/// Every update is recorded as a new revision of the design.
+///
+/// If-Match header with the ETag of the design makes the update conditional.
+/// The update is rejected if the design has been modified since.
+///
+/// Editors connected to the design over WebSocket receive the update.
 #[utoipa::path(
-    patch,
+    put,
     context_path = "/designs",
     path = "/{id}",
     tag = "Designs",
     responses(
-        (status = OK, body = Design),
+        (status = OK, body = Design, headers(("ETag" = String, description = "Revision of the design"))),
+        (status = PRECONDITION_FAILED, description = "Design has been modified. ETag header contains the current revision."),
+        (status = BAD_REQUEST, description = "If-Match header is malformed."),
+        (status = UNPROCESSABLE_ENTITY, description = "Design doesn't match the design model. Body contains the list of violations."),
         (status = NOT_FOUND),
         (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
@@ -85,6 +149,7 @@ async fn get_design(
     request_body(content = serde_json::Value, description = "Design structure in JSON format", content_type = "application/json"),
     params(
         ("id" = Uuid, Path, description = "Design record id in the database"),
+        ("If-Match" = Option<String>, Header, description = "ETag of the design revision the update is based on"),
     ),
     security(
         ("http" = [])
@@ -93,21 +158,364 @@ async fn get_design(
 async fn update_design(
     id: web::Path<Uuid>,
     data: web::Json<serde_json::Value>,
+    req: HttpRequest,
     pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::Edit)?;
+    let author = auth_user.id();
+    let expected = expected_revisions(&req)?;
+    let data = data.into_inner();
+    validation::validate_design(&data).map_err(AppError::DesignValidation)?;
+
+    collab::write_design(&hub.room(id), pool, author, None, None, move |conn| {
+        designs::update_design(conn, id, data, Some(author), expected.as_deref(), **retention)
+    })
+    .await
+    .map(design_response)
+}
+
+/// Patch a design
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+///
+/// Accepts either JSON Patch (RFC 6902) with `application/json-patch+json`
+/// or JSON Merge Patch (RFC 7396) with `application/merge-patch+json` content type.
+/// The patch is applied to the stored design atomically, nothing is saved
+/// if any of its operations fails or the patched design doesn't match the design model.
+///
+/// If-Match header is honored the same way as for design updates.
+/// Editors connected to the design over WebSocket receive the patch.
+#[utoipa::path(
+    patch,
+    context_path = "/designs",
+    path = "/{id}",
+    tag = "Designs",
+    responses(
+        (status = OK, body = Design, headers(("ETag" = String, description = "Revision of the design"))),
+        (status = BAD_REQUEST, description = "Patch document or If-Match header is malformed."),
+        (status = NOT_FOUND),
+        (status = CONFLICT, description = "Test operation of JSON Patch failed."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = PRECONDITION_FAILED, description = "Design has been modified. ETag header contains the current revision."),
+        (status = UNSUPPORTED_MEDIA_TYPE, description = "Content type is not a supported patch format."),
+        (status = UNPROCESSABLE_ENTITY, description = "Patch operation could not be applied or the patched design doesn't match the design model."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    request_body(
+        content = serde_json::Value,
+        description = "JSON Patch or JSON Merge Patch document",
+        content_type = "application/json-patch+json"
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+        ("If-Match" = Option<String>, Header, description = "ETag of the design revision the patch is based on"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+async fn patch_design(
+    id: web::Path<Uuid>,
+    body: web::Bytes,
     req: HttpRequest,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::Edit)?;
+    let author = auth_user.id();
+    let expected = expected_revisions(&req)?;
+
+    let patch = match req.content_type() {
+        JSON_PATCH_CONTENT_TYPE => DesignPatch::Json(serde_json::from_slice(&body)?),
+        MERGE_PATCH_CONTENT_TYPE => DesignPatch::Merge(serde_json::from_slice(&body)?),
+        content_type => {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "{content_type}, expected {JSON_PATCH_CONTENT_TYPE} or {MERGE_PATCH_CONTENT_TYPE}"
+            )))
+        }
+    };
+
+    // Merge patches are published as a replacement of the design.
+    let change = match &patch {
+        DesignPatch::Json(json_patch) => Some(json_patch.clone()),
+        DesignPatch::Merge(_) => None,
+    };
+
+    collab::write_design(&hub.room(id), pool, author, None, change, move |conn| {
+        designs::patch_design(conn, id, &patch, author, expected.as_deref(), **retention)
+    })
+    .await
+    .map(design_response)
+}
+
+/// Merge design operations
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+///
+/// Pushes operations made on an older revision of the design, e.g. while offline.
+/// Operations are rebased over the changes made since the base revision
+/// and applied in order, each operation as a whole. Operations which conflict
+/// with the changes are rejected, the rest is saved as one new revision.
+/// Operations on values the changes have removed or overwritten conflict with them.
+///
+/// Editors connected to the design over WebSocket receive the merged operations.
+#[utoipa::path(
+    post,
+    context_path = "/designs",
+    path = "/{id}/operations",
+    tag = "Designs",
+    request_body(content = InputOperations, content_type = "application/json"),
+    responses(
+        (status = OK, body = MergeResult, headers(("ETag" = String, description = "Revision of the design"))),
+        (status = BAD_REQUEST, description = "Operations are malformed or the base revision doesn't exist."),
+        (status = NOT_FOUND),
+        (status = CONFLICT, description = "Changes since the base revision have been pruned. ETag header contains the current revision."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNPROCESSABLE_ENTITY, description = "Merged design doesn't match the design model. Body contains the list of violations."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn merge_operations(
+    id: web::Path<Uuid>,
+    input: web::Json<InputOperations>,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::Edit)?;
+    let author = auth_user.id();
+    let InputOperations {
+        base_revision,
+        operations,
+    } = input.into_inner();
+
+    let room = hub.room(id);
+    let _writer = room.write().await;
+
+    let merged = web::block(move || {
+        let mut conn = pool.get()?;
+
+        designs::merge_operations(&mut conn, id, base_revision, operations, author, **retention)
+    })
+    .await??;
+
+    if let Some(patch) = merged.patch {
+        collab::publish(&room, &merged.design, patch, Some(author), None);
+    }
+
+    Ok(HttpResponse::Ok()
+        .insert_header(etag(&merged.design))
+        .json(MergeResult {
+            design: merged.design,
+            rejected: merged.rejected,
+        }))
+}
+
+/// Validate a design
+///
+/// A User Bearer access token should be provided.
+///
+/// Checks the design against the design model without saving it.
+#[utoipa::path(
+    post,
+    context_path = "/designs",
+    path = "/validate",
+    tag = "Designs",
+    responses(
+        (status = OK, description = "Design matches the design model."),
+        (status = UNPROCESSABLE_ENTITY, description = "Design doesn't match the design model. Body contains the list of violations."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    request_body(content = serde_json::Value, description = "Design structure in JSON format", content_type = "application/json"),
+    security(
+        ("http" = [])
+    )
+)]
+async fn validate_design(
+    data: web::Json<serde_json::Value>,
+    _auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    validation::validate_design(&data).map_err(AppError::DesignValidation)?;
+
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// Get design revisions
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+///
+/// Revisions are returned from the newest one, without design data.
+#[utoipa::path(
+    get,
+    context_path = "/designs",
+    path = "/{id}/revisions",
+    tag = "Designs",
+    responses(
+        (status = OK, body = Vec<DesignRevisionInfo>),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_design_revisions(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token: String = parse_auth_token(req)?;
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::View)?;
 
     web::block(move || {
         let mut conn = pool.get()?;
-        let projects: Vec<projects::Project> = projects::get_user_projects(&mut conn, &token)?;
 
-        if !projects.iter().any(|p| p.design_id.eq(&id)) {
-            return Err(AppError::PermissionError);
-        }
+        designs::get_design_revisions(&mut conn, id)
+    })
+    .await?
+    .map(success)
+}
 
-        designs::update_design(&mut conn, id.into_inner(), data.into_inner())
+/// Get a design revision
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+#[utoipa::path(
+    get,
+    context_path = "/designs",
+    path = "/{id}/revisions/{revision}",
+    tag = "Designs",
+    responses(
+        (status = OK, body = DesignRevision),
+        (status = NOT_FOUND, description = "Revision is not found."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+        ("revision" = i32, Path, description = "Revision number"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_design_revision(
+    path: web::Path<(Uuid, i32)>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (id, revision) = path.into_inner();
+    auth_user.require_design(id, ProjectPermission::View)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        designs::get_design_revision(&mut conn, id, revision)
     })
     .await?
     .map(success)
 }
+
+/// Restore a design revision
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+///
+/// Data of the revision becomes the data of a new design revision,
+/// history of the design is kept.
+///
+/// If-Match header is honored the same way as for design updates.
+/// Editors connected to the design over WebSocket receive the restored design.
+#[utoipa::path(
+    post,
+    context_path = "/designs",
+    path = "/{id}/revisions/{revision}/restore",
+    tag = "Designs",
+    responses(
+        (status = OK, body = Design, headers(("ETag" = String, description = "Revision of the design"))),
+        (status = PRECONDITION_FAILED, description = "Design has been modified. ETag header contains the current revision."),
+        (status = BAD_REQUEST, description = "If-Match header is malformed."),
+        (status = NOT_FOUND, description = "Revision is not found."),
+        (status = UNPROCESSABLE_ENTITY, description = "Revision doesn't match the design model. Body contains the list of violations."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+        ("revision" = i32, Path, description = "Revision number to restore"),
+        ("If-Match" = Option<String>, Header, description = "ETag of the current design revision"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn restore_design_revision(
+    path: web::Path<(Uuid, i32)>,
+    req: HttpRequest,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (id, revision) = path.into_inner();
+    auth_user.require_design(id, ProjectPermission::Edit)?;
+    let author = auth_user.id();
+    let expected = expected_revisions(&req)?;
+
+    collab::write_design(&hub.room(id), pool, author, None, None, move |conn| {
+        designs::restore_design_revision(conn, id, revision, author, expected.as_deref(), **retention)
+    })
+    .await
+    .map(design_response)
+}
+
+fn design_response(design: Design) -> HttpResponse {
+    HttpResponse::Ok().insert_header(etag(&design)).json(design)
+}
+
+fn etag(design: &Design) -> ETag {
+    ETag(EntityTag::new_strong(design.revision.to_string()))
+}
+
+// Revisions from If-Match header, `None` when the header is absent or any revision matches.
+// A malformed header is rejected, tags which are not revision numbers never match.
+fn expected_revisions(req: &HttpRequest) -> Result<Option<Vec<i32>>> {
+    if !req.headers().contains_key(IF_MATCH) {
+        return Ok(None);
+    }
+
+    match IfMatch::parse(req) {
+        Ok(IfMatch::Items(tags)) if !tags.is_empty() => Ok(Some(
+            tags.iter()
+                .filter(|tag| !tag.weak)
+                .filter_map(|tag| tag.tag().parse::<i32>().ok())
+                .collect(),
+        )),
+        Ok(IfMatch::Any) => Ok(None),
+        _ => Err(AppError::HeaderParse(
+            "If-Match must be * or a list of entity tags".to_string(),
+        )),
+    }
+}
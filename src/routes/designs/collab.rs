@@ -0,0 +1,473 @@
+use crate::{
+    errors::AppError,
+    models::{
+        designs::{self, Design, DesignPatch, RevisionRetention},
+        Result,
+    },
+    routes::{
+        auth::{self, AuthenticatedUser, ProjectPermission},
+        success,
+    },
+    services::{
+        collab::{DesignChange, DesignHub, Origin, Room, RoomHandle},
+        session::SessionKeys,
+        validation::Violation,
+    },
+    DbPool,
+};
+use actix_web::{rt, web, HttpRequest, HttpResponse, Responder};
+use actix_ws::{AggregatedMessage, AggregatedMessageStream, CloseCode, CloseReason, Closed, Session};
+use diesel::PgConnection;
+use json_patch::Patch;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectQuery {
+    /// Revision of the design the client already has. Omit to receive a snapshot of the design.
+    revision: Option<i32>,
+    /// Ticket issued for the design, used instead of the session token in the `Authorization` header.
+    ticket: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectTicket {
+    ticket: String,
+    /// Seconds the ticket could be used for.
+    expires_in: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ClientMessage {
+    #[serde(rename_all = "camelCase")]
+    Op { op_id: String, patch: Patch },
+    Sync { revision: Option<i32> },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum ServerMessage<'a> {
+    Snapshot {
+        design: &'a Design,
+    },
+    Synced {
+        revision: i32,
+    },
+    #[serde(rename_all = "camelCase")]
+    Op {
+        revision: i32,
+        patch: &'a Patch,
+        author_id: Option<Uuid>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Ack {
+        op_id: &'a str,
+        revision: i32,
+    },
+    #[serde(rename_all = "camelCase")]
+    Reject {
+        op_id: &'a str,
+        error: String,
+        #[serde(skip_serializing_if = "<[_]>::is_empty")]
+        violations: &'a [Violation],
+    },
+    Error {
+        error: String,
+    },
+}
+
+/// Issue a ticket to edit a design collaboratively
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to design's project.
+///
+/// Browsers can't set the `Authorization` header of a WebSocket handshake,
+/// they pass the ticket in the `ticket` query parameter of the connection instead.
+/// The ticket is valid for 30 seconds, the connection lasts until the session token expires.
+#[utoipa::path(
+    post,
+    context_path = "/designs",
+    path = "/{id}/ws/ticket",
+    tag = "Designs",
+    responses(
+        (status = OK, body = ConnectTicket),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+pub(super) async fn issue_ticket(
+    id: web::Path<Uuid>,
+    req: HttpRequest,
+    keys: web::Data<SessionKeys>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_design(id, ProjectPermission::View)?;
+
+    let ticket = keys.issue_ticket(&auth::bearer_claims(&req)?, id)?;
+
+    Ok(success(ConnectTicket {
+        ticket: ticket.token,
+        expires_in: ticket.expires_in,
+    }))
+}
+
+/// Edit a design collaboratively
+///
+/// A User Bearer access token should be provided, in the `Authorization` header
+/// or as a ticket issued for the design in the `ticket` query parameter.
+/// The authenticated user must have access to design's project,
+/// only editors and owners can send operations.
+///
+/// The permission of the user is checked again for every operation and resync,
+/// and when the membership of the user changes. The connection is closed
+/// when the user loses access to the design or the session token expires.
+///
+/// Upgrades the connection to WebSocket. Messages are JSON objects with `type` field.
+///
+/// Client messages:
+/// - `{"type": "op", "opId": "...", "patch": [...]}` applies JSON Patch (RFC 6902) to the stored design.
+/// - `{"type": "sync", "revision": 42}` resyncs the client from the revision it has.
+///
+/// Server messages:
+/// - `snapshot` with the whole `design`, sent when the client has no or too old revision.
+/// - `synced` with the current `revision`, sent when the client is up to date.
+/// - `op` with `revision`, `patch` and `authorId` of a change made by another editor or a REST update,
+///   `authorId` is `null` for changes pushed to the repository.
+/// - `ack` with `opId` and `revision` of an applied operation of the client.
+/// - `reject` with `opId`, `error` and `violations` of an operation which was not applied.
+/// - `error` for malformed messages.
+///
+/// Changes are sent in the order of revisions. A client which receives
+/// a revision other than the next one should resync.
+#[utoipa::path(
+    get,
+    context_path = "/designs",
+    path = "/{id}/ws",
+    tag = "Designs",
+    responses(
+        (status = SWITCHING_PROTOCOLS, description = "Connection is upgraded to WebSocket."),
+        (status = BAD_REQUEST, description = "Request is not a WebSocket handshake."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token or a ticket.")
+    ),
+    params(
+        ("id" = Uuid, Path, description = "Design record id in the database"),
+        ConnectQuery,
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn connect(
+    id: web::Path<Uuid>,
+    query: web::Query<ConnectQuery>,
+    req: HttpRequest,
+    body: web::Payload,
+    pool: web::Data<DbPool>,
+    keys: web::Data<SessionKeys>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+) -> actix_web::Result<HttpResponse> {
+    let id = id.into_inner();
+    let (user_id, expires_at) = match &query.ticket {
+        Some(ticket) => {
+            let claims = keys.verify_ticket(ticket, id)?;
+            (claims.sub, claims.session_exp)
+        }
+        None => {
+            let claims = auth::bearer_claims(&req)?;
+            (claims.sub, claims.exp)
+        }
+    };
+
+    let permission_pool = pool.clone();
+    web::block(move || {
+        let mut conn = permission_pool.get()?;
+
+        auth::require_design_permission(&mut conn, user_id, id, ProjectPermission::View)
+    })
+    .await??;
+
+    let (response, session, stream) = actix_ws::handle(&req, body)?;
+    let editor = Editor {
+        connection_id: Uuid::new_v4(),
+        design_id: id,
+        user_id,
+        expires_at,
+        room: hub.room(id),
+        pool,
+        retention: **retention,
+        session,
+        revision: 0,
+    };
+
+    rt::spawn(editor.run(stream.aggregate_continuations(), query.revision));
+
+    Ok(response)
+}
+
+/// Writes the design while holding the room of the design
+/// and publishes the change to connected editors.
+///
+/// Changes without a patch are published as a replacement of the whole design.
+pub(super) async fn write_design<F>(
+    room: &Room,
+    pool: web::Data<DbPool>,
+    author_id: Uuid,
+    origin: Option<Origin>,
+    patch: Option<Patch>,
+    write: F,
+) -> Result<Design>
+where
+    F: FnOnce(&mut PgConnection) -> Result<Design> + Send + 'static,
+{
+    let _writer = room.write().await;
+
+    let design = web::block(move || {
+        let mut conn = pool.get()?;
+
+        write(&mut conn)
+    })
+    .await??;
+
+    let patch = patch.unwrap_or_else(|| designs::replace_patch(&design.data));
+    publish(room, &design, patch, Some(author_id), origin);
+
+    Ok(design)
+}
+
+/// Publishes a saved change of the design to connected editors.
+/// The room should be held for writing while the change is saved.
+pub(crate) fn publish(room: &Room, design: &Design, patch: Patch, author_id: Option<Uuid>, origin: Option<Origin>) {
+    room.publish(DesignChange {
+        revision: design.revision,
+        patch,
+        author_id,
+        origin,
+    });
+}
+
+struct Editor {
+    connection_id: Uuid,
+    design_id: Uuid,
+    user_id: Uuid,
+    // Expiration of the session token the connection has been authenticated with.
+    expires_at: i64,
+    room: RoomHandle,
+    pool: web::Data<DbPool>,
+    retention: RevisionRetention,
+    session: Session,
+    // Last revision sent to the client.
+    revision: i32,
+}
+
+impl Editor {
+    async fn run(mut self, mut stream: AggregatedMessageStream, revision: Option<i32>) {
+        let mut changes = self.room.subscribe();
+        let mut members = self.room.subscribe_members();
+        let expires_in = (self.expires_at - chrono::Utc::now().timestamp()).max(0);
+        let expiration = tokio::time::sleep(Duration::from_secs(expires_in as u64));
+        tokio::pin!(expiration);
+
+        let mut result = self.sync(revision).await;
+        let mut reason = None;
+
+        while result.is_ok() {
+            result = tokio::select! {
+                message = stream.recv() => match message {
+                    Some(Ok(AggregatedMessage::Text(text))) => self.receive(&text).await,
+                    Some(Ok(AggregatedMessage::Ping(bytes))) => self.session.pong(&bytes).await,
+                    Some(Ok(AggregatedMessage::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => Ok(()),
+                },
+                change = changes.recv() => match change {
+                    Ok(change) => self.forward(&change).await,
+                    Err(RecvError::Lagged(_)) => self.sync(Some(self.revision)).await,
+                    Err(RecvError::Closed) => break,
+                },
+                member = members.recv() => match member {
+                    Ok(user_id) if user_id != self.user_id => Ok(()),
+                    // Lagged notifications could be about the user too.
+                    Ok(_) | Err(RecvError::Lagged(_)) => match self.can_view().await {
+                        true => Ok(()),
+                        false => {
+                            reason = Some(close_reason("Access to the design has been lost"));
+                            break;
+                        }
+                    },
+                    Err(RecvError::Closed) => break,
+                },
+                _ = &mut expiration => {
+                    reason = Some(close_reason("Session token has expired"));
+                    break;
+                },
+            };
+        }
+
+        let _ = self.session.close(reason).await;
+    }
+
+    async fn can_view(&self) -> bool {
+        let (pool, user_id, design_id) = (self.pool.clone(), self.user_id, self.design_id);
+        let result = web::block(move || {
+            let mut conn = pool.get()?;
+
+            auth::require_design_permission(&mut conn, user_id, design_id, ProjectPermission::View)
+        })
+        .await;
+
+        !matches!(result, Ok(Err(AppError::PermissionError)))
+    }
+
+    async fn receive(&mut self, text: &str) -> std::result::Result<(), Closed> {
+        match serde_json::from_str::<ClientMessage>(text) {
+            Ok(ClientMessage::Op { op_id, patch }) => self.apply(op_id, patch).await,
+            Ok(ClientMessage::Sync { revision }) => self.sync(revision).await,
+            Err(e) => self.send(ServerMessage::Error { error: e.to_string() }).await,
+        }
+    }
+
+    // The acknowledgement is sent when the change comes back from the room,
+    // so it is ordered with changes of other editors.
+    async fn apply(&mut self, op_id: String, patch: Patch) -> std::result::Result<(), Closed> {
+        let (design_id, author, retention) = (self.design_id, self.user_id, self.retention);
+        let design_patch = DesignPatch::Json(patch.clone());
+        let origin = Origin {
+            connection_id: self.connection_id,
+            op_id: op_id.clone(),
+        };
+
+        // The role of the user could have changed since the connection has been opened.
+        let result = write_design(&self.room, self.pool.clone(), author, Some(origin), Some(patch), move |conn| {
+            auth::require_design_permission(conn, author, design_id, ProjectPermission::Edit)?;
+            designs::patch_design(conn, design_id, &design_patch, author, None, retention)
+        })
+        .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => {
+                let violations = match &e {
+                    AppError::DesignValidation(violations) => violations.as_slice(),
+                    _ => &[],
+                };
+
+                self.send(ServerMessage::Reject {
+                    op_id: &op_id,
+                    error: e.to_string(),
+                    violations,
+                })
+                .await
+            }
+        }
+    }
+
+    async fn forward(&mut self, change: &DesignChange) -> std::result::Result<(), Closed> {
+        if change.revision <= self.revision {
+            return Ok(());
+        }
+
+        if change.revision != self.revision + 1 {
+            return self.sync(Some(self.revision)).await;
+        }
+
+        self.revision = change.revision;
+
+        match &change.origin {
+            Some(origin) if origin.connection_id == self.connection_id => {
+                self.send(ServerMessage::Ack {
+                    op_id: &origin.op_id,
+                    revision: change.revision,
+                })
+                .await
+            }
+            _ => {
+                self.send(ServerMessage::Op {
+                    revision: change.revision,
+                    patch: &change.patch,
+                    author_id: change.author_id,
+                })
+                .await
+            }
+        }
+    }
+
+    // Brings the client from its revision to the current one, with the changes
+    // from the room history when possible, with a snapshot otherwise.
+    async fn sync(&mut self, revision: Option<i32>) -> std::result::Result<(), Closed> {
+        let (design, changes) = match self.current(revision).await {
+            Ok(current) => current,
+            Err(e) => return self.send(ServerMessage::Error { error: e.to_string() }).await,
+        };
+
+        self.revision = design.revision;
+
+        match (revision, changes) {
+            (Some(revision), _) if revision == design.revision => {
+                self.send(ServerMessage::Synced { revision }).await
+            }
+            (_, Some(changes)) => {
+                for change in changes {
+                    self.send(ServerMessage::Op {
+                        revision: change.revision,
+                        patch: &change.patch,
+                        author_id: change.author_id,
+                    })
+                    .await?;
+                }
+
+                Ok(())
+            }
+            _ => self.send(ServerMessage::Snapshot { design: &design }).await,
+        }
+    }
+
+    // Current design and the changes made after the revision, read while
+    // holding the room so no change is published in between.
+    async fn current(&self, revision: Option<i32>) -> Result<(Design, Option<Vec<Arc<DesignChange>>>)> {
+        let _writer = self.room.write().await;
+        let pool = self.pool.clone();
+        let (design_id, user_id) = (self.design_id, self.user_id);
+
+        let design = web::block(move || {
+            let mut conn = pool.get()?;
+
+            auth::require_design_permission(&mut conn, user_id, design_id, ProjectPermission::View)?;
+            designs::get_design(&mut conn, design_id)
+        })
+        .await??;
+
+        let changes = revision
+            .filter(|revision| *revision < design.revision)
+            .and_then(|revision| self.room.changes_since(revision, design.revision));
+
+        Ok((design, changes))
+    }
+
+    async fn send(&mut self, message: ServerMessage<'_>) -> std::result::Result<(), Closed> {
+        let text = serde_json::to_string(&message).expect("Server messages are serializable");
+
+        self.session.text(text).await
+    }
+}
+
+fn close_reason(description: &str) -> CloseReason {
+    CloseReason {
+        code: CloseCode::Policy,
+        description: Some(description.to_string()),
+    }
+}
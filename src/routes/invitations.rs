@@ -0,0 +1,273 @@
+use crate::{
+    errors::AppError,
+    models::{
+        invitations::{self, InvitationStatus, NewProjectInvitation, ProjectInvitation},
+        projects::ProjectRole,
+        Result,
+    },
+    routes::{
+        auth::{AuthenticatedUser, ProjectPermission},
+        success,
+    },
+    DbPool,
+};
+use actix_web::{web, Responder};
+use ring::digest::{digest, SHA256};
+use ring::rand::{SecureRandom, SystemRandom};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+
+const DEFAULT_EXPIRES_IN: i64 = 7 * 24 * 3600;
+const MAX_EXPIRES_IN: i64 = 30 * 24 * 3600;
+
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InputInvitation {
+    role: ProjectRole,
+    /// Number of times the invitation could be accepted. Omit for multi-use invitation without limit.
+    max_uses: Option<i32>,
+    /// Lifetime of the invitation in seconds. Defaults to 7 days, at most 30 days.
+    expires_in: Option<i64>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct CreatedInvitation {
+    invitation: ProjectInvitation,
+    /// Secret token of the invitation. It is returned only once.
+    token: String,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationInfo {
+    #[serde(flatten)]
+    invitation: ProjectInvitation,
+    status: InvitationStatus,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InvitationsQuery {
+    /// Include accepted, expired and revoked invitations.
+    all: Option<bool>,
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/invitations")
+            .service(web::resource("/{token}/accept").route(web::post().to(accept_invitation))),
+    );
+}
+
+/// Create a project invitation
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project.
+///
+/// The returned token is used to accept the invitation,
+/// only its hash is stored on the server.
+#[utoipa::path(
+    post,
+    context_path = "/projects",
+    path = "/{id}/invitations",
+    tag = "Invitations",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+    ),
+    request_body(content = InputInvitation, content_type = "application/json"),
+    responses(
+        (status = OK, body = CreatedInvitation),
+        (status = BAD_REQUEST, description = "Incorrect input data"),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+pub(super) async fn create_invitation(
+    id: web::Path<Uuid>,
+    input: web::Json<InputInvitation>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_project(id, ProjectPermission::Manage)?;
+
+    let expires_in = input.expires_in.unwrap_or(DEFAULT_EXPIRES_IN);
+    if !(1..=MAX_EXPIRES_IN).contains(&expires_in) || input.max_uses.is_some_and(|m| m < 1) {
+        return Err(AppError::InvalidInput(
+            "expiresIn or maxUses is out of allowed range".to_string(),
+        ));
+    }
+
+    let token = generate_token()?;
+    let token_hash = hash_token(&token);
+    let expires_at = (chrono::Utc::now() + chrono::Duration::seconds(expires_in)).naive_utc();
+    let created_by = auth_user.id();
+
+    let invitation = web::block(move || {
+        let mut conn = pool.get()?;
+
+        invitations::create_invitation(
+            &mut conn,
+            NewProjectInvitation {
+                project_id: id,
+                token_hash: &token_hash,
+                role: input.role,
+                max_uses: input.max_uses,
+                created_by,
+                expires_at,
+            },
+        )
+    })
+    .await??;
+
+    Ok(success(CreatedInvitation { invitation, token }))
+}
+
+/// Get project invitations
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project.
+///
+/// Only pending invitations are returned unless `all` is set.
+#[utoipa::path(
+    get,
+    context_path = "/projects",
+    path = "/{id}/invitations",
+    tag = "Invitations",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+        InvitationsQuery,
+    ),
+    responses(
+        (status = OK, body = Vec<InvitationInfo>),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+pub(super) async fn get_project_invitations(
+    id: web::Path<Uuid>,
+    query: web::Query<InvitationsQuery>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_project(id, ProjectPermission::Manage)?;
+
+    let project_invitations = web::block(move || {
+        let mut conn = pool.get()?;
+
+        invitations::get_project_invitations(&mut conn, id)
+    })
+    .await??;
+
+    let now = chrono::Utc::now().naive_utc();
+    let all = query.all.unwrap_or(false);
+    let infos: Vec<InvitationInfo> = project_invitations
+        .into_iter()
+        .map(|invitation| InvitationInfo {
+            status: invitation.status(now),
+            invitation,
+        })
+        .filter(|info| all || info.status == InvitationStatus::Pending)
+        .collect();
+
+    Ok(success(infos))
+}
+
+/// Revoke a project invitation
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project.
+#[utoipa::path(
+    delete,
+    context_path = "/projects",
+    path = "/{id}/invitations/{invitation_id}",
+    tag = "Invitations",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+        ("invitation_id" = Uuid, Path, description = "Invitation record id in database"),
+    ),
+    responses(
+        (status = OK, body = ProjectInvitation),
+        (status = NOT_FOUND, description = "Invitation is not found or already revoked."),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+pub(super) async fn revoke_invitation(
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (id, invitation_id) = path.into_inner();
+    auth_user.require_project(id, ProjectPermission::Manage)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        invitations::revoke_invitation(&mut conn, id, invitation_id)
+    })
+    .await?
+    .map(success)
+}
+
+/// Accept a project invitation
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user becomes a member of the project
+/// with the role of the invitation. Members of the project keep their role.
+#[utoipa::path(
+    post,
+    context_path = "/invitations",
+    path = "/{token}/accept",
+    tag = "Invitations",
+    params(
+        ("token" = String, Path, description = "Invitation token"),
+    ),
+    responses(
+        (status = OK, body = Project),
+        (status = NOT_FOUND, description = "Invitation is not found."),
+        (status = GONE, description = "Invitation has expired, has been revoked or used up."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn accept_invitation(
+    token: web::Path<String>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let token_hash = hash_token(&token);
+    let user_id = auth_user.id();
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        invitations::accept_invitation(&mut conn, &token_hash, user_id)
+    })
+    .await?
+    .map(success)
+}
+
+fn generate_token() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    SystemRandom::new().fill(&mut bytes)?;
+
+    Ok(hex::encode(bytes))
+}
+
+fn hash_token(token: &str) -> String {
+    hex::encode(digest(&SHA256, token.as_bytes()))
+}
@@ -1,17 +1,21 @@
 use crate::{
+    errors::AppError,
     models::{
-        projects::{self, UpdateProject},
-        users, Result,
+        projects::{self, NewUserProject, ProjectRole, UpdateProject},
+        repositories::{self, Provider, RepositoryKey},
+        Result,
     },
-    routes::success,
+    routes::{
+        auth::{AuthenticatedUser, ProjectPermission},
+        invitations, success,
+    },
+    services::{collab::DesignHub, vcs::VcsProviders},
     DbPool,
 };
-use actix_web::{web, HttpRequest, Responder};
+use actix_web::{web, HttpResponse, Responder};
 use uuid::*;
 use utoipa::ToSchema;
 
-use super::parse_auth_token;
-
 #[derive(Deserialize, ToSchema, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct InputProject {
@@ -19,6 +23,19 @@ pub struct InputProject {
     repo_id: Option<Uuid>,
 }
 
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InputProjectMember {
+    user_id: Uuid,
+    role: ProjectRole,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct InputMemberRole {
+    role: ProjectRole,
+}
+
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/projects")
@@ -32,6 +49,25 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
                     .route(web::get().to(get_project))
                     .route(web::patch().to(update_project)),
             )
+            .service(
+                web::resource("/{id}/members")
+                    .route(web::get().to(get_project_members))
+                    .route(web::post().to(add_project_member)),
+            )
+            .service(
+                web::resource("/{id}/members/{user_id}")
+                    .route(web::patch().to(update_project_member))
+                    .route(web::delete().to(remove_project_member)),
+            )
+            .service(
+                web::resource("/{id}/invitations")
+                    .route(web::get().to(invitations::get_project_invitations))
+                    .route(web::post().to(invitations::create_invitation)),
+            )
+            .service(
+                web::resource("/{id}/invitations/{invitation_id}")
+                    .route(web::delete().to(invitations::revoke_invitation)),
+            )
             .service(web::resource("/find/{name}").route(web::get().to(find_project))),
     );
 }
@@ -40,6 +76,9 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 ///
 /// A User Bearer access token should be provided to create a record. 
 /// The access token provided must be associated with a user account.
+///
+/// A repository already linked to other projects could be linked by their editors,
+/// an unlinked repository by the user who has created it or can push to it.
 #[utoipa::path(
     post,
     context_path = "/projects",
@@ -48,7 +87,8 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     responses(
         (status = OK, body = RoledUser),
         (status = BAD_REQUEST, description = "Unique constaint violation."),
-        (status = NOT_FOUND, description = "User is not found by provided token."),
+        (status = NOT_FOUND, description = "User or repository is not found."),
+        (status = FORBIDDEN, description = "Authorized user can't link the repository."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
     ),
     request_body(content = InputProject, description = "Input Project in JSON format", content_type = "application/json"),
@@ -59,22 +99,28 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 async fn create_project(
     input: web::Json<InputProject>,
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
+    let user_id = auth_user.id();
+
+    if let Some(repo_id) = input.repo_id {
+        authorize_repo_link(&pool, &providers, &auth_user, repo_id).await?;
+    }
 
     web::block(move || {
         let mut conn = pool.get()?;
-        let roled_user = users::find_user(&mut conn, users::UserKey::Token(&token))?;
 
-        projects::create_project(&mut conn, &input.name, input.repo_id, roled_user.user.id)
+        projects::create_project(&mut conn, &input.name, input.repo_id, user_id)
     })
     .await?
     .map(success)
 }
 
-/// Find a user by name
+/// Find a project by name
 ///
+/// A User Bearer access token should be provided.
+/// Only the projects the authenticated user is a member of are found.
 #[utoipa::path(
     get,
     context_path = "/projects",
@@ -86,21 +132,26 @@ async fn create_project(
     responses(
         (status = OK, body = Project),
         (status = BAD_REQUEST, description = "Incorrect name format"),
-        (status = NOT_FOUND, description = "Project is not found by provided name.")
+        (status = NOT_FOUND, description = "Project of the user is not found by provided name."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
     )
 )]
-async fn find_project(name: web::Path<String>, pool: web::Data<DbPool>) -> Result<impl Responder> {
-    web::block(move || {
-        let mut conn = pool.get()?;
-
-        projects::find_project(&mut conn, projects::ProjectKey::Name(&name.to_owned()))
-    })
-    .await?
-    .map(success)
+async fn find_project(name: web::Path<String>, auth_user: AuthenticatedUser) -> Result<impl Responder> {
+    // Projects of other users are indistinguishable from missing ones.
+    auth_user
+        .projects()
+        .find(|p| p.name == *name)
+        .map(success)
+        .ok_or(AppError::RecordNotFound)
 }
 
 /// Get a project by id
 ///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be a member of the project.
 #[utoipa::path(
     get,
     context_path = "/projects",
@@ -113,13 +164,25 @@ async fn find_project(name: web::Path<String>, pool: web::Data<DbPool>) -> Resul
         (status = OK, body = Project),
         (status = BAD_REQUEST, description = "Incorrect data format"),
         (status = NOT_FOUND, description = "Project is not found by provided id"),
+        (status = FORBIDDEN, description = "Authorized user is not a member of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
     )
 )]
-async fn get_project(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<impl Responder> {
+async fn get_project(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_project(id, ProjectPermission::View)?;
+
     web::block(move || {
         let mut conn = pool.get()?;
 
-        projects::find_project(&mut conn, projects::ProjectKey::ID(id.to_owned()))
+        projects::find_project(&mut conn, projects::ProjectKey::ID(id))
     })
     .await?
     .map(success)
@@ -144,13 +207,13 @@ async fn get_project(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<imp
         ("http" = [])
     )
 )]
-async fn get_user_projects(req: HttpRequest, pool: web::Data<DbPool>) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
+async fn get_user_projects(auth_user: AuthenticatedUser, pool: web::Data<DbPool>) -> Result<impl Responder> {
+    let user_id = auth_user.id();
 
     web::block(move || {
         let mut conn = pool.get()?;
 
-        projects::get_user_projects(&mut conn, &token)
+        projects::get_user_projects(&mut conn, user_id)
     })
     .await?
     .map(success)
@@ -160,6 +223,11 @@ async fn get_user_projects(req: HttpRequest, pool: web::Data<DbPool>) -> Result<
 ///
 /// A User Bearer access token should be provided to create a record. 
 /// The access token provided must be associated with a user account.
+///
+/// The authenticated user must be able to edit the project. If the project is
+/// re-pointed to a repository already linked to other projects, the user must
+/// be an editor of one of them, and an unlinked repository must have been created
+/// by the user or the user must be able to push to it.
 #[utoipa::path(
     patch,
     context_path = "/projects",
@@ -173,6 +241,7 @@ async fn get_user_projects(req: HttpRequest, pool: web::Data<DbPool>) -> Result<
         (status = OK, body = Project),
         (status = BAD_REQUEST, description = "Incorrect data format"),
         (status = NOT_FOUND, description = "Either User is not found by provided token or project record to update."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have required permission."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
     ),
     security(
@@ -183,13 +252,22 @@ async fn update_project(
     id: web::Path<Uuid>,
     project: web::Json<InputProject>,
     pool: web::Data<DbPool>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
+    let id = id.into_inner();
+    let current = auth_user.require_project(id, ProjectPermission::Edit)?;
+
+    if let Some(repo_id) = project.repo_id.filter(|repo_id| current.repo_id != Some(*repo_id)) {
+        authorize_repo_link(&pool, &providers, &auth_user, repo_id).await?;
+    }
+
     web::block(move || {
         let mut conn = pool.get()?;
 
         projects::update_project(
             &mut conn,
-            id.into_inner(),
+            id,
             UpdateProject {
                 name: &project.name,
                 repo_id: project.repo_id,
@@ -199,3 +277,232 @@ async fn update_project(
     .await?
     .map(success)
 }
+
+// Checks the user could link the repository to a project. Repositories created
+// before their creators were recorded are checked with the provider.
+async fn authorize_repo_link(
+    pool: &web::Data<DbPool>,
+    providers: &VcsProviders,
+    auth_user: &AuthenticatedUser,
+    repo_id: Uuid,
+) -> Result<()> {
+    let pool = pool.clone();
+    let (linked, repo) = web::block(move || {
+        let mut conn = pool.get()?;
+        let repo = repositories::find_repo(&mut conn, RepositoryKey::ID(repo_id))?;
+
+        Ok::<_, AppError>((projects::get_repo_projects(&mut conn, repo_id)?, repo))
+    })
+    .await??;
+
+    if !linked.is_empty() {
+        return match linked.iter().any(|p| auth_user.can(p.id, ProjectPermission::Edit)) {
+            true => Ok(()),
+            false => Err(AppError::PermissionError),
+        };
+    }
+
+    match repo.created_by {
+        Some(user_id) if user_id == auth_user.id() => Ok(()),
+        None if repo.provider == Provider::GitHub => {
+            let github_repo = providers
+                .github
+                .get_repo(auth_user.github_token()?, &repo.owner, &repo.name)
+                .await?;
+
+            match github_repo.and_then(|r| r.permissions).is_some_and(|p| p.push) {
+                true => Ok(()),
+                false => Err(AppError::PermissionError),
+            }
+        }
+        _ => Err(AppError::PermissionError),
+    }
+}
+
+/// Get project members
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be a member of the project.
+#[utoipa::path(
+    get,
+    context_path = "/projects",
+    path = "/{id}/members",
+    tag = "Projects",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+    ),
+    responses(
+        (status = OK, body = Vec<ProjectMember>),
+        (status = FORBIDDEN, description = "Authorized user is not a member of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_project_members(
+    id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_project(id, ProjectPermission::View)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        projects::get_project_members(&mut conn, id)
+    })
+    .await?
+    .map(success)
+}
+
+/// Add a project member
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project.
+#[utoipa::path(
+    post,
+    context_path = "/projects",
+    path = "/{id}/members",
+    tag = "Projects",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+    ),
+    request_body(content = InputProjectMember, description = "User id and role of the new member", content_type = "application/json"),
+    responses(
+        (status = OK),
+        (status = BAD_REQUEST, description = "User is already a member of the project."),
+        (status = NOT_FOUND, description = "User is not found by provided id."),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn add_project_member(
+    id: web::Path<Uuid>,
+    input: web::Json<InputProjectMember>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let id = id.into_inner();
+    auth_user.require_project(id, ProjectPermission::Manage)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        projects::add_project_member(
+            &mut conn,
+            NewUserProject {
+                user_id: input.user_id,
+                project_id: id,
+                is_active: true,
+                role: input.role,
+            },
+        )
+    })
+    .await??;
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Change a project member role
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project.
+///
+/// The last owner of a project can't be demoted.
+#[utoipa::path(
+    patch,
+    context_path = "/projects",
+    path = "/{id}/members/{user_id}",
+    tag = "Projects",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+        ("user_id" = Uuid, Path, description = "Member's user id"),
+    ),
+    request_body(content = InputMemberRole, description = "New role of the member", content_type = "application/json"),
+    responses(
+        (status = OK),
+        (status = NOT_FOUND, description = "User is not a member of the project."),
+        (status = CONFLICT, description = "The member is the last owner of the project."),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn update_project_member(
+    path: web::Path<(Uuid, Uuid)>,
+    input: web::Json<InputMemberRole>,
+    pool: web::Data<DbPool>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (id, user_id) = path.into_inner();
+    let design_id = auth_user.require_project(id, ProjectPermission::Manage)?.design_id;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        projects::update_member_role(&mut conn, id, user_id, input.role)
+    })
+    .await??;
+    hub.membership_changed(design_id, user_id);
+
+    Ok(HttpResponse::Ok())
+}
+
+/// Remove a project member
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be an owner of the project
+/// or the member to remove, to leave the project.
+///
+/// The last owner of a project can't be removed.
+#[utoipa::path(
+    delete,
+    context_path = "/projects",
+    path = "/{id}/members/{user_id}",
+    tag = "Projects",
+    params(
+        ("id" = Uuid, Path, description = "Project record id in database"),
+        ("user_id" = Uuid, Path, description = "Member's user id"),
+    ),
+    responses(
+        (status = OK),
+        (status = NOT_FOUND, description = "User is not a member of the project."),
+        (status = CONFLICT, description = "The member is the last owner of the project."),
+        (status = FORBIDDEN, description = "Authorized user is not an owner of the project."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn remove_project_member(
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    hub: web::Data<DesignHub>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (id, user_id) = path.into_inner();
+    let permission = match user_id == auth_user.id() {
+        true => ProjectPermission::View,
+        false => ProjectPermission::Manage,
+    };
+    let design_id = auth_user.require_project(id, permission)?.design_id;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        projects::remove_project_member(&mut conn, id, user_id)
+    })
+    .await??;
+    hub.membership_changed(design_id, user_id);
+
+    Ok(HttpResponse::Ok())
+}
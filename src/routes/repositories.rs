@@ -1,21 +1,40 @@
 use crate::{
     errors::AppError,
     models::{
-        repositories::{self, NewRepository, RepositoryKey, RepositoryOwner, UpdateRepository},
+        designs::{self, Design, RevisionRetention},
+        projects::{self, Project},
+        pull_requests::{self, DesignPullRequest, NewDesignPullRequest, PullRequestState},
+        repositories::{
+            self, DesignLayout, DesignSettings, NewRepository, Provider, Repository, RepositoryKey,
+            RepositoryOwner, UpdateRepository,
+        },
+        users::User,
+        webhooks,
         Result,
     },
-    routes::success,
-    services::github::GitHubAPI,
+    routes::{
+        auth::{AuthenticatedUser, ProjectPermission},
+        designs::collab,
+        success,
+        webhooks::process_delivery,
+    },
+    services::{
+        collab::DesignHub,
+        design_files,
+        merge::{self, MergeConflict},
+        validation::{self, Violation},
+        vcs::{CommittedFile, RepoFile, VcsProviders},
+    },
     DbPool,
 };
-use actix_web::{web, HttpRequest, HttpResponse, Responder};
-use base64::{engine::general_purpose, Engine as _};
-use utoipa::ToSchema;
+use actix_web::{web, Responder};
+use diesel::Connection;
+use serde::{Deserialize, Deserializer};
+use utoipa::{IntoParams, ToSchema};
 use uuid::Uuid;
 
-use super::parse_auth_token;
-
-const DESIGN_FILE_NAME: &str = "design.json";
+/// Path of the design file of new repositories, the default of the repository settings.
+pub(crate) const DEFAULT_DESIGN_PATH: &str = "design.json";
 
 #[derive(Deserialize, ToSchema, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -23,7 +42,11 @@ pub struct InputRepository {
     pub name: String,
     pub owner: String,
     pub is_organization: bool,
+    /// Parameters of the repository for the API of the provider.
     pub params: serde_json::Value,
+    /// Provider to create the repository on, GitHub when omitted.
+    #[serde(default)]
+    pub provider: Provider,
 }
 
 #[derive(Deserialize, ToSchema, Debug)]
@@ -31,13 +54,119 @@ pub struct InputRepository {
 pub struct SaveRepoDesign {
     pub message: String,
     pub content: serde_json::Value,
+    /// Branch to save the design to through a pull request, a valid git branch name.
+    pub branch: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedRepoDesign {
+    /// Blob SHA of the saved design file.
+    sha: Option<String>,
+    /// Pull request of the branch, when the design is saved to a branch.
+    pull_request: Option<DesignPullRequest>,
+    /// Design which has been saved instead, merged with the changes of the design file
+    /// when it has changed since the last save or pull.
+    merged_design: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportRepository {
+    pub owner: String,
+    pub name: String,
+    /// Name of a project to create for the repository. The design of the project
+    /// is seeded from the design file of the repository.
+    pub project_name: Option<String>,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedRepository {
+    repository: Repository,
+    project: Option<Project>,
+}
+
+/// Design settings to change, the omitted ones are kept.
+#[derive(Deserialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct UpdateRepositorySettings {
+    path: Option<String>,
+    /// Branch of the design file, a valid git branch name, `null` to use the default branch of the repository.
+    #[serde(default, deserialize_with = "present")]
+    #[schema(value_type = Option<String>)]
+    branch: Option<Option<String>>,
+    pretty: Option<bool>,
+    trailing_newline: Option<bool>,
+    layout: Option<DesignLayout>,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeleteRepoQuery {
+    /// Archive the repository on the provider before its record is deleted.
+    archive: Option<bool>,
+}
+
+#[derive(Deserialize, IntoParams, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PullDesignQuery {
+    /// Replace the design with the file from GitHub in case of a conflict.
+    overwrite: Option<bool>,
+}
+
+#[derive(Serialize, ToSchema, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum PullStatus {
+    /// The design file hasn't changed since the last save or pull.
+    UpToDate,
+    /// Only the design file has changed, the design is updated with it.
+    FastForward,
+    /// Both the design file and the design have changed.
+    Conflict,
+}
+
+#[derive(Serialize, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PulledDesign {
+    pub project_id: Uuid,
+    pub status: PullStatus,
+    /// Whether the design has been replaced with the design file.
+    applied: bool,
+    /// Blob SHA of the design file in the repository.
+    sha: String,
+    design: Design,
 }
 
 pub fn configure(cfg: &mut web::ServiceConfig) {
     cfg.service(
         web::scope("/repos")
-            .service(web::resource("").route(web::post().to(create_repo)))
-            .service(web::resource("/{id}/save_design").route(web::put().to(save_repo_design))),
+            .service(
+                web::resource("")
+                    .route(web::get().to(get_repos))
+                    .route(web::post().to(create_repo)),
+            )
+            .service(web::resource("/import").route(web::post().to(import_repo)))
+            .service(
+                web::resource("/{id}")
+                    .route(web::get().to(get_repo))
+                    .route(web::patch().to(update_repo))
+                    .route(web::delete().to(delete_repo)),
+            )
+            .service(web::resource("/{id}/save_design").route(web::put().to(save_repo_design)))
+            .service(web::resource("/{id}/pull_design").route(web::post().to(pull_repo_design)))
+            .service(
+                web::resource("/{id}/design_settings").route(web::put().to(update_design_settings)),
+            )
+            .service(web::resource("/{id}/pull_requests").route(web::get().to(get_pull_requests)))
+            .service(
+                web::resource("/{id}/webhook_deliveries")
+                    .route(web::get().to(get_webhook_deliveries)),
+            )
+            .service(
+                web::resource("/{id}/webhook_deliveries/{delivery_id}/replay")
+                    .route(web::post().to(replay_webhook_delivery)),
+            ),
     );
 }
 
@@ -46,12 +175,18 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 /// A User Bearer access token should be provided to create a record.
 /// The access token provided must be associated with a user account.
 ///
-/// Unique constraint for repository record consists of (name && owner && is_organization).
+/// Unique constraint for repository record consists of (provider && name && owner && is_organization).
 ///
-/// If during creation request there is no such repository in our database by (name && owner && is_organization)
-/// then server sends request to Github API to create the repository.
+/// If during creation request there is no such repository in our database by (provider && name && owner && is_organization)
+/// then server sends request to the API of the provider, Github or Gitlab, to create the repository.
+/// The user must have signed in with the provider.
+/// Local repositories are bare git repositories the server creates on its disk,
+/// any user can create them and `params` are ignored. Like the other repositories,
+/// one is created per repository record and could be linked to several projects.
+/// The history of their designs is served by the design revisions, not by the git log.
 ///
-/// If the repository is successfully created on Github, it will be created in our database.
+/// If the repository is successfully created on the provider, it will be created in our database.
+/// If the provider rejects the request, its status and message are returned.
 ///
 /// IMPORTANT:
 /// - It is possible that a repository exists in our database but cannot be created on Github.
@@ -71,6 +206,7 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         (status = OK, body = Repository),
         (status = BAD_REQUEST, description = "Unique constaint violation."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token."),
+        (status = TOO_MANY_REQUESTS, description = "Github API rate limit is exceeded. Retry after the time from Retry-After header."),
         (status = 502, body = String, content_type = "text/plain", description = "Github API request failed.")
     ),
     security(
@@ -80,39 +216,17 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
 async fn create_repo(
     input: web::Json<InputRepository>,
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
     let input: InputRepository = input.into_inner();
+    let token = auth_user.vcs_token(input.provider)?.to_owned();
+    let user_id = auth_user.id();
 
-    let api_response = create_github_repo(&token, input.to_owned(), pool.clone()).await?;
-
-    if !api_response.status().is_success() && !api_response.status().is_informational() {
-        let response = HttpResponse::BadGateway().content_type("text/plain").body(
-            api_response
-                .text()
-                .await
-                .unwrap_or_else(|e| AppError::GithubAPIError(e.to_string()).to_string()),
-        );
-
-        return Ok(response);
-    }
-
-    let json: serde_json::Value = api_response.json::<serde_json::Value>().await?;
+    let html_url = create_remote_repo(&providers, &token, input.to_owned(), pool.clone()).await?;
 
     web::block(move || {
         let mut conn = pool.get()?;
-        let mut html_url: String = format!(
-            "https://github.com/{ow}/{nm}",
-            ow = input.owner,
-            nm = input.name
-        );
-
-        if let Some(json_url) = json.get("html_url").cloned() {
-            if let Ok(url) = serde_json::from_value(json_url) {
-                html_url = url;
-            }
-        }
 
         let new_repo = NewRepository {
             name: &input.name,
@@ -120,6 +234,8 @@ async fn create_repo(
             is_organization: input.is_organization,
             design_file_sha: None,
             html_url: &html_url,
+            provider: input.provider,
+            created_by: Some(user_id),
         };
 
         repositories::create_repo(&mut conn, new_repo)
@@ -128,40 +244,410 @@ async fn create_repo(
     .map(success)
 }
 
-async fn create_github_repo(
+/// Import a repository
+///
+/// A User Bearer access token should be provided.
+/// The access token provided must be associated with a user account.
+///
+/// Links an existing GitHub repository the authenticated user can push to.
+/// Only Github repositories could be imported.
+/// The blob SHA of the design file of the repository is stored, so the design
+/// could be saved to the repository and pulled from it.
+///
+/// When `projectName` is provided, a project of the repository is created
+/// and its design is seeded from the design file. Without a project the repository
+/// is listed for the user, who could link it to a project later.
+#[utoipa::path(
+    post,
+    context_path = "/repos",
+    path = "/import",
+    tag = "Repositories",
+    request_body(content = ImportRepository, content_type = "application/json"),
+    responses(
+        (status = OK, body = ImportedRepository),
+        (status = BAD_REQUEST, description = "Repository is not found on Github or has been imported already."),
+        (status = FORBIDDEN, description = "Authorized user can't push to the repository."),
+        (status = UNPROCESSABLE_ENTITY, description = "Design file doesn't match the design model. Body contains the list of violations."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token."),
+        (status = 502, body = String, content_type = "text/plain", description = "Github API request failed.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn import_repo(
+    input: web::Json<ImportRepository>,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let token = auth_user.github_token()?.to_owned();
+    let user_id = auth_user.id();
+    let input = input.into_inner();
+    let api = &providers.github;
+
+    let github_repo = api
+        .get_repo(&token, &input.owner, &input.name)
+        .await?
+        .ok_or_else(|| {
+            AppError::InvalidInput(format!("Repository {}/{} is not found", input.owner, input.name))
+        })?;
+
+    if !github_repo.permissions.is_some_and(|p| p.push) {
+        return Err(AppError::PermissionError);
+    }
+
+    let repo_owner = RepositoryOwner {
+        name: github_repo.name,
+        owner: github_repo.owner.login,
+        is_organization: github_repo.owner.kind == "Organization",
+    };
+    let file = api
+        .get_file_content(
+            &token,
+            RepositoryOwner {
+                name: repo_owner.name.clone(),
+                owner: repo_owner.owner.clone(),
+                is_organization: repo_owner.is_organization,
+            },
+            DEFAULT_DESIGN_PATH,
+            None,
+        )
+        .await?;
+
+    // A design file which doesn't match the design model can't seed a project.
+    let seed = match (&input.project_name, &file) {
+        (Some(_), Some(file)) => Some(parse_design_file(file)?),
+        _ => None,
+    };
+    let html_url = github_repo.html_url;
+    let retention = **retention;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        conn.transaction(|conn| {
+            let repository = repositories::create_repo(
+                conn,
+                NewRepository {
+                    name: &repo_owner.name,
+                    owner: &repo_owner.owner,
+                    is_organization: repo_owner.is_organization,
+                    design_file_sha: file.as_ref().map(|f| f.sha.as_str()),
+                    html_url: &html_url,
+                    provider: Provider::GitHub,
+                    created_by: Some(user_id),
+                },
+            )?;
+
+            let project = match input.project_name {
+                Some(project_name) => {
+                    let project = projects::create_project(conn, &project_name, Some(repository.id), user_id)?;
+                    if let Some(seed) = seed {
+                        designs::update_design(conn, project.design_id, seed, Some(user_id), None, retention)?;
+                    }
+
+                    Some(project)
+                }
+                None => None,
+            };
+
+            Ok(ImportedRepository { repository, project })
+        })
+    })
+    .await?
+    .map(success)
+}
+
+/// Get repositories
+///
+/// A User Bearer access token should be provided.
+///
+/// Returns the repositories linked to the projects the authenticated user
+/// is a member of, and the ones the user has created or imported which are not linked
+/// to any project, from the newest one.
+#[utoipa::path(
+    get,
+    context_path = "/repos",
+    path = "",
+    tag = "Repositories",
+    responses(
+        (status = OK, body = Vec<Repository>),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_repos(pool: web::Data<DbPool>, auth_user: AuthenticatedUser) -> Result<impl Responder> {
+    let user_id = auth_user.id();
+    let repo_ids: Vec<Uuid> = auth_user.projects().filter_map(|p| p.repo_id).collect();
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        repositories::get_user_repos(&mut conn, user_id, repo_ids)
+    })
+    .await?
+    .map(success)
+}
+
+/// Get a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to a project linked to the repository,
+/// or have created or imported it while no project is linked to it.
+#[utoipa::path(
+    get,
+    context_path = "/repos",
+    path = "/{id}",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+    ),
+    responses(
+        (status = OK, body = Repository),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_repo(
+    repo_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    find_permitted_repo(&pool, &auth_user, repo_id.into_inner(), ProjectPermission::View)
+        .await
+        .map(success)
+}
+
+/// Update settings of a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must own a project linked to the repository,
+/// or have created or imported it while no project is linked to it.
+///
+/// Changes the design settings which are present in the body, the same way
+/// as `PUT /repos/{id}/design_settings` does, the omitted ones are kept.
+#[utoipa::path(
+    patch,
+    context_path = "/repos",
+    path = "/{id}",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+    ),
+    request_body(content = UpdateRepositorySettings, content_type = "application/json"),
+    responses(
+        (status = OK, body = Repository),
+        (status = BAD_REQUEST, description = "Path or branch is not valid, or the layout is not supported by the provider."),
+        (status = FORBIDDEN, description = "Authorized user can't manage the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn update_repo(
+    repo_id: web::Path<Uuid>,
+    input: web::Json<UpdateRepositorySettings>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo = find_permitted_repo(&pool, &auth_user, repo_id.into_inner(), ProjectPermission::Manage).await?;
+
+    let input = input.into_inner();
+    let current = repo.design_settings();
+    let settings = DesignSettings {
+        path: input.path.unwrap_or(current.path),
+        branch: input.branch.unwrap_or(current.branch),
+        pretty: input.pretty.unwrap_or(current.pretty),
+        trailing_newline: input.trailing_newline.unwrap_or(current.trailing_newline),
+        layout: input.layout.unwrap_or(current.layout),
+    };
+
+    save_design_settings(pool, &repo, settings).await.map(success)
+}
+
+/// Delete a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must own a project linked to the repository,
+/// or have created or imported it while no project is linked to it.
+///
+/// The repository is unlinked from its projects and its record is deleted
+/// with its design pull requests, the remote repository is kept.
+/// With `archive=true` the remote repository is archived on the provider first,
+/// the record is kept if the provider rejects it. Local repositories can't be archived.
+#[utoipa::path(
+    delete,
+    context_path = "/repos",
+    path = "/{id}",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+        DeleteRepoQuery
+    ),
+    responses(
+        (status = OK, body = Repository),
+        (status = BAD_REQUEST, description = "The repository can't be archived on its provider."),
+        (status = FORBIDDEN, description = "Authorized user can't manage the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token."),
+        (status = 502, body = String, content_type = "text/plain", description = "Provider API request failed.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn delete_repo(
+    repo_id: web::Path<Uuid>,
+    query: web::Query<DeleteRepoQuery>,
+    pool: web::Data<DbPool>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo = find_permitted_repo(&pool, &auth_user, repo_id.into_inner(), ProjectPermission::Manage).await?;
+
+    // The repository is archived with the user's own token, not the GitHub App,
+    // so the user must be allowed to archive it on the provider.
+    if query.archive.unwrap_or(false) {
+        providers
+            .get(repo.provider)
+            .archive_repo(auth_user.vcs_token(repo.provider)?, repo.repo_owner())
+            .await?;
+    }
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        repositories::delete_repo(&mut conn, repo.id)
+    })
+    .await?
+    .map(success)
+}
+
+// Repository the user has the permission for through a linked project, or has created.
+// Once a project is linked to the repository, only the project members have access to it.
+async fn find_permitted_repo(
+    pool: &web::Data<DbPool>,
+    auth_user: &AuthenticatedUser,
+    repo_id: Uuid,
+    permission: ProjectPermission,
+) -> Result<Repository> {
+    let linked = auth_user.require_repo(repo_id, permission).is_ok();
+    let user_id = auth_user.id();
+    let pool = pool.clone();
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        match repositories::find_repo(&mut conn, RepositoryKey::ID(repo_id)) {
+            Ok(repo) if linked => Ok(repo),
+            Ok(repo) if repo.created_by == Some(user_id) && projects::get_repo_projects(&mut conn, repo.id)?.is_empty() => {
+                Ok(repo)
+            }
+            Err(e) if linked => Err(e),
+            // Other users' repositories are indistinguishable from missing ones.
+            _ => Err(AppError::PermissionError),
+        }
+    })
+    .await?
+}
+
+// Validates the design settings and saves them.
+async fn save_design_settings(
+    pool: web::Data<DbPool>,
+    repo: &Repository,
+    mut settings: DesignSettings,
+) -> Result<Repository> {
+    settings.path = settings.path.trim_start_matches('/').to_owned();
+    let invalid_segment = |segment: &str| segment.is_empty() || segment == "." || segment == "..";
+    if settings.path.split('/').any(invalid_segment) {
+        return Err(AppError::InvalidInput(format!(
+            "{} is not a valid file path",
+            settings.path
+        )));
+    }
+    if let Some(branch) = settings.branch.as_deref() {
+        validate_branch(branch)?;
+    }
+    if settings.layout == DesignLayout::Split && repo.provider != Provider::GitHub {
+        return Err(AppError::InvalidInput(
+            "Split layout is supported for Github repositories only".to_string(),
+        ));
+    }
+
+    let repo_id = repo.id;
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        repositories::update_design_settings(&mut conn, repo_id, settings)
+    })
+    .await?
+}
+
+// Branch names `git check-ref-format --branch` accepts, without `#` and `%`
+// which are not kept as they are in the API URLs of the providers.
+fn validate_branch(branch: &str) -> Result<()> {
+    let forbidden = |c: char| c.is_ascii_control() || " ~^:?*[\\#%".contains(c);
+    let invalid_component =
+        |component: &str| component.is_empty() || component.starts_with('.') || component.ends_with(".lock");
+
+    if branch == "@"
+        || branch.starts_with('-')
+        || branch.ends_with('.')
+        || branch.contains("..")
+        || branch.contains("@{")
+        || branch.chars().any(forbidden)
+        || branch.split('/').any(invalid_component)
+    {
+        return Err(AppError::InvalidInput(format!("{branch} is not a valid branch name")));
+    }
+
+    Ok(())
+}
+
+// A `null` field is `Some(None)`, while an omitted one is `None` by default.
+fn present<'de, T, D>(deserializer: D) -> std::result::Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+async fn create_remote_repo(
+    providers: &VcsProviders,
     token: &str,
     input: InputRepository,
     pool: web::Data<DbPool>,
-) -> Result<reqwest::Response> {
-    let block_input = input.to_owned();
+) -> Result<String> {
+    let repo_owner = repositories::RepositoryOwner {
+        name: input.name,
+        owner: input.owner,
+        is_organization: input.is_organization,
+    };
+    let block_repo_owner = repo_owner.clone();
 
     web::block(move || {
         let mut conn = pool.get()?;
-        let repo_owner = repositories::RepositoryOwner {
-            name: block_input.name,
-            owner: block_input.owner,
-            is_organization: input.is_organization,
-        };
 
-        if repositories::is_repo_exist(&mut conn, repo_owner)? {
+        if repositories::is_repo_exist(&mut conn, input.provider, block_repo_owner)? {
             return Err(AppError::RecordAlreadyExists);
         }
 
         Ok(())
     })
-    .await?
-    .map_err(AppError::from)?;
+    .await??;
 
-    let api = GitHubAPI::new()?;
-    let api_response = match input.is_organization {
-        true => {
-            api.create_org_repo(token, &input.owner, input.params.clone())
-                .await
-        }
-        false => api.create_personal_repo(token, input.params.clone()).await,
-    }?;
-
-    Ok(api_response)
+    providers
+        .get(input.provider)
+        .create_repo(token, repo_owner, input.params)
+        .await
 }
 
 /// Save design to repository
@@ -169,6 +655,31 @@ async fn create_github_repo(
 /// A User Bearer access token should be provided to create a record.
 /// The access token provided must be associated with a user account.
 ///
+/// The authenticated user must be able to edit a project linked to the repository.
+/// The design must match the design model.
+///
+/// The design file is written to the path and branch from the design settings
+/// of the repository, formatted as they set.
+///
+/// Without `branch` the design is committed to the design branch, and the sync state
+/// of the projects of the repository is updated. Projects with the saved design are
+/// in sync with the commit, a failed save is recorded for them as `pushFailed`.
+/// When the design file has changed since the last save or pull, the design is merged
+/// with it against the last saved revision of the project (three-way merge).
+/// Changes of different values are merged and the merged design is saved and applied
+/// to the project, unless its design has changed meanwhile. Changes of the same values
+/// are conflicts, they are returned with their values in the revision, the design and the file.
+/// With `branch` the design is committed to the branch, which is created from
+/// the design branch if missing. An existing branch is only written when its design
+/// file is the one saved to it last, so the branch is either created by saving
+/// or has no other changes of the design file. Then a pull request from the branch
+/// to the design branch is opened, or the open one is updated, with the message as its title.
+/// Design pull requests are tracked until they are closed, saving to a branch
+/// is supported for Github repositories only.
+///
+/// When the GitHub App is installed on the owner of the repository, the design
+/// is committed by the app with the user as a co-author, the user doesn't need
+/// a GitHub token then.
 #[utoipa::path(
     put,
     context_path = "/repos",
@@ -176,8 +687,13 @@ async fn create_github_repo(
     tag = "Repositories",
     request_body(content = SaveRepoDesign, content_type = "application/json"),
     responses(
-        (status = OK),
-        (status = BAD_REQUEST, description = "Repo is not found."),
+        (status = OK, body = SavedRepoDesign),
+        (status = BAD_REQUEST, description = "The branch is not a valid branch name, or is the design branch."),
+        (status = NOT_FOUND, description = "Repo is not found."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to a project of the repository."),
+        (status = CONFLICT, description = "Design file has changed and the design couldn't be merged with it. Body contains the list of conflicts. \
+            Or the branch has other changes of the design file than the saved ones."),
+        (status = UNPROCESSABLE_ENTITY, description = "Design doesn't match the design model. Body contains the list of violations."),
         (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token."),
         (status = 502, body = String, content_type = "text/plain", description = "Github API request failed.")
     ),
@@ -185,58 +701,698 @@ async fn create_github_repo(
         ("http" = [])
     )
 )]
-async fn save_repo_design<'a>(
+#[allow(clippy::too_many_arguments)]
+async fn save_repo_design(
     repo_id: web::Path<Uuid>,
     info: web::Json<SaveRepoDesign>,
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
+    let repo_id = repo_id.into_inner();
+    let project = auth_user.require_repo(repo_id, ProjectPermission::Edit)?.clone();
+    validation::validate_design(&info.content).map_err(AppError::DesignValidation)?;
+    if let Some(branch) = info.branch.as_deref() {
+        validate_branch(branch)?;
+    }
+
     let pool1 = pool.to_owned();
 
     let repo = web::block(move || {
         let mut conn = pool1.get()?;
 
-        repositories::find_repo(&mut conn, RepositoryKey::ID(repo_id.into_inner()))
+        repositories::find_repo(&mut conn, RepositoryKey::ID(repo_id))
     })
-    .await?
-    .map_err(AppError::from)?;
+    .await??;
 
-    let api = GitHubAPI::new()?;
+    let access = providers
+        .repo_access(&repo, &auth_user.user, auth_user.vcs_token(repo.provider))
+        .await?;
     let info: SaveRepoDesign = info.into_inner();
+    let message = access.commit_message(&info.message);
+    let token = access.token;
     let content = info.content;
-    let content_data = serde_json::to_vec(&content).map_err(AppError::from)?;
-    let base64_content = general_purpose::STANDARD.encode(content_data);
+    let repo_owner = repo.repo_owner();
 
-    let file_commit = api
-        .save_file_content(
+    let Some(branch) = info.branch else {
+        let saved = save_to_design_branch(
+            &pool,
+            &providers,
             &token,
-            RepositoryOwner {
-                name: repo.name,
-                owner: repo.owner,
-                is_organization: repo.is_organization,
-            },
-            DESIGN_FILE_NAME,
-            &info.message,
-            &base64_content,
-            repo.design_file_sha.as_deref(),
+            &repo,
+            &project,
+            &content,
+            &message,
+            &auth_user.user,
         )
-        .await?;
+        .await;
 
-    web::block(move || {
+        let (committed, merged_design) = match saved {
+            Ok(committed) => committed,
+            Err(e) => {
+                let error = e.to_string();
+
+                // The error of the save is returned even if it couldn't be recorded.
+                let _ = web::block(move || {
+                    let mut conn = pool.get()?;
+
+                    projects::record_push_failure(&mut conn, repo.id, &content, &error)
+                })
+                .await;
+
+                return Err(e);
+            }
+        };
+        let sha = committed.sha.clone();
+
+        if let Some(merged) = &merged_design {
+            let author = auth_user.id();
+            apply_merged_design(&pool, **retention, &hub, project.design_id, content.clone(), merged.to_owned(), author)
+                .await?;
+        }
+        let saved_design = merged_design.clone().unwrap_or(content);
+
+        web::block(move || {
+            let mut conn = pool.get()?;
+
+            conn.transaction(|conn| {
+                repositories::update_repo(
+                    conn,
+                    repo.id,
+                    UpdateRepository {
+                        design_file_sha: Some(&committed.sha),
+                        ..Default::default()
+                    },
+                )?;
+
+                projects::record_repo_sync(conn, repo.id, &saved_design, true, Some(&committed.commit_sha))
+            })
+        })
+        .await??;
+
+        return Ok(success(SavedRepoDesign {
+            sha: Some(sha),
+            pull_request: None,
+            merged_design,
+        }));
+    };
+
+    let api = providers.github_only(&repo, "Saving to a branch")?;
+
+    // Pull requests are made against the design branch.
+    let base_branch = match &repo.design_branch {
+        Some(design_branch) => design_branch.to_owned(),
+        None => {
+            api.get_repo(&token, &repo_owner.owner, &repo_owner.name)
+                .await?
+                .ok_or_else(|| AppError::InvalidInput("Repository is not found on Github".to_string()))?
+                .default_branch
+        }
+    };
+
+    if branch == base_branch {
+        return Err(AppError::InvalidInput(
+            "Branch of the pull request must not be the design branch".to_string(),
+        ));
+    }
+
+    let created = match api.get_branch_sha(&token, repo_owner.clone(), &branch).await? {
+        Some(_) => false,
+        None => {
+            let base_sha = api
+                .get_branch_sha(&token, repo_owner.clone(), &base_branch)
+                .await?
+                .ok_or_else(|| AppError::GithubAPIError(format!("Branch {base_branch} is not found")))?;
+            api.create_branch(&token, repo_owner.clone(), &branch, &base_sha)
+                .await?;
+
+            true
+        }
+    };
+
+    let branch_file_sha = design_files::get_design_sha(&providers, &token, &repo, &branch).await?;
+
+    // Changes of the design file committed to an existing branch by others are not overwritten.
+    if !created {
+        let (pool1, branch1) = (pool.to_owned(), branch.clone());
+        let saved = web::block(move || {
+            let mut conn = pool1.get()?;
+
+            pull_requests::find_branch_pull_request(&mut conn, repo_id, &branch1)
+        })
+        .await??;
+
+        if saved.is_none_or(|saved| saved.design_file_sha != branch_file_sha) {
+            return Err(AppError::RepoFileConflict(format!(
+                "{} on branch {branch} has changes which are not saved from the design",
+                repo.design_path
+            )));
+        }
+    }
+    let committed = design_files::save_design(
+        &providers,
+        &token,
+        &repo,
+        Some(&branch),
+        &content,
+        &message,
+        branch_file_sha.as_deref(),
+        &auth_user.user,
+    )
+    .await?;
+
+    let pull_request = match api
+        .find_open_pull_request(&token, repo_owner.clone(), &branch)
+        .await?
+    {
+        Some(pull_request) => {
+            api.update_pull_request(&token, repo_owner, pull_request.number, &info.message)
+                .await?
+        }
+        None => {
+            api.create_pull_request(&token, repo_owner, &info.message, &branch, &base_branch)
+                .await?
+        }
+    };
+    let sha = committed.sha;
+    let design_file_sha = sha.clone();
+
+    let pull_request = web::block(move || {
         let mut conn = pool.get()?;
 
-        repositories::update_repo(
+        pull_requests::save_pull_request(
             &mut conn,
-            repo.id,
-            UpdateRepository {
-                design_file_sha: file_commit.content.map(|c| c.sha).as_deref(),
-                ..Default::default()
+            NewDesignPullRequest {
+                repository_id: repo.id,
+                branch: &branch,
+                number: pull_request.number,
+                html_url: &pull_request.html_url,
+                state: PullRequestState::Open,
+                design_file_sha: Some(&design_file_sha),
             },
         )
     })
+    .await??;
+
+    Ok(success(SavedRepoDesign {
+        sha: Some(sha),
+        pull_request: Some(pull_request),
+        merged_design: None,
+    }))
+}
+
+// Saves the design to the design branch. When the design file has changed since
+// the last save or pull, the design is merged with it and the merged design is saved.
+#[allow(clippy::too_many_arguments)]
+async fn save_to_design_branch(
+    pool: &web::Data<DbPool>,
+    providers: &VcsProviders,
+    token: &str,
+    repo: &Repository,
+    project: &Project,
+    design: &serde_json::Value,
+    message: &str,
+    author: &User,
+) -> Result<(CommittedFile, Option<serde_json::Value>)> {
+    let branch = repo.design_branch.as_deref();
+    let sha = repo.design_file_sha.as_deref();
+
+    let conflict = match design_files::save_design(providers, token, repo, branch, design, message, sha, author).await {
+        Err(conflict @ AppError::RepoFileConflict(_)) => conflict,
+        saved => return saved.map(|committed| (committed, None)),
+    };
+    let Some((merged, file_sha)) = merge_design_file(pool, providers, token, repo, project, design).await? else {
+        return Err(conflict);
+    };
+
+    design_files::save_design(providers, token, repo, branch, &merged, message, Some(&file_sha), author)
+        .await
+        .map(|committed| (committed, Some(merged)))
+}
+
+// Design merged with the design file against the last saved revision of the project,
+// with the SHA of the file. `None` when there is no design file or it is not JSON.
+async fn merge_design_file(
+    pool: &web::Data<DbPool>,
+    providers: &VcsProviders,
+    token: &str,
+    repo: &Repository,
+    project: &Project,
+    ours: &serde_json::Value,
+) -> Result<Option<(serde_json::Value, String)>> {
+    let Some(file) = design_files::get_design(providers, token, repo, repo.design_branch.as_deref()).await? else {
+        return Ok(None);
+    };
+    let Ok(theirs) = serde_json::from_slice::<serde_json::Value>(&file.content) else {
+        return Ok(None);
+    };
+
+    let (design_id, pushed_revision) = (project.design_id, project.pushed_revision);
+    let pool = pool.to_owned();
+
+    // The revision could have been pruned since it was saved.
+    let base = web::block(move || {
+        let mut conn = pool.get()?;
+
+        match pushed_revision.map(|revision| designs::get_design_revision(&mut conn, design_id, revision)) {
+            Some(Ok(revision)) => Ok(Some(revision.data)),
+            Some(Err(AppError::RecordNotFound)) | None => Ok(None),
+            Some(Err(e)) => Err(e),
+        }
+    })
+    .await??;
+
+    let merged = match base {
+        Some(base) => merge::merge(&base, ours, &theirs),
+        None if *ours == theirs => Ok(theirs),
+        // Without the base the designs could only be taken as a whole.
+        None => Err(vec![MergeConflict {
+            pointer: String::new(),
+            base: None,
+            ours: Some(ours.to_owned()),
+            theirs: Some(theirs),
+        }]),
+    }
+    .map_err(AppError::DesignConflict)?;
+    validation::validate_design(&merged).map_err(AppError::DesignValidation)?;
+
+    Ok(Some((merged, file.sha)))
+}
+
+// Replaces the saved design of the project with the merged one,
+// unless the design has changed since it has been saved.
+async fn apply_merged_design(
+    pool: &web::Data<DbPool>,
+    retention: RevisionRetention,
+    hub: &DesignHub,
+    design_id: Uuid,
+    saved: serde_json::Value,
+    merged: serde_json::Value,
+    author: Uuid,
+) -> Result<()> {
+    let room = hub.room(design_id);
+    let _writer = room.write().await;
+    let pool = pool.to_owned();
+
+    let design = web::block(move || {
+        let mut conn = pool.get()?;
+
+        let design = designs::get_design(&mut conn, design_id)?;
+        if design.data != saved {
+            return Ok(None);
+        }
+
+        designs::update_design(&mut conn, design_id, merged, Some(author), Some(&[design.revision]), retention).map(Some)
+    })
+    .await??;
+
+    if let Some(design) = design {
+        collab::publish(&room, &design, designs::replace_patch(&design.data), Some(author), None);
+    }
+
+    Ok(())
+}
+
+/// Update design file settings of a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must own a project linked to the repository,
+/// or have created or imported it while no project is linked to it.
+///
+/// Sets the path and the branch of the design file, and how it is formatted.
+/// With the split layout the design is saved as a directory of files committed
+/// at once, and the files are joined back into the design when it is pulled.
+/// Saving, pulling and push webhooks use these settings.
+/// The split layout is supported for Github repositories only.
+/// Moving the design file to another path or branch resets its tracked SHA,
+/// so the design should be pulled or saved with the new settings. Only the SHA
+/// of the current location is tracked, moving the design file back to a previous
+/// path or branch doesn't restore the SHA it had there.
+#[utoipa::path(
+    put,
+    context_path = "/repos",
+    path = "/{id}/design_settings",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+    ),
+    request_body(content = DesignSettings, content_type = "application/json"),
+    responses(
+        (status = OK, body = Repository),
+        (status = BAD_REQUEST, description = "Path or branch is not valid, or the layout is not supported by the provider."),
+        (status = FORBIDDEN, description = "Authorized user can't manage the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn update_design_settings(
+    repo_id: web::Path<Uuid>,
+    settings: web::Json<DesignSettings>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo = find_permitted_repo(&pool, &auth_user, repo_id.into_inner(), ProjectPermission::Manage).await?;
+
+    save_design_settings(pool, &repo, settings.into_inner()).await.map(success)
+}
+
+/// Get open design pull requests of a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to a project linked to the repository.
+///
+/// Pull requests are returned from the newest one.
+#[utoipa::path(
+    get,
+    context_path = "/repos",
+    path = "/{id}/pull_requests",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+    ),
+    responses(
+        (status = OK, body = Vec<DesignPullRequest>),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to a project of the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_pull_requests(
+    repo_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo_id = repo_id.into_inner();
+    auth_user.require_repo(repo_id, ProjectPermission::View)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        pull_requests::get_open_pull_requests(&mut conn, repo_id)
+    })
     .await?
-    .map_err(AppError::from)?;
+    .map(success)
+}
+
+/// Pull design from repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be able to edit a project linked to the repository.
+///
+/// Fetches the design file from the path and branch set for the repository
+/// and compares its blob SHA with the SHA of the last saved or pulled file.
+/// The file is pulled into the designs of all projects linked to the repository.
+/// A design is updated when only the file has changed (fast-forward). When the design
+/// has changed as well it is a conflict, the design is kept unless `overwrite` is set
+/// and the user can edit its project.
+/// The sync state of the projects of the repository is updated with the pulled file.
+///
+/// The response contains the designs of the projects the user has access to.
+#[utoipa::path(
+    post,
+    context_path = "/repos",
+    path = "/{id}/pull_design",
+    tag = "Repositories",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+        PullDesignQuery,
+    ),
+    responses(
+        (status = OK, body = Vec<PulledDesign>),
+        (status = BAD_REQUEST, description = "There is no design file in the repository."),
+        (status = NOT_FOUND, description = "Repo is not found."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to a project of the repository."),
+        (status = UNPROCESSABLE_ENTITY, description = "Design file doesn't match the design model. Body contains the list of violations."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token."),
+        (status = 502, body = String, content_type = "text/plain", description = "Github API request failed.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn pull_repo_design(
+    repo_id: web::Path<Uuid>,
+    query: web::Query<PullDesignQuery>,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo_id = repo_id.into_inner();
+    auth_user.require_repo(repo_id, ProjectPermission::Edit)?;
+    let author = auth_user.id();
+    let pool1 = pool.to_owned();
+
+    let repo = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        repositories::find_repo(&mut conn, RepositoryKey::ID(repo_id))
+    })
+    .await??;
+
+    let access = providers
+        .repo_access(&repo, &auth_user.user, auth_user.vcs_token(repo.provider))
+        .await?;
+    let file = design_files::get_design(&providers, &access.token, &repo, repo.design_branch.as_deref())
+        .await?
+        .ok_or_else(|| AppError::InvalidInput(format!("{} is not found in the repository", repo.design_path)))?;
+
+    let overwrite = |project: &Project| {
+        query.overwrite.unwrap_or(false) && auth_user.can(project.id, ProjectPermission::Edit)
+    };
+    let pulled = pull_design_file(&pool, **retention, &hub, &repo, Some(author), None, file, overwrite).await?;
+
+    Ok(success(
+        pulled
+            .into_iter()
+            .filter(|pulled| auth_user.can(pulled.project_id, ProjectPermission::View))
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// Pulls the design file into the designs of the projects of the repository.
+///
+/// A design is updated with the file when only the file has changed since the last
+/// save or pull. In case of a conflict the design is replaced only when `overwrite`
+/// allows it for the project. The pulled file is recorded as the last pulled one
+/// unless a conflicting design has been kept.
+///
+/// `author` is the user pulling the file, `None` for the files pushed to the repository.
+/// `commit_sha` is the commit of the file, when it is known.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn pull_design_file<F>(
+    pool: &web::Data<DbPool>,
+    retention: RevisionRetention,
+    hub: &DesignHub,
+    repo: &Repository,
+    author: Option<Uuid>,
+    commit_sha: Option<String>,
+    file: RepoFile,
+    overwrite: F,
+) -> Result<Vec<PulledDesign>>
+where
+    F: Fn(&Project) -> bool,
+{
+    let content = parse_design_file(&file)?;
+    let repo_id = repo.id;
+    let pool1 = pool.to_owned();
+
+    let projects = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        projects::get_repo_projects(&mut conn, repo_id)
+    })
+    .await??;
+
+    // The designs are held from the comparison to the update.
+    let rooms: Vec<_> = projects.iter().map(|project| hub.room(project.design_id)).collect();
+    let mut writers = Vec::with_capacity(rooms.len());
+    for room in &rooms {
+        writers.push(room.write().await);
+    }
+
+    let design_ids: Vec<_> = projects.iter().map(|project| project.design_id).collect();
+    let pool1 = pool.to_owned();
+    let designs = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        design_ids
+            .into_iter()
+            .map(|design_id| designs::get_design(&mut conn, design_id))
+            .collect::<Result<Vec<_>>>()
+    })
+    .await??;
+
+    let saved_sha = repo.design_file_sha.as_deref();
+    let mut pulled = Vec::with_capacity(projects.len());
+    for (project, design) in projects.iter().zip(designs) {
+        let status = if saved_sha == Some(file.sha.as_str()) || design.data == content {
+            PullStatus::UpToDate
+        } else if saved_sha == Some(design_files::design_sha(repo, &design.data)?.as_str()) {
+            PullStatus::FastForward
+        } else {
+            PullStatus::Conflict
+        };
+        let applied = match status {
+            PullStatus::UpToDate => false,
+            PullStatus::FastForward => true,
+            PullStatus::Conflict => overwrite(project),
+        };
+
+        pulled.push(PulledDesign {
+            project_id: project.id,
+            status,
+            applied,
+            sha: file.sha.clone(),
+            design,
+        });
+    }
+
+    // A conflicting file stays unpulled, so saving a design over it fails.
+    let file_pulled = pulled.iter().all(|pulled| pulled.status != PullStatus::Conflict || pulled.applied);
+    let file_moved = saved_sha != Some(file.sha.as_str());
+    let pool = pool.to_owned();
+
+    let pulled = web::block(move || {
+        let mut conn = pool.get()?;
+
+        conn.transaction::<_, AppError, _>(|conn| {
+            if file_pulled {
+                repositories::update_repo(
+                    conn,
+                    repo_id,
+                    UpdateRepository {
+                        design_file_sha: Some(&file.sha),
+                        ..Default::default()
+                    },
+                )?;
+            }
+
+            for pulled in pulled.iter_mut().filter(|pulled| pulled.applied) {
+                pulled.design =
+                    designs::update_design(conn, pulled.design.id, content.clone(), author, None, retention)?;
+            }
+            projects::record_repo_sync(conn, repo_id, &content, file_moved, commit_sha.as_deref())?;
+
+            Ok(pulled)
+        })
+    })
+    .await??;
+
+    for (room, pulled) in rooms.iter().zip(&pulled) {
+        if pulled.applied {
+            collab::publish(room, &pulled.design, designs::replace_patch(&pulled.design.data), author, None);
+        }
+    }
+
+    Ok(pulled)
+}
+
+/// Get webhook deliveries of a repository
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must have access to a project linked to the repository.
+///
+/// Deliveries are returned from the newest one.
+#[utoipa::path(
+    get,
+    context_path = "/repos",
+    path = "/{id}/webhook_deliveries",
+    tag = "Webhooks",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+    ),
+    responses(
+        (status = OK, body = Vec<WebhookDelivery>),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to a project of the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn get_webhook_deliveries(
+    repo_id: web::Path<Uuid>,
+    pool: web::Data<DbPool>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let repo_id = repo_id.into_inner();
+    auth_user.require_repo(repo_id, ProjectPermission::View)?;
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        webhooks::get_repo_deliveries(&mut conn, repo_id)
+    })
+    .await?
+    .map(success)
+}
+
+/// Replay a webhook delivery
+///
+/// A User Bearer access token should be provided.
+/// The authenticated user must be able to edit a project linked to the repository.
+///
+/// Processes the recorded delivery again, e.g. after a failure or a conflict has been resolved.
+#[utoipa::path(
+    post,
+    context_path = "/repos",
+    path = "/{id}/webhook_deliveries/{delivery_id}/replay",
+    tag = "Webhooks",
+    params(
+        ("id" = Uuid, Path, description = "Repository record id in database"),
+        ("delivery_id" = Uuid, Path, description = "Webhook delivery record id in database"),
+    ),
+    responses(
+        (status = OK, body = WebhookDelivery),
+        (status = NOT_FOUND, description = "Delivery is not found."),
+        (status = FORBIDDEN, description = "Authorized user doesn't have access to a project of the repository."),
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+    ),
+    security(
+        ("http" = [])
+    )
+)]
+async fn replay_webhook_delivery(
+    path: web::Path<(Uuid, Uuid)>,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    providers: web::Data<VcsProviders>,
+    auth_user: AuthenticatedUser,
+) -> Result<impl Responder> {
+    let (repo_id, delivery_id) = path.into_inner();
+    auth_user.require_repo(repo_id, ProjectPermission::Edit)?;
+    let pool1 = pool.to_owned();
+
+    let delivery = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        webhooks::find_repo_delivery(&mut conn, repo_id, delivery_id)
+    })
+    .await??;
+
+    process_delivery(&pool, **retention, &hub, &providers, delivery)
+        .await
+        .map(success)
+}
+
+// Design of the design file, it must match the design model.
+fn parse_design_file(file: &RepoFile) -> Result<serde_json::Value> {
+    let content: serde_json::Value = serde_json::from_slice(&file.content).map_err(|e| {
+        AppError::DesignValidation(vec![Violation {
+            pointer: String::new(),
+            message: e.to_string(),
+        }])
+    })?;
+    validation::validate_design(&content).map_err(AppError::DesignValidation)?;
 
-    Ok(HttpResponse::Ok())
+    Ok(content)
 }
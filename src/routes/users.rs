@@ -1,16 +1,24 @@
 use crate::{
+    errors::AppError,
     models::{
-        users::{self, NewUser},
+        repositories::Provider,
+        users::{self, NewUser, ProviderAccount, RoledUser},
         Result,
     },
-    routes::success,
+    routes::{auth::AuthenticatedUser, success},
+    services::vcs::{VcsProvider, VcsProviders},
     DbPool,
 };
-use actix_web::{web, HttpRequest, Responder};
+use actix_web::{guard, http::header::Header, web, HttpRequest, Responder};
+use actix_web_httpauth::headers::authorization::{Authorization, Bearer};
 use utoipa::ToSchema;
 use uuid::*;
 
-use super::parse_auth_token;
+#[derive(Debug, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct NewUserInput {
+    name: String,
+}
 
 #[derive(Debug, Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
@@ -25,21 +33,30 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
         web::scope("/users")
             .service(
                 web::resource("")
-                    .route(web::post().to(create_user))
                     .route(web::patch().to(update_user)),
             )
             .service(web::resource("/find").route(web::get().to(find_user_by_token)))
             .service(web::resource("/find/{name}").route(web::get().to(find_user)))
             // .service(web::resource("/roles").route(web::get().to(get_user_roles)))
-            .service(web::resource("/{id}").route(web::get().to(get_user)))
-            .service(web::resource("/{id}/token").route(web::patch().to(update_user_token))),
+            .service(web::resource("/{id}").route(web::get().to(get_user))),
+    );
+}
+
+/// Routes authorized with a GitHub access token instead of a session token,
+/// they are configured outside of the session authentication.
+pub fn configure_github_token(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::resource("/users")
+            .guard(guard::Post())
+            .route(web::post().to(create_user)),
     );
 }
 
 /// Create a user
 ///
-/// A User Bearer access token should be provided to create a record. 
-/// The access token provided will be associated with a user account.
+/// A GitHub access token should be provided, the user is created with its GitHub account
+/// and the primary email of the account. The token isn't stored, the user signs in
+/// with `/auth/github/access_token` to get a session and the tokens of the account.
 #[utoipa::path(
     post,
     context_path = "/users",
@@ -48,20 +65,27 @@ pub fn configure(cfg: &mut web::ServiceConfig) {
     responses(
         (status = OK, body = RoledUser),
         (status = BAD_REQUEST, description = "Unique constaint violation."),
-        (status = NOT_FOUND, description = "User role record is not found by provided ID."),
-        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
+        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's GitHub access token.")
     ),
-    request_body(content = UserInput, description = "Input User in JSON format", content_type = "application/json"),
+    request_body(content = NewUserInput, description = "Input User in JSON format", content_type = "application/json"),
     security(
         ("http" = [])
     )
 )]
 async fn create_user(
-    user: web::Json<UserInput>,
+    user: web::Json<NewUserInput>,
     pool: web::Data<DbPool>,
+    providers: web::Data<VcsProviders>,
     req: HttpRequest,
 ) -> Result<impl Responder> {
-    let token: String = parse_auth_token(req)?;
+    let authorization = Authorization::<Bearer>::parse(&req).map_err(|_| AppError::AuthError)?;
+    let token = authorization.as_ref().token();
+    let github_user = providers.github.get_user(token).await.map_err(|_| AppError::AuthError)?;
+    let email = providers.github.get_primary_email(token).await?;
+    let account = ProviderAccount {
+        provider: Provider::GitHub,
+        id: github_user.id,
+    };
     let user = user.into_inner();
 
     web::block(move || {
@@ -72,9 +96,10 @@ async fn create_user(
             NewUser {
                 name: user.name,
                 // role_id: user.role_id,
-                email: user.email,
-                access_token: Some(token),
+                email,
+                provider: Some(Provider::GitHub),
             },
+            account,
         )
     })
     .await?
@@ -126,16 +151,8 @@ async fn find_user(name: web::Path<String>, pool: web::Data<DbPool>) -> Result<i
         ("http" = [])
     )
 )]
-async fn find_user_by_token(req: HttpRequest, pool: web::Data<DbPool>) -> Result<impl Responder> {
-    let token: String = parse_auth_token(req)?;
-
-    web::block(move || {
-        let mut conn = pool.get()?;
-
-        users::find_user(&mut conn, users::UserKey::Token(&token))
-    })
-    .await?
-    .map(success)
+async fn find_user_by_token(auth_user: AuthenticatedUser) -> Result<impl Responder> {
+    Ok(success(RoledUser { user: auth_user.user }))
 }
 
 /// Get a user by id
@@ -187,9 +204,9 @@ async fn get_user(id: web::Path<Uuid>, pool: web::Data<DbPool>) -> Result<impl R
 async fn update_user(
     user: web::Json<UserInput>,
     pool: web::Data<DbPool>,
-    req: HttpRequest,
+    auth_user: AuthenticatedUser,
 ) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
+    let user_id = auth_user.id();
 
     web::block(move || {
         let mut conn = pool.get()?;
@@ -197,12 +214,12 @@ async fn update_user(
 
         users::update_user(
             &mut conn,
-            &token,
+            user_id,
             NewUser {
                 name: user.name,
                 // role_id: user.role_id,
                 email: user.email,
-                access_token: Some(token.clone()),
+                provider: None,
             },
         )
     })
@@ -210,44 +227,6 @@ async fn update_user(
     .map(success)
 }
 
-/// Update a user token
-///
-/// A User Bearer access token should be provided to create a record. 
-/// The access token provided must be associated with a user account.
-#[utoipa::path(
-    patch,
-    context_path = "/users",
-    path = "/{id}/token",
-    tag = "Users",
-    responses(
-        (status = OK, body = RoledUser),
-        (status = BAD_REQUEST, description = "Incorrect input data"),
-        (status = NOT_FOUND, description = "User record not found by provided id."),
-        (status = UNAUTHORIZED, description = "User is not authorized. Pass user's access token.")
-    ),
-    params(
-        ("id" = Uuid, Path, description = "User's id"),
-    ),
-    security(
-        ("http" = [])
-    )
-)]
-async fn update_user_token(
-    id: web::Path<Uuid>,
-    pool: web::Data<DbPool>,
-    req: HttpRequest,
-) -> Result<impl Responder> {
-    let token = parse_auth_token(req)?;
-
-    web::block(move || {
-        let mut conn = pool.get()?;
-
-        users::update_user_token(&mut conn, id.into_inner(), &token)
-    })
-    .await?
-    .map(success)
-}
-
 /* 
 /// Get all user roles
 ///
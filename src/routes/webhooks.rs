@@ -0,0 +1,337 @@
+use crate::{
+    errors::AppError,
+    models::{
+        designs::RevisionRetention,
+        projects,
+        pull_requests::{self, PullRequestState},
+        repositories::{self, Provider, Repository, RepositoryKey, RepositoryOwner},
+        webhooks::{self, NewWebhookDelivery, WebhookDelivery, WebhookStatus},
+        Result,
+    },
+    routes::{
+        repositories::{pull_design_file, PullStatus},
+        success,
+    },
+    services::{
+        collab::DesignHub,
+        design_files,
+        github::WebhookSecret,
+        vcs::VcsProviders,
+    },
+    DbPool,
+};
+use actix_web::{web, HttpRequest, HttpResponse, Responder};
+
+#[derive(Deserialize, Debug)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    reference: String,
+    after: String,
+    #[serde(default)]
+    deleted: bool,
+    repository: EventRepository,
+    #[serde(default)]
+    commits: Vec<PushCommit>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PullRequestEvent {
+    action: String,
+    pull_request: PullRequestPayload,
+}
+
+#[derive(Deserialize, Debug)]
+struct PullRequestPayload {
+    number: i32,
+    #[serde(default)]
+    merged: bool,
+}
+
+// Part of the payload every repository event has.
+#[derive(Deserialize, Debug)]
+struct RepositoryEvent {
+    repository: EventRepository,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventRepository {
+    name: String,
+    owner: EventOwner,
+    default_branch: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct EventOwner {
+    login: Option<String>,
+    name: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PushCommit {
+    #[serde(default)]
+    added: Vec<String>,
+    #[serde(default)]
+    modified: Vec<String>,
+    #[serde(default)]
+    removed: Vec<String>,
+}
+
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/webhooks")
+            .service(web::resource("/github").route(web::post().to(github_webhook))),
+    );
+}
+
+/// Receive a GitHub webhook delivery
+///
+/// Deliveries are signed with the secret from `GITHUB_WEBHOOK_SECRET`
+/// in `X-Hub-Signature-256` header. Without the secret deliveries are not accepted.
+///
+/// Push events changing the design file on the design branch of a known repository
+/// update the design of the linked project, the same way the design is pulled.
+/// Pull request events update the state of the tracked design pull requests.
+/// Every delivery is recorded. Deliveries which have been recorded already are skipped,
+/// unless they have failed.
+#[utoipa::path(
+    post,
+    context_path = "/webhooks",
+    path = "/github",
+    tag = "Webhooks",
+    request_body(content = serde_json::Value, description = "GitHub event payload", content_type = "application/json"),
+    params(
+        ("X-GitHub-Event" = String, Header, description = "Name of the event"),
+        ("X-GitHub-Delivery" = String, Header, description = "Unique id of the delivery"),
+        ("X-Hub-Signature-256" = String, Header, description = "HMAC SHA256 of the body"),
+    ),
+    responses(
+        (status = OK, body = WebhookDelivery, description = "Delivery is recorded and processed, or skipped as a duplicate."),
+        (status = BAD_REQUEST, description = "Delivery headers or payload are malformed."),
+        (status = UNAUTHORIZED, description = "Signature of the delivery is not valid."),
+        (status = SERVICE_UNAVAILABLE, description = "Webhook secret is not configured."),
+    )
+)]
+async fn github_webhook(
+    req: HttpRequest,
+    body: web::Bytes,
+    secret: web::Data<Option<WebhookSecret>>,
+    pool: web::Data<DbPool>,
+    retention: web::Data<RevisionRetention>,
+    hub: web::Data<DesignHub>,
+    providers: web::Data<VcsProviders>,
+) -> Result<impl Responder> {
+    let secret = secret.as_ref().as_ref().ok_or_else(|| {
+        AppError::NotConfigured("GITHUB_WEBHOOK_SECRET is not set, webhooks are not accepted".to_string())
+    })?;
+    let signature = header(&req, "X-Hub-Signature-256").ok_or(AppError::AuthError)?;
+    secret.verify(signature, &body)?;
+
+    let event = header(&req, "X-GitHub-Event")
+        .ok_or_else(|| AppError::InvalidInput("X-GitHub-Event header is missing".to_string()))?
+        .to_owned();
+    let delivery_id = header(&req, "X-GitHub-Delivery")
+        .ok_or_else(|| AppError::InvalidInput("X-GitHub-Delivery header is missing".to_string()))?
+        .to_owned();
+    let payload: serde_json::Value = serde_json::from_slice(&body)?;
+    let pool1 = pool.to_owned();
+
+    let delivery = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        webhooks::record_delivery(
+            &mut conn,
+            NewWebhookDelivery {
+                delivery_id: &delivery_id,
+                event: &event,
+                payload,
+            },
+        )
+    })
+    .await??;
+
+    let Some(delivery) = delivery else {
+        return Ok(HttpResponse::Ok().body("Delivery has been recorded already."));
+    };
+
+    process_delivery(&pool, **retention, &hub, &providers, delivery)
+        .await
+        .map(success)
+}
+
+/// Processes a recorded delivery and saves its outcome.
+/// Recorded deliveries could be processed again, it doesn't depend on the request.
+pub(crate) async fn process_delivery(
+    pool: &web::Data<DbPool>,
+    retention: RevisionRetention,
+    hub: &DesignHub,
+    providers: &VcsProviders,
+    delivery: WebhookDelivery,
+) -> Result<WebhookDelivery> {
+    let (repo_id, outcome) = match event_repository(pool, &delivery).await {
+        Ok(Some(repo)) => (
+            Some(repo.id),
+            handle_event(pool, retention, hub, providers, &delivery, repo).await,
+        ),
+        Ok(None) => (None, Ok(WebhookStatus::Ignored)),
+        Err(e) => (None, Err(e)),
+    };
+
+    let (status, error) = match outcome {
+        Ok(status) => (status, None),
+        Err(e) => (WebhookStatus::Failed, Some(e.to_string())),
+    };
+    let pool = pool.to_owned();
+
+    web::block(move || {
+        let mut conn = pool.get()?;
+
+        webhooks::update_delivery_status(&mut conn, delivery.id, status, repo_id, error)
+    })
+    .await?
+}
+
+// Known repository the delivery is about, `None` for events which are not handled.
+async fn event_repository(
+    pool: &web::Data<DbPool>,
+    delivery: &WebhookDelivery,
+) -> Result<Option<Repository>> {
+    if !matches!(delivery.event.as_str(), "push" | "pull_request") {
+        return Ok(None);
+    }
+
+    let event: RepositoryEvent = serde_json::from_value(delivery.payload.clone())?;
+    let repository = event.repository;
+    let owner = repository
+        .owner
+        .login
+        .or(repository.owner.name)
+        .ok_or_else(|| AppError::InvalidInput("Repository owner is missing".to_string()))?;
+    let repo_owner = RepositoryOwner {
+        name: repository.name,
+        owner,
+        is_organization: repository.owner.kind.as_deref() == Some("Organization"),
+    };
+    let pool = pool.to_owned();
+
+    let repo = web::block(move || {
+        let mut conn = pool.get()?;
+
+        repositories::find_repo(&mut conn, RepositoryKey::Owner(Provider::GitHub, repo_owner))
+    })
+    .await?;
+
+    match repo {
+        Ok(repo) => Ok(Some(repo)),
+        Err(AppError::RecordNotFound) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+async fn handle_event(
+    pool: &web::Data<DbPool>,
+    retention: RevisionRetention,
+    hub: &DesignHub,
+    providers: &VcsProviders,
+    delivery: &WebhookDelivery,
+    repo: Repository,
+) -> Result<WebhookStatus> {
+    match delivery.event.as_str() {
+        "push" => {
+            let push: PushEvent = serde_json::from_value(delivery.payload.clone())?;
+            sync_push(pool, retention, hub, providers, &push, repo).await
+        }
+        "pull_request" => {
+            let event: PullRequestEvent = serde_json::from_value(delivery.payload.clone())?;
+            sync_pull_request(pool, &event, repo).await
+        }
+        _ => Ok(WebhookStatus::Ignored),
+    }
+}
+
+async fn sync_push(
+    pool: &web::Data<DbPool>,
+    retention: RevisionRetention,
+    hub: &DesignHub,
+    providers: &VcsProviders,
+    push: &PushEvent,
+    repo: Repository,
+) -> Result<WebhookStatus> {
+    let branch = repo
+        .design_branch
+        .as_deref()
+        .unwrap_or(&push.repository.default_branch);
+    let tracked_ref = format!("refs/heads/{branch}");
+    let touches_design = push.commits.iter().any(|commit| {
+        commit
+            .added
+            .iter()
+            .chain(&commit.modified)
+            .chain(&commit.removed)
+            .any(|path| repo.is_design_path(path))
+    });
+
+    if push.deleted || push.reference != tracked_ref || !touches_design {
+        return Ok(WebhookStatus::Ignored);
+    }
+
+    let repo_id = repo.id;
+    let repo_provider = repo.provider;
+    let pool1 = pool.to_owned();
+
+    let (_, editor, user_token) = web::block(move || {
+        let mut conn = pool1.get()?;
+
+        projects::find_repo_editor(&mut conn, repo_id, repo_provider)
+    })
+    .await??
+    .ok_or_else(|| {
+        AppError::GithubAuthError("No editor of the repository projects has GitHub access".to_string())
+    })?;
+    let access = providers.repo_access(&repo, &editor, Ok(&user_token)).await?;
+
+    let file = design_files::get_design(providers, &access.token, &repo, Some(&push.after))
+        .await?
+        .ok_or_else(|| AppError::InvalidInput(format!("{} is not found in the pushed commit", repo.design_path)))?;
+
+    // The access of the editor only reads the file, the pushed revision has no author.
+    let commit_sha = Some(push.after.clone());
+    let pulled = pull_design_file(pool, retention, hub, &repo, None, commit_sha, file, |_| false).await?;
+
+    Ok(match pulled.iter().any(|pulled| pulled.status == PullStatus::Conflict) {
+        true => WebhookStatus::Conflict,
+        false => WebhookStatus::Processed,
+    })
+}
+
+async fn sync_pull_request(
+    pool: &web::Data<DbPool>,
+    event: &PullRequestEvent,
+    repo: Repository,
+) -> Result<WebhookStatus> {
+    let state = match event.action.as_str() {
+        "closed" if event.pull_request.merged => PullRequestState::Merged,
+        "closed" => PullRequestState::Closed,
+        "reopened" => PullRequestState::Open,
+        _ => return Ok(WebhookStatus::Ignored),
+    };
+    let number = event.pull_request.number;
+    let pool = pool.to_owned();
+
+    let pull_request = web::block(move || {
+        let mut conn = pool.get()?;
+
+        pull_requests::update_pull_request_state(&mut conn, repo.id, number, state)
+    })
+    .await??;
+
+    Ok(match pull_request {
+        Some(_) => WebhookStatus::Processed,
+        None => WebhookStatus::Ignored,
+    })
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|value| value.to_str().ok())
+}
@@ -1,11 +1,67 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    design_pull_requests (id) {
+        id -> Uuid,
+        repository_id -> Uuid,
+        #[max_length = 255]
+        branch -> Varchar,
+        number -> Int4,
+        html_url -> Text,
+        #[max_length = 20]
+        state -> Varchar,
+        #[max_length = 50]
+        design_file_sha -> Nullable<Varchar>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    design_revisions (design_id, revision) {
+        design_id -> Uuid,
+        revision -> Int4,
+        data -> Jsonb,
+        author_id -> Nullable<Uuid>,
+        restored_from -> Nullable<Int4>,
+        created_at -> Timestamp,
+        patch -> Nullable<Jsonb>,
+    }
+}
+
 diesel::table! {
     designs (id) {
         id -> Uuid,
         data -> Jsonb,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        revision -> Int4,
+    }
+}
+
+diesel::table! {
+    invitation_acceptances (invitation_id, user_id) {
+        invitation_id -> Uuid,
+        user_id -> Uuid,
+        created_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    project_invitations (id) {
+        id -> Uuid,
+        project_id -> Uuid,
+        #[max_length = 64]
+        token_hash -> Varchar,
+        #[max_length = 20]
+        role -> Varchar,
+        max_uses -> Nullable<Int4>,
+        use_count -> Int4,
+        created_by -> Uuid,
+        expires_at -> Timestamp,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
@@ -18,6 +74,12 @@ diesel::table! {
         design_id -> Uuid,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 20]
+        sync_state -> Nullable<Varchar>,
+        pushed_revision -> Nullable<Int4>,
+        #[max_length = 50]
+        pushed_commit_sha -> Nullable<Varchar>,
+        sync_error -> Nullable<Text>,
     }
 }
 
@@ -34,11 +96,35 @@ diesel::table! {
         html_url -> Text,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 255]
+        design_path -> Varchar,
+        #[max_length = 255]
+        design_branch -> Nullable<Varchar>,
+        design_pretty -> Bool,
+        design_trailing_newline -> Bool,
+        #[max_length = 20]
+        design_layout -> Varchar,
+        #[max_length = 20]
+        provider -> Varchar,
+        created_by -> Nullable<Uuid>,
     }
 }
 
 diesel::table! {
-    user_refresh_tokens (user_id) {
+    user_identities (user_id, provider) {
+        user_id -> Uuid,
+        #[max_length = 20]
+        provider -> Varchar,
+        #[max_length = 100]
+        provider_user_id -> Nullable<Varchar>,
+        access_token -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_refresh_tokens (user_id, provider) {
         user_id -> Uuid,
         refresh_token_cypher -> Bytea,
         cypher_nonce -> Bytea,
@@ -49,6 +135,8 @@ diesel::table! {
         token_type -> Varchar,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 20]
+        provider -> Varchar,
     }
 }
 
@@ -59,9 +147,10 @@ diesel::table! {
         name -> Varchar,
         #[max_length = 255]
         email -> Varchar,
-        access_token -> Nullable<Text>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 20]
+        provider -> Varchar,
     }
 }
 
@@ -72,20 +161,55 @@ diesel::table! {
         is_active -> Bool,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        #[max_length = 20]
+        role -> Varchar,
+    }
+}
+
+diesel::table! {
+    webhook_deliveries (id) {
+        id -> Uuid,
+        #[max_length = 64]
+        delivery_id -> Varchar,
+        #[max_length = 50]
+        event -> Varchar,
+        payload -> Jsonb,
+        repository_id -> Nullable<Uuid>,
+        #[max_length = 20]
+        status -> Varchar,
+        error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Timestamp,
     }
 }
 
+diesel::joinable!(design_pull_requests -> repositories (repository_id));
+diesel::joinable!(design_revisions -> designs (design_id));
+diesel::joinable!(design_revisions -> users (author_id));
+diesel::joinable!(invitation_acceptances -> project_invitations (invitation_id));
+diesel::joinable!(invitation_acceptances -> users (user_id));
+diesel::joinable!(project_invitations -> projects (project_id));
+diesel::joinable!(project_invitations -> users (created_by));
 diesel::joinable!(projects -> designs (design_id));
 diesel::joinable!(projects -> repositories (repo_id));
+diesel::joinable!(repositories -> users (created_by));
+diesel::joinable!(user_identities -> users (user_id));
 diesel::joinable!(user_refresh_tokens -> users (user_id));
 diesel::joinable!(users_projects -> projects (project_id));
 diesel::joinable!(users_projects -> users (user_id));
+diesel::joinable!(webhook_deliveries -> repositories (repository_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    design_pull_requests,
+    design_revisions,
     designs,
+    invitation_acceptances,
+    project_invitations,
     projects,
     repositories,
+    user_identities,
     user_refresh_tokens,
     users,
     users_projects,
+    webhook_deliveries,
 );
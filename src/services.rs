@@ -1,3 +1,12 @@
-
 pub(super) mod github;
-pub(super) mod encrypt;
\ No newline at end of file
+pub(super) mod github_app;
+pub(super) mod encrypt;
+pub(super) mod session;
+pub(super) mod collab;
+pub(super) mod rebase;
+pub(super) mod validation;
+pub(super) mod design_files;
+pub(super) mod vcs;
+pub(super) mod gitlab;
+pub(super) mod local_git;
+pub(super) mod merge;
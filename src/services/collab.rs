@@ -0,0 +1,154 @@
+use std::collections::{HashMap, VecDeque};
+use std::ops::Deref;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, Mutex as AsyncMutex, MutexGuard};
+use uuid::Uuid;
+
+const CHANNEL_CAPACITY: usize = 256;
+// Number of recent changes kept to resync reconnected editors without a full snapshot.
+const HISTORY_LEN: usize = 100;
+
+/// Applied change of a design, one change per design revision.
+#[derive(Debug)]
+pub struct DesignChange {
+    pub revision: i32,
+    pub patch: json_patch::Patch,
+    /// `None` for changes pushed to the repository.
+    pub author_id: Option<Uuid>,
+    /// Editor operation the change came from, `None` for REST updates.
+    pub origin: Option<Origin>,
+}
+
+#[derive(Debug)]
+pub struct Origin {
+    pub connection_id: Uuid,
+    pub op_id: String,
+}
+
+/// Editors of a design connected to this server.
+pub struct Room {
+    changes: broadcast::Sender<Arc<DesignChange>>,
+    // Users whose membership in the project of the design has changed.
+    members: broadcast::Sender<Uuid>,
+    writer: AsyncMutex<()>,
+    history: Mutex<VecDeque<Arc<DesignChange>>>,
+}
+
+impl Room {
+    fn new() -> Self {
+        let (changes, _) = broadcast::channel(CHANNEL_CAPACITY);
+        let (members, _) = broadcast::channel(CHANNEL_CAPACITY);
+
+        Room {
+            changes,
+            members,
+            writer: AsyncMutex::new(()),
+            history: Mutex::new(VecDeque::with_capacity(HISTORY_LEN)),
+        }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<DesignChange>> {
+        self.changes.subscribe()
+    }
+
+    pub fn subscribe_members(&self) -> broadcast::Receiver<Uuid> {
+        self.members.subscribe()
+    }
+
+    /// Serializes writes of the design, so changes are published in the order of revisions.
+    pub async fn write(&self) -> MutexGuard<'_, ()> {
+        self.writer.lock().await
+    }
+
+    pub fn publish(&self, change: DesignChange) {
+        let change = Arc::new(change);
+        let mut history = self.history.lock().expect("History lock is poisoned");
+
+        if history.back().is_some_and(|last| last.revision >= change.revision) {
+            return;
+        }
+
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(change.clone());
+
+        // Sending fails only when nobody is connected.
+        let _ = self.changes.send(change);
+    }
+
+    /// Changes made after the revision up to the current revision,
+    /// `None` when some of them are not in the history anymore.
+    pub fn changes_since(&self, revision: i32, current: i32) -> Option<Vec<Arc<DesignChange>>> {
+        let history = self.history.lock().expect("History lock is poisoned");
+        let changes: Vec<_> = history
+            .iter()
+            .filter(|change| change.revision > revision && change.revision <= current)
+            .cloned()
+            .collect();
+
+        // Revisions in the history are distinct, so none is missing when the counts match.
+        (changes.len() as i32 == current - revision).then_some(changes)
+    }
+}
+
+/// Rooms of the designs being edited. Rooms are created on demand
+/// and dropped when the last handle of the room is dropped.
+#[derive(Default)]
+pub struct DesignHub {
+    rooms: Arc<Mutex<HashMap<Uuid, Arc<Room>>>>,
+}
+
+impl DesignHub {
+    pub fn room(&self, design_id: Uuid) -> RoomHandle {
+        let room = self
+            .rooms
+            .lock()
+            .expect("Rooms lock is poisoned")
+            .entry(design_id)
+            .or_insert_with(|| Arc::new(Room::new()))
+            .clone();
+
+        RoomHandle {
+            design_id,
+            room,
+            rooms: self.rooms.clone(),
+        }
+    }
+
+    /// Notifies editors of the design that the membership of the user has changed,
+    /// so their connections check the access of the user again.
+    pub fn membership_changed(&self, design_id: Uuid, user_id: Uuid) {
+        let rooms = self.rooms.lock().expect("Rooms lock is poisoned");
+
+        if let Some(room) = rooms.get(&design_id) {
+            // Sending fails only when nobody is connected.
+            let _ = room.members.send(user_id);
+        }
+    }
+}
+
+pub struct RoomHandle {
+    design_id: Uuid,
+    room: Arc<Room>,
+    rooms: Arc<Mutex<HashMap<Uuid, Arc<Room>>>>,
+}
+
+impl Deref for RoomHandle {
+    type Target = Room;
+
+    fn deref(&self) -> &Room {
+        &self.room
+    }
+}
+
+impl Drop for RoomHandle {
+    fn drop(&mut self) {
+        let mut rooms = self.rooms.lock().expect("Rooms lock is poisoned");
+
+        // The map and this handle are the only owners left.
+        if Arc::strong_count(&self.room) == 2 {
+            rooms.remove(&self.design_id);
+        }
+    }
+}
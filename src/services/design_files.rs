@@ -0,0 +1,415 @@
+use crate::{
+    errors::AppError,
+    models::{
+        repositories::{DesignLayout, Repository},
+        users::User,
+        Result,
+    },
+    services::{
+        github::{self, FileChange, GitHubAPI},
+        validation::Violation,
+        vcs::{CommittedFile, RepoFile, VcsProviders},
+    },
+};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Index of the split layout, it lists the files of the split collections.
+const INDEX_FILE_NAME: &str = "index.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+struct Index {
+    /// The design without the split collections.
+    design: Map<String, Value>,
+    /// Files of each split collection in the order of its entities.
+    files: Map<String, Value>,
+}
+
+/// Design file of the repository at the reference, the default branch when `None`.
+/// For the split layout the directory is read as one file with the SHA of its tree.
+pub async fn get_design(
+    providers: &VcsProviders,
+    token: &str,
+    repo: &Repository,
+    reference: Option<&str>,
+) -> Result<Option<RepoFile>> {
+    if repo.design_layout == DesignLayout::File {
+        return providers
+            .get(repo.provider)
+            .get_file(token, repo.repo_owner(), &repo.design_path, reference)
+            .await;
+    }
+
+    let api = split_layout_api(providers, repo)?;
+    let reference = match reference {
+        Some(reference) => reference.to_owned(),
+        None => default_branch(api, token, repo).await?,
+    };
+    let Some(directory) = api
+        .get_directory(token, repo.repo_owner(), &repo.design_path, &reference)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let mut files = Vec::with_capacity(directory.files.len());
+    for file in directory.files {
+        let content = api.get_blob(token, repo.repo_owner(), &file.sha).await?;
+        files.push((file.path, content));
+    }
+
+    Ok(Some(RepoFile {
+        sha: directory.sha,
+        content: serde_json::to_vec(&join_design(files)?)?,
+    }))
+}
+
+/// SHA of the design file at the branch, `None` if there is no design file.
+pub async fn get_design_sha(
+    providers: &VcsProviders,
+    token: &str,
+    repo: &Repository,
+    branch: &str,
+) -> Result<Option<String>> {
+    match repo.design_layout {
+        DesignLayout::File => Ok(providers
+            .get(repo.provider)
+            .get_file(token, repo.repo_owner(), &repo.design_path, Some(branch))
+            .await?
+            .map(|file| file.sha)),
+        DesignLayout::Split => Ok(split_layout_api(providers, repo)?
+            .get_directory(token, repo.repo_owner(), &repo.design_path, branch)
+            .await?
+            .map(|directory| directory.sha)),
+    }
+}
+
+/// Commits the design to the branch, the default branch when `None`.
+/// `sha` is the SHA of the design file being replaced, the commit fails
+/// if the design file has changed since. When the design file is unchanged nothing
+/// is committed, and the commit is the head of the branch.
+#[allow(clippy::too_many_arguments)]
+pub async fn save_design(
+    providers: &VcsProviders,
+    token: &str,
+    repo: &Repository,
+    branch: Option<&str>,
+    design: &Value,
+    message: &str,
+    sha: Option<&str>,
+    author: &User,
+) -> Result<CommittedFile> {
+    if repo.design_layout == DesignLayout::File {
+        let content = repo.encode_design(design)?;
+
+        return providers
+            .get(repo.provider)
+            .commit_file(token, repo.repo_owner(), &repo.design_path, message, &content, sha, branch, author)
+            .await;
+    }
+
+    let api = split_layout_api(providers, repo)?;
+    let branch = match branch {
+        Some(branch) => branch.to_owned(),
+        None => default_branch(api, token, repo).await?,
+    };
+    let head = api
+        .get_branch_sha(token, repo.repo_owner(), &branch)
+        .await?
+        .ok_or_else(|| AppError::GithubAPIError(format!("Branch {branch} is not found")))?;
+    let current = api
+        .get_directory(token, repo.repo_owner(), &repo.design_path, &head)
+        .await?;
+
+    if current.as_ref().map(|directory| directory.sha.as_str()) != sha {
+        return Err(AppError::RepoFileConflict(format!(
+            "{} has changed since the last save or pull",
+            repo.design_path
+        )));
+    }
+
+    let files = split_design(design)?;
+    let new_sha = github::tree_sha(&files);
+    if current.as_ref().is_some_and(|directory| directory.sha == new_sha) {
+        return Ok(CommittedFile {
+            sha: new_sha,
+            commit_sha: head,
+        });
+    }
+
+    // Only changed files are sent, files which are not in the design anymore are deleted.
+    let current: HashMap<String, String> = current
+        .map(|directory| directory.files.into_iter().map(|file| (file.path, file.sha)).collect())
+        .unwrap_or_default();
+    let paths: HashSet<&str> = files.iter().map(|(path, _)| path.as_str()).collect();
+    let mut changes: Vec<FileChange> = current
+        .keys()
+        .filter(|path| !paths.contains(path.as_str()))
+        .map(|path| FileChange {
+            path: format!("{}/{path}", repo.design_path),
+            content: None,
+        })
+        .collect();
+
+    for (path, content) in files {
+        if current.get(&path) != Some(&github::blob_sha(content.as_bytes())) {
+            changes.push(FileChange {
+                path: format!("{}/{path}", repo.design_path),
+                content: Some(content),
+            });
+        }
+    }
+
+    let commit_sha = api
+        .commit_files(token, repo.repo_owner(), &branch, &head, changes, message)
+        .await?;
+
+    Ok(CommittedFile {
+        sha: new_sha,
+        commit_sha,
+    })
+}
+
+/// SHA the design file would have with the design.
+pub fn design_sha(repo: &Repository, design: &Value) -> Result<String> {
+    match repo.design_layout {
+        DesignLayout::File => Ok(github::blob_sha(&repo.encode_design(design)?)),
+        DesignLayout::Split => Ok(github::tree_sha(&split_design(design)?)),
+    }
+}
+
+/// Files of the split layout, paths are relative to the design directory.
+///
+/// Top level arrays of objects are split into a directory per array and a file per object.
+/// Files are named after `id` of the objects when all of them have a distinct one,
+/// after their positions otherwise. The rest of the design is kept in the index.
+pub fn split_design(design: &Value) -> Result<Vec<(String, String)>> {
+    let design = design
+        .as_object()
+        .ok_or_else(|| AppError::InvalidInput("Design must be a JSON object".to_string()))?;
+
+    let mut index = Index {
+        design: Map::new(),
+        files: Map::new(),
+    };
+    let mut files = Vec::new();
+
+    for (key, value) in design {
+        let entities = match value.as_array() {
+            Some(entities) if is_collection(key, entities) => entities,
+            _ => {
+                index.design.insert(key.to_owned(), value.to_owned());
+                continue;
+            }
+        };
+
+        let names = entity_names(entities);
+        let mut paths = Vec::with_capacity(entities.len());
+
+        for (name, entity) in names.into_iter().zip(entities) {
+            let path = format!("{key}/{name}.json");
+            files.push((path.clone(), canonical_json(entity)?));
+            paths.push(Value::String(path));
+        }
+
+        index.files.insert(key.to_owned(), Value::Array(paths));
+    }
+
+    files.push((INDEX_FILE_NAME.to_string(), canonical_json(&serde_json::to_value(index)?)?));
+
+    Ok(files)
+}
+
+/// Reassembles the design from the files of the split layout.
+pub fn join_design(files: Vec<(String, Vec<u8>)>) -> Result<Value> {
+    let mut files: HashMap<String, Vec<u8>> = files.into_iter().collect();
+
+    let index = files
+        .remove(INDEX_FILE_NAME)
+        .ok_or_else(|| invalid_file(INDEX_FILE_NAME, "file is missing"))?;
+    let index: Index =
+        serde_json::from_slice(&index).map_err(|e| invalid_file(INDEX_FILE_NAME, &e.to_string()))?;
+    let mut design = index.design;
+
+    for (key, paths) in index.files {
+        let paths = paths
+            .as_array()
+            .ok_or_else(|| invalid_file(INDEX_FILE_NAME, &format!("files of {key} must be an array")))?;
+        let mut entities = Vec::with_capacity(paths.len());
+
+        for path in paths {
+            let path = path
+                .as_str()
+                .ok_or_else(|| invalid_file(INDEX_FILE_NAME, &format!("files of {key} must be paths")))?;
+            let content = files
+                .get(path)
+                .ok_or_else(|| invalid_file(path, "file is missing"))?;
+            let entity: Value =
+                serde_json::from_slice(content).map_err(|e| invalid_file(path, &e.to_string()))?;
+
+            entities.push(entity);
+        }
+
+        design.insert(key, Value::Array(entities));
+    }
+
+    Ok(Value::Object(design))
+}
+
+// Arrays of objects under keys which could be directory names are split.
+fn is_collection(key: &str, entities: &[Value]) -> bool {
+    let is_name = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+
+    is_name && !entities.is_empty() && entities.iter().all(Value::is_object)
+}
+
+fn entity_names(entities: &[Value]) -> Vec<String> {
+    let ids: Vec<Option<String>> = entities
+        .iter()
+        .map(|entity| match entity.get("id") {
+            Some(Value::String(id)) => Some(file_name(id)),
+            Some(Value::Number(id)) => Some(id.to_string()),
+            _ => None,
+        })
+        .collect();
+    let distinct: HashSet<&str> = ids.iter().flatten().map(String::as_str).collect();
+
+    if distinct.len() == entities.len() && !distinct.contains("") {
+        return ids.into_iter().flatten().collect();
+    }
+
+    let width = entities.len().to_string().len();
+    (0..entities.len())
+        .map(|position| format!("{position:0width$}"))
+        .collect()
+}
+
+fn file_name(id: &str) -> String {
+    id.chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+// Pretty-printed JSON with sorted keys and a trailing newline, so equal values have equal files.
+fn canonical_json(value: &Value) -> Result<String> {
+    let mut json = serde_json::to_string_pretty(&sorted(value))?;
+    json.push('\n');
+
+    Ok(json)
+}
+
+fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Object(object) => {
+            let mut entries: Vec<(&String, &Value)> = object.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.to_owned(), sorted(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.iter().map(sorted).collect()),
+        value => value.to_owned(),
+    }
+}
+
+fn invalid_file(path: &str, message: &str) -> AppError {
+    AppError::DesignValidation(vec![Violation {
+        pointer: String::new(),
+        message: format!("{path}: {message}"),
+    }])
+}
+
+// The split layout is read and committed as git trees, which only GitHub is supported for.
+fn split_layout_api<'a>(providers: &'a VcsProviders, repo: &Repository) -> Result<&'a GitHubAPI> {
+    providers.github_only(repo, "Split layout")
+}
+
+async fn default_branch(api: &GitHubAPI, token: &str, repo: &Repository) -> Result<String> {
+    api.get_repo(token, &repo.owner, &repo.name)
+        .await?
+        .map(|github_repo| github_repo.default_branch)
+        .ok_or_else(|| AppError::InvalidInput("Repository is not found on Github".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn read(files: Vec<(String, String)>) -> Vec<(String, Vec<u8>)> {
+        files.into_iter().map(|(path, content)| (path, content.into_bytes())).collect()
+    }
+
+    fn paths(files: &[(String, String)]) -> Vec<&str> {
+        files.iter().map(|(path, _)| path.as_str()).collect()
+    }
+
+    #[test]
+    fn split_design_is_joined_back() {
+        let design = json!({
+            "name": "design",
+            "entities": [{ "id": "b", "size": 1 }, { "id": "a/1", "size": 2 }],
+            "links": [{ "id": 7 }],
+            "tags": ["a", "b"],
+            "nested": { "entities": [{ "id": "c" }] },
+        });
+
+        let files = split_design(&design).unwrap();
+
+        assert_eq!(paths(&files), vec!["entities/b.json", "entities/a_1.json", "links/7.json", "index.json"]);
+        assert_eq!(join_design(read(files)).unwrap(), design);
+    }
+
+    #[test]
+    fn split_design_names_files_by_positions_without_distinct_ids() {
+        let entities: Vec<Value> = (0..10).map(|size| json!({ "id": "same", "size": size })).collect();
+        let design = json!({ "entities": entities, "sizes": [{ "size": 1 }, { "id": "", "size": 2 }] });
+
+        let files = split_design(&design).unwrap();
+
+        assert_eq!(files[0].0, "entities/00.json");
+        assert_eq!(files[9].0, "entities/09.json");
+        assert_eq!(paths(&files)[10..], ["sizes/0.json", "sizes/1.json", "index.json"]);
+        assert_eq!(join_design(read(files)).unwrap(), design);
+    }
+
+    #[test]
+    fn split_design_keeps_equal_files_for_equal_designs() {
+        let design = json!({ "entities": [{ "size": 1, "id": "a" }] });
+        let reordered = json!({ "entities": [{ "id": "a", "size": 1 }] });
+
+        assert_eq!(split_design(&design).unwrap(), split_design(&reordered).unwrap());
+    }
+
+    #[test]
+    fn join_design_reports_missing_files() {
+        let mut files = read(split_design(&json!({ "entities": [{ "id": "a" }] })).unwrap());
+        files.retain(|(path, _)| path == INDEX_FILE_NAME);
+
+        match join_design(files) {
+            Err(AppError::DesignValidation(violations)) => {
+                assert_eq!(violations[0].message, "entities/a.json: file is missing")
+            }
+            result => panic!("Expected a validation error, got {result:?}"),
+        }
+    }
+
+    #[test]
+    fn tree_sha_is_git_tree_id() {
+        // `git write-tree` of the files, directories are sorted as if they end with `/`.
+        let files = [("a.json", "1\n"), ("a-b.json", "2\n"), ("a/b.json", "3\n"), ("index.json", "{}\n")]
+            .map(|(path, content)| (path.to_string(), content.to_string()));
+
+        assert_eq!(github::tree_sha(&files), "d972371ecdbaa6d19ae20fc7d7f4a610d07f6090");
+    }
+}
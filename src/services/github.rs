@@ -1,9 +1,28 @@
 use crate::{
     errors::AppError,
-    models::{repositories::RepositoryOwner, Result},
+    models::{
+        repositories::{Provider, RepositoryOwner},
+        users::{self, TokenData},
+        Result,
+    },
+    services::{
+        github_app::{GitHubApp, Installation, InstallationToken},
+        vcs::{CommittedFile, RepoFile, VcsProvider, VcsUser},
+    },
 };
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
 use reqwest::*;
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY, SHA256};
+use ring::hmac;
+use serde::de::DeserializeOwned;
 use serde_json::json;
+use tokio::time::sleep;
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct UserEmail {
@@ -13,6 +32,8 @@ pub struct UserEmail {
     visibility: Option<String>,
 }
 
+// The whole account is deserialized, though only a part of it is used.
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct User {
     pub avatar_url: String,
@@ -59,6 +80,7 @@ pub struct User {
     pub url: String,
 }
 
+#[allow(dead_code)]
 #[derive(Deserialize)]
 pub struct Plan {
     pub collaborators: i32,
@@ -87,6 +109,111 @@ pub struct Content {
     pub _links: Links,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct GitHubRepository {
+    pub name: String,
+    pub owner: RepositoryAccount,
+    pub html_url: String,
+    pub default_branch: String,
+    /// Permissions of the authenticated user, present for authenticated requests.
+    pub permissions: Option<RepositoryPermissions>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryAccount {
+    pub login: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RepositoryPermissions {
+    pub push: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequest {
+    pub number: i32,
+    pub html_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitRef {
+    object: GitObject,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitObject {
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RefCommit {
+    sha: String,
+    commit: RefCommitData,
+}
+
+#[derive(Debug, Deserialize)]
+struct RefCommitData {
+    tree: Tree,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitTree {
+    tree: Vec<TreeEntry>,
+    truncated: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TreeEntry {
+    pub path: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub sha: String,
+}
+
+/// Directory of a repository with its files, paths of the files are relative to the directory.
+#[derive(Debug)]
+pub struct Directory {
+    /// SHA of the git tree of the directory.
+    pub sha: String,
+    pub files: Vec<TreeEntry>,
+}
+
+/// Change of a file in a commit, the file is deleted when there is no content.
+#[derive(Debug)]
+pub struct FileChange {
+    pub path: String,
+    pub content: Option<String>,
+}
+
+// Response of the contents API, directories are listed as arrays.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Contents {
+    File(ContentFile),
+    Directory(#[allow(dead_code)] Vec<serde::de::IgnoredAny>),
+}
+
+// Entry of the contents API, its type is `file`, `symlink` or `submodule`.
+// Only files have content, the encoding is `none` and content is empty for files over 1 MB.
+#[derive(Debug, Deserialize)]
+struct ContentFile {
+    #[serde(rename = "type")]
+    kind: String,
+    sha: String,
+    #[serde(default)]
+    encoding: String,
+    #[serde(default)]
+    content: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Blob {
+    encoding: String,
+    content: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Links {
     #[serde(rename = "self")]
@@ -137,14 +264,232 @@ pub struct Verification {
     pub payload: Option<String>,
 }
 
+/// Requests are retried this many times on rate limits, and idempotent requests on server errors.
+const MAX_RETRIES: u32 = 3;
+/// Longer rate limit waits are not waited out, the request fails with the time to wait instead.
+const MAX_RATE_LIMIT_WAIT: Duration = Duration::from_secs(10);
+/// Secondary rate limits without a hint are waited out for a minute, as GitHub asks.
+/// It is longer than `MAX_RATE_LIMIT_WAIT`, so these requests fail right away.
+const SECONDARY_RATE_LIMIT_WAIT: Duration = Duration::from_secs(60);
+/// Number of cached responses, the oldest ones are evicted first.
+const CACHE_CAPACITY: usize = 128;
+/// Larger responses are not cached.
+const MAX_CACHED_BODY: usize = 256 * 1024;
+/// Base URL of the REST API unless `GITHUB_API_URL` is set.
+const DEFAULT_API_URL: &str = "https://api.github.com";
+/// Base URL of the web pages and OAuth unless `GITHUB_URL` is set.
+const DEFAULT_WEB_URL: &str = "https://github.com";
+
+/// Response of the GitHub API read into memory.
+#[derive(Clone, Debug)]
+pub struct GitHubResponse {
+    pub status: StatusCode,
+    pub headers: header::HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl GitHubResponse {
+    pub fn json<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_slice(&self.body).map_err(|e| AppError::GithubAPIError(e.to_string()))
+    }
+
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// Fails with the status and the message from GitHub unless the request has succeeded.
+    pub fn success(self) -> Result<Self> {
+        if self.status.is_success() {
+            return Ok(self);
+        }
+
+        let message = match serde_json::from_slice::<ErrorMessage>(&self.body) {
+            Ok(error) if error.errors.is_empty() => error.message,
+            Ok(error) => {
+                let details: Vec<String> = error
+                    .errors
+                    .iter()
+                    .map(|detail| match detail.get("message").or_else(|| detail.get("code")) {
+                        Some(serde_json::Value::String(message)) => message.to_owned(),
+                        _ => detail.to_string(),
+                    })
+                    .collect();
+
+                format!("{}: {}", error.message, details.join("; "))
+            }
+            Err(_) => self.text(),
+        };
+
+        Err(AppError::GithubStatusError(self.status.as_u16(), message))
+    }
+}
+
+#[derive(Deserialize)]
+struct ErrorMessage {
+    message: String,
+    #[serde(default)]
+    errors: Vec<serde_json::Value>,
+}
+
+// GET responses with an ETag, revalidated with conditional requests.
+#[derive(Default)]
+struct ResponseCache {
+    responses: HashMap<String, (String, GitHubResponse)>,
+    order: VecDeque<String>,
+}
+
+impl ResponseCache {
+    fn get(&self, key: &str) -> Option<&(String, GitHubResponse)> {
+        self.responses.get(key)
+    }
+
+    fn insert(&mut self, key: String, etag: String, response: GitHubResponse) {
+        if self.responses.insert(key.clone(), (etag, response)).is_none() {
+            self.order.push_back(key);
+        }
+
+        while self.order.len() > CACHE_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.responses.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Default, Debug)]
+struct GenerateAccessTokenParams {
+    client_id: String,
+    client_secret: String,
+    code: String,
+    redirect_uri: Option<String>,
+    repository_id: Option<String>,
+}
+
+#[derive(Serialize, Default, Debug)]
+struct RefreshAccessTokenParams {
+    client_id: String,
+    client_secret: String,
+    grant_type: String,
+    refresh_token: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+struct GenerateAccessTokenResponse {
+    access_token: String,
+    expires_in: i32,
+    refresh_token: String,
+    refresh_token_expires_in: i32,
+    scope: String,
+    token_type: String,
+}
+
+impl From<GenerateAccessTokenResponse> for TokenData {
+    fn from(response: GenerateAccessTokenResponse) -> Self {
+        TokenData {
+            access_token: response.access_token,
+            expires_in: response.expires_in,
+            refresh_token: response.refresh_token,
+            refresh_token_expires_in: response.refresh_token_expires_in,
+            scope: response.scope,
+            token_type: response.token_type,
+        }
+    }
+}
+
+/// Client of the GitHub OAuth application from `GITHUB_CLIENT_ID` and `GITHUB_CLIENT_SECRET`.
+struct GitHubAuth {
+    client: Client,
+    base_url: Url,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+impl GitHubAuth {
+    fn new() -> Result<Self> {
+        let client_id = env::var("GITHUB_CLIENT_ID").ok();
+        let client_secret = env::var("GITHUB_CLIENT_SECRET").ok();
+        let base_url = web_base_url()?;
+        let client = reqwest::Client::builder().build()?;
+
+        Ok(GitHubAuth {
+            client,
+            base_url,
+            client_id,
+            client_secret,
+        })
+    }
+
+    fn credentials(&self) -> Result<(String, String)> {
+        match (&self.client_id, &self.client_secret) {
+            (Some(client_id), Some(client_secret)) => Ok((client_id.to_owned(), client_secret.to_owned())),
+            _ => Err(AppError::GithubAuthError(
+                "GITHUB_CLIENT_ID and GITHUB_CLIENT_SECRET must be present.".to_string(),
+            )),
+        }
+    }
+
+    async fn generate_access_token(
+        &self,
+        params: GenerateAccessTokenParams,
+    ) -> Result<GenerateAccessTokenResponse> {
+        let url = url_with_path(&self.base_url, "/login/oauth/access_token");
+
+        let response = self
+            .client
+            .post(url)
+            .query(&params)
+            .send()
+            .await?;
+
+        let body = response.text().await?;
+
+        if let Ok(success) = serde_urlencoded::from_str::<GenerateAccessTokenResponse>(&body) {
+            return Ok(success);
+        }
+
+        Err(AppError::GithubAuthError(body))
+    }
+
+    async fn refresh_access_token(
+        &self,
+        params: RefreshAccessTokenParams,
+    ) -> Result<GenerateAccessTokenResponse> {
+        let url = url_with_path(&self.base_url, "/login/oauth/access_token");
+
+        let response = self
+            .client
+            .post(url)
+            .query(&params)
+            .send()
+            .await?;
+
+        let body = response.text().await?;
+
+        if let Ok(success) = serde_urlencoded::from_str::<GenerateAccessTokenResponse>(&body) {
+            return Ok(success);
+        }
+
+        Err(AppError::GithubAuthError(body))
+    }
+}
+
+/// Client of the GitHub REST API and OAuth application, shared by the workers.
+///
+/// Requests wait out short rate limits, idempotent requests are retried on server errors
+/// with backoff. GET responses are cached and revalidated with their ETag.
+/// When a GitHub App is configured, its installation tokens are used for the repositories
+/// of the accounts it is installed on.
 pub struct GitHubAPI {
     client: Client,
     base_url: Url,
+    cache: Mutex<ResponseCache>,
+    auth: GitHubAuth,
+    app: Option<GitHubApp>,
 }
 
 impl GitHubAPI {
     pub fn new() -> Result<Self> {
-        let base_url = Url::parse("https://api.github.com")?;
+        let base_url = api_base_url()?;
         let mut default_headers: header::HeaderMap = header::HeaderMap::new();
         default_headers.insert(
             header::ACCEPT,
@@ -163,26 +508,59 @@ impl GitHubAPI {
             .default_headers(default_headers)
             .build()?;
 
-        Ok(GitHubAPI { client, base_url })
+        Ok(GitHubAPI {
+            client,
+            base_url,
+            cache: Mutex::new(ResponseCache::default()),
+            auth: GitHubAuth::new()?,
+            app: GitHubApp::from_env()?,
+        })
     }
 
-    pub async fn get_user_primary_email(&self, token: &str) -> Result<UserEmail> {
-        let token_value = header::HeaderValue::from_str(&["Bearer ", token].concat())?;
-        let mut url = self.base_url.clone();
-        url.set_path("/user/emails");
+    /// Installation token of the GitHub App on the account, `None` when the app
+    /// is not configured or is not installed on the account.
+    pub async fn installation_token(&self, account: &str, is_organization: bool) -> Result<Option<String>> {
+        let Some(app) = &self.app else {
+            return Ok(None);
+        };
 
-        let response = self
-            .client
-            .get(url)
-            .header(header::AUTHORIZATION, token_value)
-            .send()
-            .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))?;
+        if let Some(token) = app.cached_token(account) {
+            return Ok(token);
+        }
 
-        let emails = response
-            .json::<Vec<UserEmail>>()
-            .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))?;
+        let jwt = app.jwt()?;
+        let path = match is_organization {
+            true => format!("/orgs/{account}/installation"),
+            false => format!("/users/{account}/installation"),
+        };
+        let response = self.send(self.request(Method::GET, &path, &jwt)?).await?;
+
+        if response.status == StatusCode::NOT_FOUND {
+            app.cache_not_installed(account);
+            return Ok(None);
+        }
+
+        let installation = response.success()?.json::<Installation>()?;
+        let request = self.request(
+            Method::POST,
+            &format!("/app/installations/{}/access_tokens", installation.id),
+            &jwt,
+        )?;
+        let token = self
+            .send(request)
+            .await?
+            .success()?
+            .json::<InstallationToken>()?;
+
+        app.cache_token(account, &token);
+
+        Ok(Some(token.token))
+    }
+
+    pub async fn get_user_primary_email(&self, token: &str) -> Result<UserEmail> {
+        let emails: Vec<UserEmail> = self
+            .get_all(token, self.request(Method::GET, "/user/emails", token)?)
+            .await?;
 
         let primary_email = emails.iter().find(|e| e.primary).cloned();
 
@@ -196,22 +574,10 @@ impl GitHubAPI {
     }
 
     pub async fn get_auth_user(&self, token: &str) -> Result<User> {
-        let token_value = header::HeaderValue::from_str(&["Bearer ", token].concat())?;
-        let mut url = self.base_url.clone();
-        url.set_path("/user");
-
-        let response = self
-            .client
-            .get(url)
-            .header(header::AUTHORIZATION, token_value)
-            .send()
-            .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))?;
-
-        response
+        self.send(self.request(Method::GET, "/user", token)?)
+            .await?
+            .success()?
             .json::<User>()
-            .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))
     }
 
     pub async fn create_org_repo(
@@ -219,39 +585,256 @@ impl GitHubAPI {
         token: &str,
         org: &str,
         body: serde_json::Value,
-    ) -> Result<Response> {
-        let token_value = header::HeaderValue::from_str(&["Bearer ", token].concat())?;
-        let mut url = self.base_url.clone();
-        url.set_path(&format!("/orgs/{org}/repos"));
+    ) -> Result<GitHubResponse> {
+        let request = self.request(Method::POST, &format!("/orgs/{org}/repos"), token)?;
 
-        self.client
-            .post(url)
-            .header(header::AUTHORIZATION, token_value)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))
+        self.send(request.json(&body)).await
     }
 
     pub async fn create_personal_repo(
         &self,
         token: &str,
         body: serde_json::Value,
-    ) -> Result<Response> {
-        let token_value = header::HeaderValue::from_str(&["Bearer ", token].concat())?;
-        let mut url = self.base_url.clone();
-        url.set_path("/user/repos");
+    ) -> Result<GitHubResponse> {
+        let request = self.request(Method::POST, "/user/repos", token)?;
 
-        self.client
-            .post(url)
-            .header(header::AUTHORIZATION, token_value)
-            .json(&body)
-            .send()
+        self.send(request.json(&body)).await
+    }
+
+    /// Returns `None` if there is no such repository or the user can't see it.
+    pub async fn get_repo(&self, token: &str, owner: &str, name: &str) -> Result<Option<GitHubRepository>> {
+        let response = self
+            .send(self.request(Method::GET, &format!("/repos/{owner}/{name}"), token)?)
+            .await?;
+
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response.success()?.json::<GitHubRepository>().map(Some)
+    }
+
+    pub async fn archive_repo(&self, token: &str, repo_owner: RepositoryOwner) -> Result<()> {
+        let request = self.request(
+            Method::PATCH,
+            &format!("/repos/{}/{}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        self.send(request.json(&json!({ "archived": true })))
+            .await?
+            .success()
+            .map(|_| ())
+    }
+
+    /// Returns `None` if there is no such file in the repository.
+    /// The file is read from the default branch unless `reference` (commit, branch or tag) is set.
+    pub async fn get_file_content(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: Option<&str>,
+    ) -> Result<Option<RepoFile>> {
+        let mut request = self.request(
+            Method::GET,
+            &format!("/repos/{}/{}/contents/{path}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+        if let Some(reference) = reference {
+            request = request.query(&[("ref", reference)]);
+        }
+
+        let response = self.send(request).await?;
+
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let file = match response.success()?.json::<Contents>()? {
+            Contents::File(file) if file.kind == "file" => file,
+            _ => return Err(AppError::InvalidInput(format!("{path} is not a file"))),
+        };
+
+        let content = match file.encoding.as_str() {
+            "base64" => decode_base64(&file.content)?,
+            // Content of large files is read from the git blob.
+            "none" => self.get_blob(token, repo_owner, &file.sha).await?,
+            encoding => {
+                return Err(AppError::GithubAPIError(format!(
+                    "Unexpected file encoding {encoding}"
+                )))
+            }
+        };
+
+        Ok(Some(RepoFile {
+            sha: file.sha,
+            content,
+        }))
+    }
+
+    pub async fn get_blob(&self, token: &str, repo_owner: RepositoryOwner, sha: &str) -> Result<Vec<u8>> {
+        let request = self.request(
+            Method::GET,
+            &format!("/repos/{}/{}/git/blobs/{sha}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        let blob = self.send(request).await?.success()?.json::<Blob>()?;
+
+        if blob.encoding != "base64" {
+            return Err(AppError::GithubAPIError(format!(
+                "Unexpected blob encoding {}",
+                blob.encoding
+            )));
+        }
+
+        decode_base64(&blob.content)
+    }
+
+    /// Returns the directory at the commit, branch or tag, `None` if there is no such directory.
+    /// Only files are listed, with the files of subdirectories.
+    pub async fn get_directory(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: &str,
+    ) -> Result<Option<Directory>> {
+        let commit = self.get_commit(token, repo_owner.clone(), reference).await?;
+        let mut sha = commit.commit.tree.sha;
+
+        // Trees are walked down from the root, so only the directory is listed recursively.
+        for segment in path.split('/') {
+            let tree = self.get_tree(token, repo_owner.clone(), &sha, false).await?;
+            let entry = tree
+                .tree
+                .into_iter()
+                .find(|entry| entry.path == segment && entry.kind == "tree");
+
+            match entry {
+                Some(entry) => sha = entry.sha,
+                None => return Ok(None),
+            }
+        }
+
+        let tree = self.get_tree(token, repo_owner, &sha, true).await?;
+        if tree.truncated {
+            return Err(AppError::GithubAPIError(format!(
+                "Directory {path} has too many files"
+            )));
+        }
+
+        let files = tree
+            .tree
+            .into_iter()
+            .filter(|entry| entry.kind == "blob")
+            .collect();
+
+        Ok(Some(Directory { sha, files }))
+    }
+
+    /// Commits the changes on top of `head` and moves the branch to the commit.
+    /// Fails with `RepoFileConflict` if the branch has moved from `head`,
+    /// or `head` is not a commit anymore. Returns the SHA of the commit.
+    pub async fn commit_files(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        branch: &str,
+        head: &str,
+        changes: Vec<FileChange>,
+        message: &str,
+    ) -> Result<String> {
+        let base = self
+            .get_commit(token, repo_owner.clone(), head)
             .await
-            .map_err(|e| AppError::GithubAPIError(e.to_string()))
+            .map_err(|e| match e {
+                AppError::GithubStatusError(404 | 422, _) => {
+                    AppError::RepoFileConflict(format!("Commit {head} of branch {branch} is not found"))
+                }
+                e => e,
+            })?;
+        let repo_path = format!("/repos/{}/{}", repo_owner.owner, repo_owner.name);
+
+        let entries: Vec<serde_json::Value> = changes
+            .into_iter()
+            .map(|change| match change.content {
+                Some(content) => json!({
+                    "path" : change.path,
+                    "mode" : "100644",
+                    "type" : "blob",
+                    "content" : content
+                }),
+                None => json!({
+                    "path" : change.path,
+                    "mode" : "100644",
+                    "type" : "blob",
+                    "sha" : null
+                }),
+            })
+            .collect();
+
+        let request = self.request(Method::POST, &format!("{repo_path}/git/trees"), token)?;
+        let tree = self
+            .send(request.json(&json!({ "base_tree" : base.commit.tree.sha, "tree" : entries })))
+            .await?
+            .success()?
+            .json::<GitObject>()?;
+
+        let request = self.request(Method::POST, &format!("{repo_path}/git/commits"), token)?;
+        let commit = self
+            .send(request.json(&json!({ "message" : message, "tree" : tree.sha, "parents" : [base.sha] })))
+            .await?
+            .success()?
+            .json::<GitObject>()?;
+
+        let request = self.request(Method::PATCH, &format!("{repo_path}/git/refs/heads/{branch}"), token)?;
+        // GitHub rejects the update when it is not a fast forward, the branch has moved.
+        self.send(request.json(&json!({ "sha" : commit.sha, "force" : false })))
+            .await?
+            .success()
+            .map_err(|e| match e {
+                AppError::GithubStatusError(422, message) => AppError::RepoFileConflict(message),
+                e => e,
+            })?;
+
+        Ok(commit.sha)
+    }
+
+    // Commit of the commit SHA, branch or tag.
+    async fn get_commit(&self, token: &str, repo_owner: RepositoryOwner, reference: &str) -> Result<RefCommit> {
+        let request = self.request(
+            Method::GET,
+            &format!("/repos/{}/{}/commits/{reference}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        self.send(request).await?.success()?.json::<RefCommit>()
+    }
+
+    async fn get_tree(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        sha: &str,
+        recursive: bool,
+    ) -> Result<GitTree> {
+        let mut request = self.request(
+            Method::GET,
+            &format!("/repos/{}/{}/git/trees/{sha}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+        if recursive {
+            request = request.query(&[("recursive", "1")]);
+        }
+
+        self.send(request).await?.success()?.json::<GitTree>()
     }
 
     // SHA is reequired if you are updating a file. The blob SHA of the file being replaced.
+    // The file is saved to the default branch unless `branch` is set.
+    #[allow(clippy::too_many_arguments)]
     pub async fn save_file_content(
         &self,
         token: &str,
@@ -260,36 +843,564 @@ impl GitHubAPI {
         message: &str,
         base64_content: &str,
         sha: Option<&str>,
+        branch: Option<&str>,
     ) -> Result<FileCommit> {
-        let token_value = header::HeaderValue::from_str(&format!("Bearer {token}"))?;
-        let mut url = self.base_url.to_owned();
-        url.set_path(&format!(
-            "/repos/{}/{}/contents/{path}",
-            repo_owner.owner, repo_owner.name
-        ));
+        let request = self.request(
+            Method::PUT,
+            &format!("/repos/{}/{}/contents/{path}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
 
         let json = json!({
             "message" : message,
             "content" : base64_content,
+            "sha" : sha,
+            "branch" : branch
+        });
+
+        self.send(request.json(&json))
+            .await?
+            .success()?
+            .json::<FileCommit>()
+    }
+
+    /// Returns the SHA of the commit the branch points to, `None` if there is no such branch.
+    pub async fn get_branch_sha(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        branch: &str,
+    ) -> Result<Option<String>> {
+        let request = self.request(
+            Method::GET,
+            &format!("/repos/{}/{}/git/ref/heads/{branch}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        let response = self.send(request).await?;
+
+        if response.status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        response
+            .success()?
+            .json::<GitRef>()
+            .map(|git_ref| Some(git_ref.object.sha))
+    }
+
+    /// Creates the branch pointing to the commit.
+    pub async fn create_branch(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        branch: &str,
+        sha: &str,
+    ) -> Result<()> {
+        let request = self.request(
+            Method::POST,
+            &format!("/repos/{}/{}/git/refs", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        let json = json!({
+            "ref" : format!("refs/heads/{branch}"),
             "sha" : sha
         });
 
-        let response = self
+        self.send(request.json(&json)).await?.success()?;
+
+        Ok(())
+    }
+
+    /// Returns the open pull request from the branch of the repository, if there is one.
+    pub async fn find_open_pull_request(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        branch: &str,
+    ) -> Result<Option<PullRequest>> {
+        let head = format!("{}:{branch}", repo_owner.owner);
+        let request = self
+            .request(
+                Method::GET,
+                &format!("/repos/{}/{}/pulls", repo_owner.owner, repo_owner.name),
+                token,
+            )?
+            .query(&[("state", "open"), ("head", &head)]);
+
+        let pulls: Vec<PullRequest> = self.get_all(token, request).await?;
+
+        Ok(pulls.into_iter().next())
+    }
+
+    pub async fn create_pull_request(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        title: &str,
+        head: &str,
+        base: &str,
+    ) -> Result<PullRequest> {
+        let request = self.request(
+            Method::POST,
+            &format!("/repos/{}/{}/pulls", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        let json = json!({
+            "title" : title,
+            "head" : head,
+            "base" : base
+        });
+
+        self.send(request.json(&json))
+            .await?
+            .success()?
+            .json::<PullRequest>()
+    }
+
+    pub async fn update_pull_request(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        number: i32,
+        title: &str,
+    ) -> Result<PullRequest> {
+        let request = self.request(
+            Method::PATCH,
+            &format!("/repos/{}/{}/pulls/{number}", repo_owner.owner, repo_owner.name),
+            token,
+        )?;
+
+        self.send(request.json(&json!({ "title" : title })))
+            .await?
+            .success()?
+            .json::<PullRequest>()
+    }
+
+    fn request(&self, method: Method, path: &str, token: &str) -> Result<RequestBuilder> {
+        self.request_url(method, url_with_path(&self.base_url, path), token)
+    }
+
+    fn request_url(&self, method: Method, url: Url, token: &str) -> Result<RequestBuilder> {
+        let token_value = header::HeaderValue::from_str(&format!("Bearer {token}"))?;
+
+        Ok(self
             .client
-            .put(url)
-            .header(header::AUTHORIZATION, token_value)
-            .json(&json)
-            .send()
-            .await
+            .request(method, url)
+            .header(header::AUTHORIZATION, token_value))
+    }
+
+    // Items of all pages of a list, following `Link` headers.
+    async fn get_all<T: DeserializeOwned>(&self, token: &str, request: RequestBuilder) -> Result<Vec<T>> {
+        let mut items = Vec::new();
+        let mut response = self
+            .send(request.query(&[("per_page", "100")]))
+            .await?
+            .success()?;
+
+        loop {
+            items.extend(response.json::<Vec<T>>()?);
+
+            let Some(next) = next_page(&response.headers) else {
+                return Ok(items);
+            };
+
+            response = self
+                .send(self.request_url(Method::GET, next, token)?)
+                .await?
+                .success()?;
+        }
+    }
+
+    async fn send(&self, request: RequestBuilder) -> Result<GitHubResponse> {
+        let mut request = request
+            .build()
             .map_err(|e| AppError::GithubAPIError(e.to_string()))?;
+        let cache_key = (request.method() == Method::GET).then(|| cache_key(&request));
+
+        if let Some(key) = &cache_key {
+            if let Some((etag, _)) = self.cache.lock().expect("Cache lock is poisoned").get(key) {
+                request
+                    .headers_mut()
+                    .insert(header::IF_NONE_MATCH, header::HeaderValue::from_str(etag)?);
+            }
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            let retry = request
+                .try_clone()
+                .ok_or_else(|| AppError::GithubAPIError("Request can't be retried".to_string()))?;
+            let idempotent = request.method().is_idempotent();
+
+            let response = match self.client.execute(retry).await {
+                Ok(response) => response,
+                Err(_) if idempotent && attempt < MAX_RETRIES => {
+                    sleep(backoff(attempt)).await;
+                    attempt += 1;
+                    continue;
+                }
+                Err(e) => return Err(AppError::GithubAPIError(e.to_string())),
+            };
+
+            let response = GitHubResponse {
+                status: response.status(),
+                headers: response.headers().to_owned(),
+                body: response
+                    .bytes()
+                    .await
+                    .map_err(|e| AppError::GithubAPIError(e.to_string()))?
+                    .to_vec(),
+            };
+
+            if response.status == StatusCode::NOT_MODIFIED {
+                let cached = cache_key.as_deref().and_then(|key| {
+                    let cache = self.cache.lock().expect("Cache lock is poisoned");
+                    cache.get(key).map(|(_, cached)| cached.to_owned())
+                });
+
+                match cached {
+                    Some(cached) => return Ok(cached),
+                    // The response has been evicted in the meantime.
+                    None => {
+                        request.headers_mut().remove(header::IF_NONE_MATCH);
+                        continue;
+                    }
+                }
+            }
+
+            if let Some(wait) = rate_limit_wait(&response) {
+                if wait > MAX_RATE_LIMIT_WAIT || attempt >= MAX_RETRIES {
+                    return Err(AppError::GithubRateLimited(wait.as_secs().max(1)));
+                }
+
+                sleep(wait).await;
+                attempt += 1;
+                continue;
+            }
+
+            if response.status.is_server_error() && idempotent && attempt < MAX_RETRIES {
+                sleep(backoff(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+
+            let etag = response
+                .headers
+                .get(header::ETAG)
+                .and_then(|etag| etag.to_str().ok());
+
+            if let (Some(key), Some(etag)) = (cache_key, etag) {
+                if response.status == StatusCode::OK && response.body.len() <= MAX_CACHED_BODY {
+                    self.cache.lock().expect("Cache lock is poisoned").insert(
+                        key,
+                        etag.to_owned(),
+                        response.to_owned(),
+                    );
+                }
+            }
+
+            return Ok(response);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl VcsProvider for GitHubAPI {
+    fn provider(&self) -> Provider {
+        Provider::GitHub
+    }
+
+    async fn generate_access_token(&self, code: String) -> Result<TokenData> {
+        let (client_id, client_secret) = self.auth.credentials()?;
+
+        self.auth
+            .generate_access_token(GenerateAccessTokenParams {
+                client_id,
+                client_secret,
+                code,
+                ..Default::default()
+            })
+            .await
+            .map(TokenData::from)
+    }
+
+    async fn refresh_access_token(&self, refresh_token: String) -> Result<TokenData> {
+        let (client_id, client_secret) = self.auth.credentials()?;
+
+        self.auth
+            .refresh_access_token(RefreshAccessTokenParams {
+                client_id,
+                client_secret,
+                grant_type: "refresh_token".to_string(),
+                refresh_token,
+            })
+            .await
+            .map(TokenData::from)
+    }
+
+    async fn get_user(&self, token: &str) -> Result<VcsUser> {
+        let user = self.get_auth_user(token).await?;
+
+        Ok(VcsUser {
+            id: user.id.to_string(),
+            login: user.login,
+            name: user.name,
+        })
+    }
+
+    async fn get_primary_email(&self, token: &str) -> Result<String> {
+        self.get_user_primary_email(token).await.map(|email| email.email)
+    }
+
+    async fn create_repo(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        params: serde_json::Value,
+    ) -> Result<String> {
+        // Installation tokens can create repositories of organizations only.
+        let response = match repo_owner.is_organization {
+            true => {
+                let token = self
+                    .installation_token(&repo_owner.owner, true)
+                    .await?
+                    .unwrap_or_else(|| token.to_owned());
 
-        if response.status().is_success() {
-            return response
-                .json::<FileCommit>()
-                .await
-                .map_err(|e| AppError::GithubAPIError(e.to_string()));
+                self.create_org_repo(&token, &repo_owner.owner, params).await
+            }
+            false => self.create_personal_repo(token, params).await,
+        }?;
+        let json = response.success()?.json::<serde_json::Value>()?;
+
+        match json.get("html_url").and_then(|url| url.as_str()) {
+            Some(html_url) => Ok(html_url.to_owned()),
+            None => {
+                let path = format!("/{}/{}", repo_owner.owner, repo_owner.name);
+                Ok(url_with_path(&web_base_url()?, &path).to_string())
+            }
         }
+    }
+
+    async fn archive_repo(&self, token: &str, repo_owner: RepositoryOwner) -> Result<()> {
+        GitHubAPI::archive_repo(self, token, repo_owner).await
+    }
+
+    async fn get_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: Option<&str>,
+    ) -> Result<Option<RepoFile>> {
+        self.get_file_content(token, repo_owner, path, reference).await
+    }
+
+    async fn commit_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        message: &str,
+        content: &[u8],
+        sha: Option<&str>,
+        branch: Option<&str>,
+        _author: &users::User,
+    ) -> Result<CommittedFile> {
+        // GitHub requires the SHA of an existing file, and rejects an outdated one.
+        let file_commit = self
+            .save_file_content(token, repo_owner, path, message, &encode_base64(content), sha, branch)
+            .await
+            .map_err(|e| match e {
+                AppError::GithubStatusError(409, message) => AppError::RepoFileConflict(message),
+                AppError::GithubStatusError(422, message) if sha.is_none() => AppError::RepoFileConflict(message),
+                e => e,
+            })?;
+
+        Ok(CommittedFile {
+            sha: file_commit
+                .content
+                .map(|content| content.sha)
+                .unwrap_or_else(|| blob_sha(content)),
+            commit_sha: file_commit.commit.sha,
+        })
+    }
+}
 
-        Err(AppError::GithubAPIError(response.text().await?))
+// Responses differ by user, so the key has a digest of the access token.
+fn cache_key(request: &Request) -> String {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .map(|value| value.as_bytes())
+        .unwrap_or_default();
+
+    format!(
+        "{} {}",
+        request.url(),
+        hex::encode(digest(&SHA256, authorization))
+    )
+}
+
+// How long to wait before the request could be repeated, `None` unless it is rate limited.
+fn rate_limit_wait(response: &GitHubResponse) -> Option<Duration> {
+    if response.status != StatusCode::FORBIDDEN && response.status != StatusCode::TOO_MANY_REQUESTS {
+        return None;
+    }
+
+    let number = |name: &str| {
+        response
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+    };
+
+    if let Some(seconds) = number("retry-after") {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    if number("x-ratelimit-remaining") == Some(0) {
+        let reset = number("x-ratelimit-reset")?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs();
+
+        return Some(Duration::from_secs(reset.saturating_sub(now).max(1)));
     }
+
+    let message = String::from_utf8_lossy(&response.body).to_lowercase();
+    message
+        .contains("secondary rate limit")
+        .then_some(SECONDARY_RATE_LIMIT_WAIT)
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500 << attempt)
+}
+
+// URL of the next page from the `Link` header.
+fn next_page(headers: &header::HeaderMap) -> Option<Url> {
+    let link = headers.get(header::LINK)?.to_str().ok()?;
+
+    link.split(',').find_map(|part| {
+        let (url, params) = part.split_once(';')?;
+        let is_next = params.split(';').any(|param| param.trim() == "rel=\"next\"");
+
+        is_next
+            .then(|| url.trim().trim_start_matches('<').trim_end_matches('>'))
+            .and_then(|url| Url::parse(url).ok())
+    })
+}
+
+/// Base URL of the REST API from `GITHUB_API_URL`, e.g. `https://github.example.com/api/v3`
+/// for GitHub Enterprise Server or the URL of a local mock server.
+pub fn api_base_url() -> Result<Url> {
+    base_url("GITHUB_API_URL", DEFAULT_API_URL)
+}
+
+/// Base URL of the web pages and OAuth from `GITHUB_URL`.
+pub fn web_base_url() -> Result<Url> {
+    base_url("GITHUB_URL", DEFAULT_WEB_URL)
+}
+
+fn base_url(name: &str, default: &str) -> Result<Url> {
+    let url = env::var(name).unwrap_or_else(|_| default.to_string());
+
+    Url::parse(&url).map_err(|e| AppError::UrlParse(format!("{name}: {e}")))
+}
+
+/// URL of the path under the base URL. The path of the base URL is kept,
+/// so the API of GitHub Enterprise Server is requested under `/api/v3`.
+pub fn url_with_path(base_url: &Url, path: &str) -> Url {
+    let mut url = base_url.to_owned();
+    url.set_path(&format!("{}{path}", base_url.path().trim_end_matches('/')));
+
+    url
+}
+
+/// Secret of the GitHub webhook read from `GITHUB_WEBHOOK_SECRET`.
+pub struct WebhookSecret(hmac::Key);
+
+impl WebhookSecret {
+    /// `None` when the secret is not set, webhook deliveries are not accepted then.
+    pub fn from_env() -> Option<Self> {
+        let secret = env::var("GITHUB_WEBHOOK_SECRET").ok().filter(|secret| !secret.is_empty())?;
+
+        Some(WebhookSecret(hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())))
+    }
+
+    /// Checks `X-Hub-Signature-256` header value of the delivery body.
+    pub fn verify(&self, signature: &str, body: &[u8]) -> Result<()> {
+        let signature = signature
+            .strip_prefix("sha256=")
+            .and_then(|s| hex::decode(s).ok())
+            .ok_or(AppError::AuthError)?;
+
+        hmac::verify(&self.0, body, &signature).map_err(|_| AppError::AuthError)
+    }
+}
+
+/// SHA of the git blob with the content, as GitHub reports it for files.
+pub fn blob_sha(content: &[u8]) -> String {
+    hex::encode(object_id("blob", content))
+}
+
+/// SHA of the git tree of a directory with the files, as GitHub reports it for directories.
+/// Paths of the files are relative to the directory.
+pub fn tree_sha(files: &[(String, String)]) -> String {
+    let files: Vec<(&str, &[u8])> = files
+        .iter()
+        .map(|(path, content)| (path.as_str(), content.as_bytes()))
+        .collect();
+
+    hex::encode(tree_id(&files))
+}
+
+fn tree_id(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut subtrees: BTreeMap<&str, Vec<(&str, &[u8])>> = BTreeMap::new();
+    // Entries are (sort key, mode, name, object id), git sorts directories as if they end with `/`.
+    let mut entries: Vec<(String, &str, &str, Vec<u8>)> = Vec::new();
+
+    for (path, content) in files {
+        match path.split_once('/') {
+            Some((dir, rest)) => subtrees.entry(dir).or_default().push((rest, content)),
+            None => entries.push((path.to_string(), "100644", path, object_id("blob", content))),
+        }
+    }
+
+    for (dir, files) in subtrees {
+        entries.push((format!("{dir}/"), "40000", dir, tree_id(&files)));
+    }
+
+    entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+    let mut tree = Vec::new();
+    for (_, mode, name, id) in entries {
+        tree.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+        tree.extend_from_slice(&id);
+    }
+
+    object_id("tree", &tree)
+}
+
+fn object_id(kind: &str, content: &[u8]) -> Vec<u8> {
+    let mut object = format!("{kind} {}\0", content.len()).into_bytes();
+    object.extend_from_slice(content);
+
+    digest(&SHA1_FOR_LEGACY_USE_ONLY, &object).as_ref().to_vec()
+}
+
+pub fn encode_base64(content: &[u8]) -> String {
+    general_purpose::STANDARD.encode(content)
+}
+
+// GitHub splits base64 content into lines.
+fn decode_base64(content: &str) -> Result<Vec<u8>> {
+    let content: String = content.split_whitespace().collect();
+
+    general_purpose::STANDARD
+        .decode(content)
+        .map_err(|e| AppError::GithubAPIError(e.to_string()))
 }
@@ -0,0 +1,129 @@
+use crate::{errors::AppError, models::Result};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+
+/// GitHub accepts app JWTs which expire in at most 10 minutes.
+const JWT_EXPIRES_IN: i64 = 9 * 60;
+/// JWTs are issued in the past to allow for clock drift.
+const JWT_CLOCK_DRIFT: i64 = 60;
+/// Installation tokens are renewed this long before they expire.
+const TOKEN_RENEW_BEFORE: i64 = 5 * 60;
+/// Accounts without the app installed are checked again after this long.
+const NOT_INSTALLED_EXPIRES_IN: i64 = 60;
+
+#[derive(Serialize, Debug)]
+struct AppClaims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct Installation {
+    pub id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Token of the installation on an account, `None` when the app is not installed on it.
+struct CachedToken {
+    token: Option<String>,
+    expires_at: DateTime<Utc>,
+}
+
+/// GitHub App the server authenticates as for repository operations.
+///
+/// The app is read from `GITHUB_APP_ID` and `GITHUB_APP_PRIVATE_KEY`, the PEM encoded
+/// private key of the app, where newlines may be escaped as `\n`.
+/// Installation tokens are cached by the account until they are about to expire.
+pub struct GitHubApp {
+    app_id: String,
+    key: EncodingKey,
+    tokens: Mutex<HashMap<String, CachedToken>>,
+}
+
+impl GitHubApp {
+    /// `None` when the app is not configured, empty variables are treated as unset.
+    pub fn from_env() -> Result<Option<Self>> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.is_empty());
+
+        let (app_id, private_key) = match (var("GITHUB_APP_ID"), var("GITHUB_APP_PRIVATE_KEY")) {
+            (Some(app_id), Some(private_key)) => (app_id, private_key),
+            (None, None) => return Ok(None),
+            _ => {
+                return Err(AppError::GithubAuthError(
+                    "GITHUB_APP_ID and GITHUB_APP_PRIVATE_KEY must be present together.".to_string(),
+                ))
+            }
+        };
+
+        let key = EncodingKey::from_rsa_pem(private_key.replace("\\n", "\n").as_bytes())
+            .map_err(|e| AppError::CryptoError(format!("GITHUB_APP_PRIVATE_KEY: {e}")))?;
+
+        Ok(Some(GitHubApp {
+            app_id,
+            key,
+            tokens: Mutex::new(HashMap::new()),
+        }))
+    }
+
+    /// JWT the app authenticates with to get its installations and their tokens.
+    pub fn jwt(&self) -> Result<String> {
+        let now = Utc::now().timestamp();
+        let claims = AppClaims {
+            iat: now - JWT_CLOCK_DRIFT,
+            exp: now + JWT_EXPIRES_IN,
+            iss: self.app_id.clone(),
+        };
+
+        encode(&Header::new(Algorithm::RS256), &claims, &self.key)
+            .map_err(|e| AppError::CryptoError(e.to_string()))
+    }
+
+    /// Cached token of the installation on the account, `Some(None)` when it is known
+    /// that the app is not installed, and `None` when the installation should be looked up.
+    pub fn cached_token(&self, account: &str) -> Option<Option<String>> {
+        let tokens = self.tokens.lock().expect("Token cache lock is poisoned");
+
+        tokens
+            .get(&account.to_lowercase())
+            .filter(|cached| cached.expires_at > Utc::now())
+            .map(|cached| cached.token.clone())
+    }
+
+    pub fn cache_token(&self, account: &str, token: &InstallationToken) {
+        self.cache(
+            account,
+            CachedToken {
+                token: Some(token.token.clone()),
+                expires_at: token.expires_at - Duration::seconds(TOKEN_RENEW_BEFORE),
+            },
+        );
+    }
+
+    pub fn cache_not_installed(&self, account: &str) {
+        self.cache(
+            account,
+            CachedToken {
+                token: None,
+                expires_at: Utc::now() + Duration::seconds(NOT_INSTALLED_EXPIRES_IN),
+            },
+        );
+    }
+
+    // Account logins are case-insensitive.
+    fn cache(&self, account: &str, cached: CachedToken) {
+        let mut tokens = self.tokens.lock().expect("Token cache lock is poisoned");
+
+        tokens.retain(|_, cached| cached.expires_at > Utc::now());
+        tokens.insert(account.to_lowercase(), cached);
+    }
+}
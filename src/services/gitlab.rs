@@ -0,0 +1,417 @@
+use crate::{
+    errors::AppError,
+    models::{
+        repositories::{Provider, RepositoryOwner},
+        users::{self, TokenData},
+        Result,
+    },
+    services::{
+        github,
+        vcs::{CommittedFile, RepoFile, VcsProvider, VcsUser},
+    },
+};
+use async_trait::async_trait;
+use base64::{engine::general_purpose, Engine as _};
+use reqwest::*;
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::env;
+
+/// Base URL of GitLab unless `GITLAB_URL` is set, e.g. for a self-managed instance.
+const DEFAULT_URL: &str = "https://gitlab.com";
+/// Branch of new repositories which don't have a default branch yet.
+const DEFAULT_BRANCH: &str = "main";
+
+#[derive(Serialize, Debug)]
+struct AccessTokenParams<'a> {
+    client_id: &'a str,
+    client_secret: &'a str,
+    grant_type: &'a str,
+    code: Option<&'a str>,
+    refresh_token: Option<&'a str>,
+    redirect_uri: Option<&'a str>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AccessTokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: Option<i32>,
+    refresh_token: String,
+    #[serde(default)]
+    scope: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct User {
+    id: i64,
+    username: String,
+    name: Option<String>,
+    /// Primary email, present with `read_user` scope.
+    email: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Namespace {
+    id: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct Project {
+    web_url: String,
+    /// `None` for empty repositories.
+    default_branch: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct File {
+    /// SHA of the git blob of the file.
+    blob_id: String,
+    last_commit_id: String,
+    encoding: String,
+    content: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct Commit {
+    id: String,
+}
+
+/// Client of the GitLab REST API and OAuth application.
+///
+/// The OAuth application is read from `GITLAB_CLIENT_ID`, `GITLAB_CLIENT_SECRET`
+/// and `GITLAB_REDIRECT_URI`, which GitLab requires to match the authorization request.
+pub struct GitLabAPI {
+    client: Client,
+    base_url: Url,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+    redirect_uri: Option<String>,
+}
+
+impl GitLabAPI {
+    pub fn new() -> Result<Self> {
+        let url = env::var("GITLAB_URL").unwrap_or_else(|_| DEFAULT_URL.to_string());
+        let base_url = Url::parse(&url).map_err(|e| AppError::UrlParse(format!("GITLAB_URL: {e}")))?;
+        let client = reqwest::Client::builder()
+            .user_agent("UnielitServer")
+            .build()?;
+
+        Ok(GitLabAPI {
+            client,
+            base_url,
+            client_id: env::var("GITLAB_CLIENT_ID").ok(),
+            client_secret: env::var("GITLAB_CLIENT_SECRET").ok(),
+            redirect_uri: env::var("GITLAB_REDIRECT_URI").ok(),
+        })
+    }
+
+    async fn request_access_token(
+        &self,
+        grant_type: &str,
+        code: Option<&str>,
+        refresh_token: Option<&str>,
+    ) -> Result<TokenData> {
+        let (Some(client_id), Some(client_secret)) = (&self.client_id, &self.client_secret) else {
+            return Err(AppError::GitlabAuthError(
+                "GITLAB_CLIENT_ID and GITLAB_CLIENT_SECRET must be present.".to_string(),
+            ));
+        };
+        let params = AccessTokenParams {
+            client_id,
+            client_secret,
+            grant_type,
+            code,
+            refresh_token,
+            redirect_uri: self.redirect_uri.as_deref(),
+        };
+
+        let response = self
+            .client
+            .post(github::url_with_path(&self.base_url, "/oauth/token"))
+            .form(&params)
+            .send()
+            .await?;
+        let status = response.status();
+        let body = response.text().await?;
+
+        if !status.is_success() {
+            return Err(AppError::GitlabAuthError(body));
+        }
+
+        let token: AccessTokenResponse =
+            serde_json::from_str(&body).map_err(|e| AppError::GitlabAuthError(e.to_string()))?;
+
+        Ok(TokenData {
+            access_token: token.access_token,
+            expires_in: token.expires_in.unwrap_or_default(),
+            refresh_token: token.refresh_token,
+            // GitLab refresh tokens don't expire, they are replaced when used.
+            refresh_token_expires_in: 0,
+            scope: token.scope,
+            token_type: token.token_type,
+        })
+    }
+
+    async fn get_current_user(&self, token: &str) -> Result<User> {
+        let url = self.api_url(&["user"])?;
+
+        self.send(self.request(Method::GET, url, token))
+            .await?
+            .ok_or_else(|| AppError::GitlabAuthError("User is not found".to_string()))
+    }
+
+    async fn get_project(&self, token: &str, repo_owner: &RepositoryOwner) -> Result<Project> {
+        let url = self.api_url(&["projects", &project_id(repo_owner)])?;
+
+        self.send(self.request(Method::GET, url, token))
+            .await?
+            .ok_or_else(|| {
+                AppError::InvalidInput(format!(
+                    "Repository {}/{} is not found on Gitlab",
+                    repo_owner.owner, repo_owner.name
+                ))
+            })
+    }
+
+    async fn default_branch(&self, token: &str, repo_owner: &RepositoryOwner) -> Result<String> {
+        let project = self.get_project(token, repo_owner).await?;
+
+        Ok(project.default_branch.unwrap_or_else(|| DEFAULT_BRANCH.to_string()))
+    }
+
+    async fn get_repository_file(
+        &self,
+        token: &str,
+        repo_owner: &RepositoryOwner,
+        path: &str,
+        reference: &str,
+    ) -> Result<Option<File>> {
+        let url = self.api_url(&["projects", &project_id(repo_owner), "repository", "files", path])?;
+
+        self.send(self.request(Method::GET, url, token).query(&[("ref", reference)]))
+            .await
+    }
+
+    // URL of the API path, segments are percent-encoded as GitLab expects for paths in segments.
+    fn api_url(&self, segments: &[&str]) -> Result<Url> {
+        let mut url = github::url_with_path(&self.base_url, "/api/v4");
+        url.path_segments_mut()
+            .map_err(|_| AppError::UrlParse("GITLAB_URL must be a base URL".to_string()))?
+            .extend(segments);
+
+        Ok(url)
+    }
+
+    fn request(&self, method: Method, url: Url, token: &str) -> RequestBuilder {
+        self.client.request(method, url).bearer_auth(token)
+    }
+
+    // Body of a successful response, `None` for 404 Not Found.
+    async fn send<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<Option<T>> {
+        let response = request.send().await?;
+        let status = response.status();
+        let body = response.bytes().await?;
+
+        if status == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        if !status.is_success() {
+            return Err(status_error(status, &body));
+        }
+
+        serde_json::from_slice(&body)
+            .map(Some)
+            .map_err(|e| AppError::GitlabAPIError(e.to_string()))
+    }
+}
+
+#[async_trait(?Send)]
+impl VcsProvider for GitLabAPI {
+    fn provider(&self) -> Provider {
+        Provider::GitLab
+    }
+
+    async fn generate_access_token(&self, code: String) -> Result<TokenData> {
+        self.request_access_token("authorization_code", Some(&code), None)
+            .await
+    }
+
+    async fn refresh_access_token(&self, refresh_token: String) -> Result<TokenData> {
+        self.request_access_token("refresh_token", None, Some(&refresh_token))
+            .await
+    }
+
+    async fn get_user(&self, token: &str) -> Result<VcsUser> {
+        let user = self.get_current_user(token).await?;
+
+        Ok(VcsUser {
+            id: user.id.to_string(),
+            login: user.username,
+            name: user.name,
+        })
+    }
+
+    async fn get_primary_email(&self, token: &str) -> Result<String> {
+        self.get_current_user(token)
+            .await?
+            .email
+            .ok_or_else(|| AppError::GitlabAuthError("Primary email is not available, read_user scope is required".to_string()))
+    }
+
+    /// Parameters are the attributes of the new project. Repositories of organizations
+    /// are created in the group or the namespace of the owner.
+    async fn create_repo(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        params: Value,
+    ) -> Result<String> {
+        let mut body = match params {
+            Value::Object(params) => params,
+            _ => Map::new(),
+        };
+        body.insert("name".to_string(), json!(repo_owner.name));
+        body.insert("path".to_string(), json!(repo_owner.name));
+
+        if repo_owner.is_organization {
+            let url = self.api_url(&["namespaces", &repo_owner.owner])?;
+            let namespace: Namespace = self
+                .send(self.request(Method::GET, url, token))
+                .await?
+                .ok_or_else(|| AppError::InvalidInput(format!("Gitlab namespace {} is not found", repo_owner.owner)))?;
+
+            body.insert("namespace_id".to_string(), json!(namespace.id));
+        }
+
+        let url = self.api_url(&["projects"])?;
+        let project: Project = self
+            .send(self.request(Method::POST, url, token).json(&body))
+            .await?
+            .ok_or_else(|| AppError::GitlabAPIError("Project could not be created".to_string()))?;
+
+        Ok(project.web_url)
+    }
+
+    async fn archive_repo(&self, token: &str, repo_owner: RepositoryOwner) -> Result<()> {
+        let url = self.api_url(&["projects", &project_id(&repo_owner), "archive"])?;
+
+        self.send::<Value>(self.request(Method::POST, url, token))
+            .await?
+            .map(|_| ())
+            .ok_or_else(|| {
+                AppError::InvalidInput(format!(
+                    "Repository {}/{} is not found on Gitlab",
+                    repo_owner.owner, repo_owner.name
+                ))
+            })
+    }
+
+    async fn get_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: Option<&str>,
+    ) -> Result<Option<RepoFile>> {
+        let reference = match reference {
+            Some(reference) => reference.to_owned(),
+            None => self.default_branch(token, &repo_owner).await?,
+        };
+        let Some(file) = self
+            .get_repository_file(token, &repo_owner, path, &reference)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        if file.encoding != "base64" {
+            return Err(AppError::GitlabAPIError(format!("Unexpected encoding {}", file.encoding)));
+        }
+
+        let content = general_purpose::STANDARD
+            .decode(&file.content)
+            .map_err(|e| AppError::GitlabAPIError(e.to_string()))?;
+
+        Ok(Some(RepoFile {
+            sha: file.blob_id,
+            content,
+        }))
+    }
+
+    /// GitLab doesn't check the blob SHA of the replaced file, so it is compared here
+    /// and the file is updated from the last commit which has changed it.
+    async fn commit_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        message: &str,
+        content: &[u8],
+        sha: Option<&str>,
+        branch: Option<&str>,
+        _author: &users::User,
+    ) -> Result<CommittedFile> {
+        let branch = match branch {
+            Some(branch) => branch.to_owned(),
+            None => self.default_branch(token, &repo_owner).await?,
+        };
+        let current = self
+            .get_repository_file(token, &repo_owner, path, &branch)
+            .await?;
+
+        if current.as_ref().map(|file| file.blob_id.as_str()) != sha {
+            return Err(AppError::RepoFileConflict(format!(
+                "{path} has changed since the last save or pull"
+            )));
+        }
+
+        let mut action = json!({
+            "action": "create",
+            "file_path": path,
+            "content": github::encode_base64(content),
+            "encoding": "base64",
+        });
+        if let Some(file) = current {
+            action["action"] = json!("update");
+            action["last_commit_id"] = json!(file.last_commit_id);
+        }
+
+        let url = self.api_url(&["projects", &project_id(&repo_owner), "repository", "commits"])?;
+        let body = json!({
+            "branch": branch,
+            "commit_message": message,
+            "actions": [action],
+        });
+
+        let commit = self
+            .send::<Commit>(self.request(Method::POST, url, token).json(&body))
+            .await?
+            .ok_or_else(|| AppError::InvalidInput(format!("Branch {branch} is not found")))?;
+
+        Ok(CommittedFile {
+            sha: github::blob_sha(content),
+            commit_sha: commit.id,
+        })
+    }
+}
+
+// Projects are addressed by their full path, it is a single segment of the URL.
+fn project_id(repo_owner: &RepositoryOwner) -> String {
+    format!("{}/{}", repo_owner.owner, repo_owner.name)
+}
+
+// GitLab messages are strings, or objects of messages by the field for validation errors.
+fn status_error(status: StatusCode, body: &[u8]) -> AppError {
+    let message = match serde_json::from_slice::<Value>(body) {
+        Ok(error) => match error.get("message").or_else(|| error.get("error")) {
+            Some(Value::String(message)) => message.to_owned(),
+            Some(message) => message.to_string(),
+            None => error.to_string(),
+        },
+        Err(_) => String::from_utf8_lossy(body).into_owned(),
+    };
+
+    AppError::GitlabStatusError(status.as_u16(), message)
+}
@@ -0,0 +1,316 @@
+use crate::{
+    errors::AppError,
+    models::{
+        repositories::{Provider, RepositoryOwner},
+        users::{TokenData, User},
+        Result,
+    },
+    services::vcs::{CommittedFile, RepoFile, VcsProvider, VcsUser},
+};
+use actix_web::web;
+use async_trait::async_trait;
+use url::Url;
+use uuid::Uuid;
+
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Directory of the repositories unless `LOCAL_REPOS_DIR` is set.
+const DEFAULT_REPOS_DIR: &str = "repos";
+/// Branch of new repositories.
+const DEFAULT_BRANCH: &str = "main";
+
+/// Bare git repositories on the disk of the server, at `<owner>/<name>.git`
+/// under `LOCAL_REPOS_DIR`.
+///
+/// The server reads and commits the files itself with the `git` command,
+/// so there are no tokens, and commits are authored by the user who saves the design.
+/// Like the repositories of the other providers, a repository is created per repository
+/// record and could be linked to several projects. The history of the designs and its
+/// restore are the design revisions, the git history is not read back.
+pub struct LocalGit {
+    root: PathBuf,
+}
+
+impl LocalGit {
+    pub fn new() -> Self {
+        let root = env::var("LOCAL_REPOS_DIR").unwrap_or_else(|_| DEFAULT_REPOS_DIR.to_string());
+
+        LocalGit { root: root.into() }
+    }
+
+    // Owner and name are single path segments, so repositories stay under the root.
+    fn repo_dir(&self, repo_owner: &RepositoryOwner) -> Result<GitDir> {
+        for segment in [&repo_owner.owner, &repo_owner.name] {
+            let invalid = segment.is_empty()
+                || segment.starts_with('.')
+                || segment.starts_with('-')
+                || segment.contains(['/', '\\']);
+
+            if invalid {
+                return Err(AppError::InvalidInput(format!(
+                    "{segment} is not a valid name of a local repository or its owner"
+                )));
+            }
+        }
+
+        Ok(GitDir(
+            self.root
+                .join(&repo_owner.owner)
+                .join(format!("{}.git", repo_owner.name)),
+        ))
+    }
+}
+
+#[async_trait(?Send)]
+impl VcsProvider for LocalGit {
+    fn provider(&self) -> Provider {
+        Provider::Local
+    }
+
+    async fn generate_access_token(&self, _code: String) -> Result<TokenData> {
+        Err(no_sign_in())
+    }
+
+    async fn refresh_access_token(&self, _refresh_token: String) -> Result<TokenData> {
+        Err(no_sign_in())
+    }
+
+    async fn get_user(&self, _token: &str) -> Result<VcsUser> {
+        Err(no_sign_in())
+    }
+
+    async fn get_primary_email(&self, _token: &str) -> Result<String> {
+        Err(no_sign_in())
+    }
+
+    /// Parameters are ignored, the repository is initialized with an unborn default branch.
+    async fn create_repo(
+        &self,
+        _token: &str,
+        repo_owner: RepositoryOwner,
+        _params: serde_json::Value,
+    ) -> Result<String> {
+        let dir = self.repo_dir(&repo_owner)?;
+
+        web::block(move || {
+            if dir.0.exists() {
+                return Err(AppError::InvalidInput(format!(
+                    "Local repository {}/{} already exists",
+                    repo_owner.owner, repo_owner.name
+                )));
+            }
+
+            let path = dir.0.to_string_lossy();
+            run(
+                Command::new("git").args(["init", "--bare", "--initial-branch", DEFAULT_BRANCH, &path]),
+                None,
+            )?;
+
+            let path = dir.0.canonicalize().map_err(|e| AppError::LocalGitError(e.to_string()))?;
+            Url::from_file_path(&path)
+                .map(String::from)
+                .map_err(|_| AppError::LocalGitError(format!("{} is not a valid URL", path.display())))
+        })
+        .await?
+    }
+
+    async fn archive_repo(&self, _token: &str, _repo_owner: RepositoryOwner) -> Result<()> {
+        Err(AppError::InvalidInput(
+            "Local repositories can't be archived".to_string(),
+        ))
+    }
+
+    async fn get_file(
+        &self,
+        _token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: Option<&str>,
+    ) -> Result<Option<RepoFile>> {
+        let dir = self.repo_dir(&repo_owner)?;
+        let object = format!("{}:{path}", reference.unwrap_or("HEAD"));
+
+        web::block(move || {
+            let Some(sha) = dir.rev_parse(&object)? else {
+                return Ok(None);
+            };
+            let content = run(dir.git().args(["cat-file", "blob", &sha]), None)?;
+
+            Ok(Some(RepoFile { sha, content }))
+        })
+        .await?
+    }
+
+    /// The file is committed on top of the branch with a temporary index, and the branch
+    /// is moved only if it still points to the commit the file has been checked against.
+    async fn commit_file(
+        &self,
+        _token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        message: &str,
+        content: &[u8],
+        sha: Option<&str>,
+        branch: Option<&str>,
+        author: &User,
+    ) -> Result<CommittedFile> {
+        let dir = self.repo_dir(&repo_owner)?;
+        let (path, message, content) = (path.to_owned(), message.to_owned(), content.to_vec());
+        let (sha, branch) = (sha.map(str::to_owned), branch.map(str::to_owned));
+        let (name, email) = (author.name.clone(), author.email.clone());
+
+        web::block(move || {
+            let branch = match branch {
+                Some(branch) => branch,
+                None => text(run(dir.git().args(["symbolic-ref", "--short", "HEAD"]), None)?),
+            };
+            let reference = format!("refs/heads/{branch}");
+            run(Command::new("git").args(["check-ref-format", &reference]), None)
+                .map_err(|_| AppError::InvalidInput(format!("{branch} is not a valid branch name")))?;
+
+            let head = dir.rev_parse(&format!("{reference}^{{commit}}"))?;
+            let current = match &head {
+                Some(head) => dir.rev_parse(&format!("{head}:{path}"))?,
+                None => None,
+            };
+
+            if current != sha {
+                return Err(AppError::RepoFileConflict(format!(
+                    "{path} has changed since the last save or pull"
+                )));
+            }
+
+            let blob = text(run(dir.git().args(["hash-object", "-w", "--stdin"]), Some(&content))?);
+            let tree = dir.write_tree(head.as_deref(), &path, &blob)?;
+
+            let mut commit_tree = dir.git();
+            commit_tree.args(["commit-tree", &tree, "-m", &message]);
+            if let Some(head) = &head {
+                commit_tree.args(["-p", head]);
+            }
+            for (variable, value) in [
+                ("GIT_AUTHOR_NAME", &name),
+                ("GIT_AUTHOR_EMAIL", &email),
+                ("GIT_COMMITTER_NAME", &name),
+                ("GIT_COMMITTER_EMAIL", &email),
+            ] {
+                commit_tree.env(variable, value);
+            }
+            let commit = text(run(&mut commit_tree, None)?);
+
+            // An empty old value requires the branch not to exist yet.
+            run(
+                dir.git()
+                    .args(["update-ref", &reference, &commit, head.as_deref().unwrap_or("")]),
+                None,
+            )
+            .map_err(|_| AppError::RepoFileConflict(format!("Branch {branch} has moved during the commit")))?;
+
+            Ok(CommittedFile {
+                sha: blob,
+                commit_sha: commit,
+            })
+        })
+        .await?
+    }
+}
+
+struct GitDir(PathBuf);
+
+impl GitDir {
+    fn git(&self) -> Command {
+        let mut command = Command::new("git");
+        command.arg("--git-dir").arg(&self.0);
+
+        command
+    }
+
+    // SHA of the object, `None` if there is no such object.
+    // The object is never read as an option, whatever the reference it starts with.
+    fn rev_parse(&self, object: &str) -> Result<Option<String>> {
+        if !self.0.exists() {
+            return Err(AppError::InvalidInput(format!(
+                "Local repository {} is not found",
+                self.0.display()
+            )));
+        }
+
+        let output = self
+            .git()
+            .args(["rev-parse", "--verify", "--quiet", "--end-of-options", object])
+            .output()
+            .map_err(|e| AppError::LocalGitError(e.to_string()))?;
+
+        Ok(output.status.success().then(|| text(output.stdout)))
+    }
+
+    // Tree of the commit with the blob at the path.
+    fn write_tree(&self, head: Option<&str>, path: &str, blob: &str) -> Result<String> {
+        let index = IndexFile(self.0.join(format!("index-{}", Uuid::new_v4().simple())));
+        let with_index = |args: &[&str]| {
+            let mut command = self.git();
+            command.env("GIT_INDEX_FILE", &index.0).args(args);
+
+            run(&mut command, None)
+        };
+
+        match head {
+            Some(head) => with_index(&["read-tree", head])?,
+            None => with_index(&["read-tree", "--empty"])?,
+        };
+        with_index(&["update-index", "--add", "--cacheinfo", &format!("100644,{blob},{path}")])?;
+
+        with_index(&["write-tree"]).map(text)
+    }
+}
+
+// Index file which is removed when it is dropped.
+struct IndexFile(PathBuf);
+
+impl Drop for IndexFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+// Output of the command, fails with its error output unless it has succeeded.
+fn run(command: &mut Command, input: Option<&[u8]>) -> Result<Vec<u8>> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| AppError::LocalGitError(e.to_string()))?;
+
+    if let Some(input) = input {
+        child
+            .stdin
+            .take()
+            .expect("Stdin is piped")
+            .write_all(input)
+            .map_err(|e| AppError::LocalGitError(e.to_string()))?;
+    }
+
+    let output = child
+        .wait_with_output()
+        .map_err(|e| AppError::LocalGitError(e.to_string()))?;
+
+    if !output.status.success() {
+        return Err(AppError::LocalGitError(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+fn text(output: Vec<u8>) -> String {
+    String::from_utf8_lossy(&output).trim().to_string()
+}
+
+fn no_sign_in() -> AppError {
+    AppError::InvalidInput("Users can't sign in with local repositories".to_string())
+}
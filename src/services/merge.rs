@@ -0,0 +1,238 @@
+use serde_json::{Map, Value};
+use utoipa::ToSchema;
+
+use std::collections::HashSet;
+
+/// Value which both sides of a merge have changed differently.
+#[derive(Serialize, ToSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeConflict {
+    /// JSON pointer (RFC 6901) to the value, in `ours` when the value is there, in `theirs` otherwise.
+    pub pointer: String,
+    /// Value in the common base, `null` when it has been added since or the base is unknown.
+    pub base: Option<Value>,
+    /// Value in the design being saved, `null` when it has been removed.
+    pub ours: Option<Value>,
+    /// Value in the design file, `null` when it has been removed.
+    pub theirs: Option<Value>,
+}
+
+/// Structural three-way merge of two documents changed independently since `base`.
+///
+/// A value changed on one side only takes the change. Objects are merged by key.
+/// Arrays of objects with distinct `id`s are merged by the ids, in the order of `ours`
+/// followed by the objects only `theirs` has added. Other arrays are merged by index
+/// when neither side has changed their length. Values both sides have changed differently
+/// are conflicts, and the document is merged only if there are none.
+pub fn merge(base: &Value, ours: &Value, theirs: &Value) -> Result<Value, Vec<MergeConflict>> {
+    let mut conflicts = Vec::new();
+    let merged = merge_value(Some(base), Some(ours), Some(theirs), &mut Vec::new(), &mut conflicts);
+
+    match (merged, conflicts.is_empty()) {
+        (Some(merged), true) => Ok(merged),
+        _ => Err(conflicts),
+    }
+}
+
+// Merged value, `None` when it is removed. Conflicting values are reported and `ours` is kept.
+fn merge_value(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Value> {
+    if ours == theirs || theirs == base {
+        return ours.cloned();
+    }
+    if ours == base {
+        return theirs.cloned();
+    }
+
+    match (base, ours, theirs) {
+        // Objects added on both sides are merged as if they have been empty.
+        (None | Some(Value::Object(_)), Some(Value::Object(ours)), Some(Value::Object(theirs))) => {
+            let empty = Map::new();
+            let base = base.and_then(Value::as_object).unwrap_or(&empty);
+
+            Some(Value::Object(merge_objects(base, ours, theirs, path, conflicts)))
+        }
+        (Some(Value::Array(base_values)), Some(Value::Array(our_values)), Some(Value::Array(their_values))) => {
+            match merge_arrays(base_values, our_values, their_values, path, conflicts) {
+                Some(merged) => Some(Value::Array(merged)),
+                None => conflict(base, ours, theirs, path, conflicts),
+            }
+        }
+        _ => conflict(base, ours, theirs, path, conflicts),
+    }
+}
+
+fn merge_objects(
+    base: &Map<String, Value>,
+    ours: &Map<String, Value>,
+    theirs: &Map<String, Value>,
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Map<String, Value> {
+    let keys: Vec<&String> = ours
+        .keys()
+        .chain(theirs.keys().filter(|key| !ours.contains_key(*key)))
+        .collect();
+    let mut merged = Map::new();
+
+    for key in keys {
+        path.push(key.to_owned());
+        if let Some(value) = merge_value(base.get(key), ours.get(key), theirs.get(key), path, conflicts) {
+            merged.insert(key.to_owned(), value);
+        }
+        path.pop();
+    }
+
+    merged
+}
+
+// `None` when the arrays can't be merged element by element.
+fn merge_arrays(
+    base: &[Value],
+    ours: &[Value],
+    theirs: &[Value],
+    path: &mut Vec<String>,
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Vec<Value>> {
+    if let (Some(base_ids), Some(our_ids), Some(their_ids)) = (entity_ids(base), entity_ids(ours), entity_ids(theirs)) {
+        let find = |values: &[Value], ids: &[&Value], id: &Value| -> Option<Value> {
+            ids.iter().position(|other| *other == id).map(|index| values[index].clone())
+        };
+        let mut merged = Vec::with_capacity(ours.len());
+
+        for (index, (id, value)) in our_ids.iter().zip(ours).enumerate() {
+            path.push(index.to_string());
+            let base_value = find(base, &base_ids, id);
+            let their_value = find(theirs, &their_ids, id);
+            merged.extend(merge_value(base_value.as_ref(), Some(value), their_value.as_ref(), path, conflicts));
+            path.pop();
+        }
+
+        for (index, (id, value)) in their_ids.iter().zip(theirs).enumerate() {
+            if our_ids.contains(id) {
+                continue;
+            }
+
+            path.push(index.to_string());
+            let base_value = find(base, &base_ids, id);
+            merged.extend(merge_value(base_value.as_ref(), None, Some(value), path, conflicts));
+            path.pop();
+        }
+
+        return Some(merged);
+    }
+
+    if base.len() != ours.len() || base.len() != theirs.len() {
+        return None;
+    }
+
+    let mut merged = Vec::with_capacity(ours.len());
+    for (index, ((base, ours), theirs)) in base.iter().zip(ours).zip(theirs).enumerate() {
+        path.push(index.to_string());
+        merged.extend(merge_value(Some(base), Some(ours), Some(theirs), path, conflicts));
+        path.pop();
+    }
+
+    Some(merged)
+}
+
+// Ids of the objects, `None` unless all of them have a distinct string or number `id`.
+fn entity_ids(values: &[Value]) -> Option<Vec<&Value>> {
+    let ids: Vec<&Value> = values
+        .iter()
+        .map(|value| value.get("id").filter(|id| id.is_string() || id.is_number()))
+        .collect::<Option<_>>()?;
+    let distinct: HashSet<String> = ids.iter().map(|id| id.to_string()).collect();
+
+    (distinct.len() == ids.len()).then_some(ids)
+}
+
+fn conflict(
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    path: &[String],
+    conflicts: &mut Vec<MergeConflict>,
+) -> Option<Value> {
+    conflicts.push(MergeConflict {
+        pointer: path
+            .iter()
+            .map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1")))
+            .collect(),
+        base: base.cloned(),
+        ours: ours.cloned(),
+        theirs: theirs.cloned(),
+    });
+
+    ours.cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn merges_edits_of_disjoint_keys() {
+        let base = json!({ "name": "design", "options": { "a": 1, "b": 2 } });
+        let ours = json!({ "name": "renamed", "options": { "a": 1, "b": 2 } });
+        let theirs = json!({ "name": "design", "options": { "a": 10, "c": 3 } });
+
+        assert_eq!(
+            merge(&base, &ours, &theirs),
+            Ok(json!({ "name": "renamed", "options": { "a": 10, "c": 3 } }))
+        );
+    }
+
+    #[test]
+    fn merges_arrays_of_entities_by_id() {
+        let base = json!([{ "id": "a", "x": 1 }, { "id": "b", "x": 2 }, { "id": "c", "x": 3 }]);
+        // Ours removes `a`, modifies `c` and adds `d`, theirs modifies `b` and adds `e` first.
+        let ours = json!([{ "id": "b", "x": 2 }, { "id": "c", "x": 30 }, { "id": "d", "x": 4 }]);
+        let theirs = json!([
+            { "id": "e", "x": 5 },
+            { "id": "a", "x": 1 },
+            { "id": "b", "x": 20 },
+            { "id": "c", "x": 3 },
+        ]);
+
+        assert_eq!(
+            merge(&base, &ours, &theirs),
+            Ok(json!([{ "id": "b", "x": 20 }, { "id": "c", "x": 30 }, { "id": "d", "x": 4 }, { "id": "e", "x": 5 }]))
+        );
+    }
+
+    #[test]
+    fn reports_removal_of_modified_value() {
+        let base = json!({ "entities": [{ "id": "a", "x": 1 }], "name": "design" });
+        let ours = json!({ "entities": [], "name": "design" });
+        let theirs = json!({ "entities": [{ "id": "a", "x": 2 }], "name": "renamed" });
+
+        assert_eq!(
+            merge(&base, &ours, &theirs),
+            Err(vec![MergeConflict {
+                pointer: "/entities/0".to_string(),
+                base: Some(json!({ "id": "a", "x": 1 })),
+                ours: None,
+                theirs: Some(json!({ "id": "a", "x": 2 })),
+            }])
+        );
+    }
+
+    #[test]
+    fn escapes_conflict_pointers() {
+        let base = json!({ "a/b": { "c~d": 1 } });
+        let ours = json!({ "a/b": { "c~d": 2 } });
+        let theirs = json!({ "a/b": { "c~d": 3 } });
+
+        let conflicts = merge(&base, &ours, &theirs).unwrap_err();
+
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].pointer, "/a~1b/c~0d");
+    }
+}
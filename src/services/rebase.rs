@@ -0,0 +1,508 @@
+use json_patch::{AddOperation, MoveOperation, Patch, PatchOperation, RemoveOperation};
+use serde_json::Value;
+
+/// Result of transforming an operation over an operation applied before it.
+enum Transformed {
+    Op(PatchOperation),
+    /// The operation has no effect anymore, e.g. it removes a value removed already.
+    Noop,
+    /// The operation targets a value which doesn't exist anymore.
+    Conflict,
+}
+
+/// Rebases patches made on top of the `base` document over the operations
+/// applied to the document since (operational transformation).
+///
+/// Array indices are shifted by insertions and removals made before them,
+/// operations on removed or overwritten values are conflicts: a value is overwritten
+/// by a replacement of it or of its ancestors, up to the whole document,
+/// and by an addition, a copy or a move to the object member holding it.
+/// `base` tells arrays from objects, tokens of paths which are not in `base`
+/// are treated as array indices when numeric.
+///
+/// The patches are made one after another, so each one is rebased in the context
+/// of the patches before it: after the applied ones, and without the rejected ones.
+pub struct Rebaser<'a> {
+    base: &'a Value,
+    /// Operations to transform the next patch over, in the context of the previous patch.
+    applied: Vec<PatchOperation>,
+}
+
+/// Patch rebased over the applied operations.
+pub struct Rebased {
+    pub patch: Patch,
+    // The applied operations moved past the patch.
+    applied: Vec<PatchOperation>,
+}
+
+impl<'a> Rebaser<'a> {
+    pub fn new(base: &'a Value, applied: Vec<PatchOperation>) -> Self {
+        Rebaser { base, applied }
+    }
+
+    /// Transforms the patch as a whole, `None` when it has a conflict.
+    pub fn rebase(&self, patch: &Patch) -> Option<Rebased> {
+        let mut ops = Vec::with_capacity(patch.len());
+        let mut rebased_applied = self.applied.clone();
+
+        for op in &patch.0 {
+            let mut transformed = Some(op.clone());
+            let mut next_applied = Vec::with_capacity(rebased_applied.len());
+
+            for prior in &rebased_applied {
+                if let Some(op) = transformed.take() {
+                    transformed = match transform(&op, prior, self.base, true) {
+                        Transformed::Op(op) => Some(op),
+                        Transformed::Noop => None,
+                        Transformed::Conflict => return None,
+                    };
+                    // Applied operations are moved past the op, so the next ops
+                    // of the patch are transformed in their own context.
+                    if let Transformed::Op(prior) = transform(prior, &op, self.base, false) {
+                        next_applied.push(prior);
+                    }
+                } else {
+                    next_applied.push(prior.clone());
+                }
+            }
+
+            rebased_applied = next_applied;
+            ops.extend(transformed);
+        }
+
+        Some(Rebased {
+            patch: Patch(ops),
+            applied: rebased_applied,
+        })
+    }
+
+    /// The next patches are rebased after the applied patch.
+    pub fn apply(&mut self, rebased: Rebased) {
+        self.applied = rebased.applied;
+    }
+
+    /// The next patches are rebased without the rejected patch: they are transformed
+    /// over its inverse first, so their paths don't count on its changes.
+    pub fn reject(&mut self, patch: &Patch) {
+        let mut applied: Vec<PatchOperation> = patch.0.iter().rev().filter_map(inverse).collect();
+        applied.append(&mut self.applied);
+
+        self.applied = applied;
+    }
+}
+
+// Operation undoing the paths of `op`, `None` when it doesn't change them. Only the paths
+// are transformed over, so the values which are not known, of removed values, are `null`.
+fn inverse(op: &PatchOperation) -> Option<PatchOperation> {
+    match op {
+        PatchOperation::Add(add) => Some(PatchOperation::Remove(RemoveOperation { path: add.path.clone() })),
+        PatchOperation::Copy(copy) => Some(PatchOperation::Remove(RemoveOperation { path: copy.path.clone() })),
+        PatchOperation::Remove(remove) => Some(PatchOperation::Add(AddOperation {
+            path: remove.path.clone(),
+            value: Value::Null,
+        })),
+        PatchOperation::Move(mov) => Some(PatchOperation::Move(MoveOperation {
+            from: mov.path.clone(),
+            path: mov.from.clone(),
+        })),
+        // Replacements and tests don't move values.
+        PatchOperation::Replace(_) | PatchOperation::Test(_) => None,
+    }
+}
+
+// Transforms `op` to be applied after `prior`, both made on the same document.
+// `op_yields` decides which of two insertions at the same array index goes first.
+fn transform(op: &PatchOperation, prior: &PatchOperation, base: &Value, op_yields: bool) -> Transformed {
+    match prior {
+        PatchOperation::Add(prior) => map_paths(op, |path, inserts| {
+            let path = not_overwritten(path, &prior.path, base)?;
+            Some(after_insert(path, &prior.path, base, inserts, op_yields))
+        }),
+        PatchOperation::Copy(prior) => map_paths(op, |path, inserts| {
+            let path = not_overwritten(path, &prior.path, base)?;
+            Some(after_insert(path, &prior.path, base, inserts, op_yields))
+        }),
+        PatchOperation::Remove(prior) => {
+            if let PatchOperation::Remove(remove) = op {
+                if remove.path == prior.path {
+                    return Transformed::Noop;
+                }
+            }
+
+            map_paths(op, |path, inserts| after_remove(path, &prior.path, base, inserts))
+        }
+        PatchOperation::Move(prior) => map_paths(op, |path, inserts| {
+            let path = not_overwritten(path, &prior.path, base)?;
+            let from = parse(&prior.from);
+
+            if is_prefix(&from, &path) {
+                let mut moved = parse(&prior.path);
+                moved.extend_from_slice(&path[from.len()..]);
+
+                return Some(moved);
+            }
+
+            let path = after_remove(path, &prior.from, base, inserts)?;
+            Some(after_insert(path, &prior.path, base, inserts, op_yields))
+        }),
+        PatchOperation::Replace(prior) => map_paths(op, |path, _| {
+            (!is_prefix(&parse(&prior.path), &path)).then_some(path)
+        }),
+        PatchOperation::Test(_) => Transformed::Op(op.clone()),
+    }
+}
+
+// Applies `f` to the paths of the operation, `f` is told whether the path is an insertion point.
+fn map_paths<F>(op: &PatchOperation, f: F) -> Transformed
+where
+    F: Fn(Vec<String>, bool) -> Option<Vec<String>>,
+{
+    let map = |pointer: &str, inserts: bool| f(parse(pointer), inserts).map(|path| format(&path));
+
+    let op = match op.clone() {
+        PatchOperation::Add(mut add) => map(&add.path, true).map(|path| {
+            add.path = path;
+            PatchOperation::Add(add)
+        }),
+        PatchOperation::Remove(mut remove) => map(&remove.path, false).map(|path| {
+            remove.path = path;
+            PatchOperation::Remove(remove)
+        }),
+        PatchOperation::Replace(mut replace) => map(&replace.path, false).map(|path| {
+            replace.path = path;
+            PatchOperation::Replace(replace)
+        }),
+        PatchOperation::Test(mut test) => map(&test.path, false).map(|path| {
+            test.path = path;
+            PatchOperation::Test(test)
+        }),
+        PatchOperation::Move(mut mov) => map(&mov.from, false).zip(map(&mov.path, true)).map(|(from, path)| {
+            mov.from = from;
+            mov.path = path;
+            PatchOperation::Move(mov)
+        }),
+        PatchOperation::Copy(mut copy) => map(&copy.from, false).zip(map(&copy.path, true)).map(|(from, path)| {
+            copy.from = from;
+            copy.path = path;
+            PatchOperation::Copy(copy)
+        }),
+    };
+
+    op.map_or(Transformed::Conflict, Transformed::Op)
+}
+
+// Path after a value has been inserted at `inserted`.
+fn after_insert(mut path: Vec<String>, inserted: &str, base: &Value, inserts: bool, yields: bool) -> Vec<String> {
+    let inserted = parse(inserted);
+
+    if let Some((index, inserted_index)) = array_indices(&path, &inserted, base) {
+        // A path to the same index refers to the value shifted by the insertion,
+        // unless it is an insertion itself and goes first.
+        let goes_first = inserts && path.len() == inserted.len() && !yields;
+        if index > inserted_index || (index == inserted_index && !goes_first) {
+            path[inserted.len() - 1] = (index + 1).to_string();
+        }
+    }
+
+    path
+}
+
+// The path unless its value has been overwritten by a value written to the object member
+// at `written`. Values written to arrays are inserted, they don't overwrite other values.
+fn not_overwritten(path: Vec<String>, written: &str, base: &Value) -> Option<Vec<String>> {
+    let written = parse(written);
+
+    match written.split_last() {
+        // The whole document has been written.
+        None => None,
+        Some((last, parent)) if is_prefix(&written, &path) && !is_array(base, parent, last) => None,
+        _ => Some(path),
+    }
+}
+
+// Path after the value at `removed` has been removed, `None` if the path was removed with it.
+fn after_remove(mut path: Vec<String>, removed: &str, base: &Value, inserts: bool) -> Option<Vec<String>> {
+    let removed = parse(removed);
+
+    if removed.is_empty() {
+        return Some(path);
+    }
+
+    if is_prefix(&removed, &path) {
+        // Insertions at the removed position are still valid.
+        return (inserts && path.len() == removed.len()).then_some(path);
+    }
+
+    if let Some((index, removed_index)) = array_indices(&path, &removed, base) {
+        if index > removed_index {
+            path[removed.len() - 1] = (index - 1).to_string();
+        }
+    }
+
+    Some(path)
+}
+
+// Indices of `path` and `other` when both go through the same array.
+// `-` (the end of an array) doesn't affect and isn't affected by other indices.
+fn array_indices(path: &[String], other: &[String], base: &Value) -> Option<(usize, usize)> {
+    let (last, parent) = other.split_last()?;
+
+    if path.len() < other.len() || !is_prefix(parent, path) || !is_array(base, parent, last) {
+        return None;
+    }
+
+    Some((path[parent.len()].parse().ok()?, last.parse().ok()?))
+}
+
+fn is_array(base: &Value, parent: &[String], token: &str) -> bool {
+    match base.pointer(&format(parent)) {
+        Some(value) => value.is_array(),
+        None => token == "-" || token.parse::<usize>().is_ok(),
+    }
+}
+
+fn is_prefix(prefix: &[String], path: &[String]) -> bool {
+    path.len() >= prefix.len() && path[..prefix.len()] == *prefix
+}
+
+// Reference tokens of the JSON pointer (RFC 6901).
+fn parse(pointer: &str) -> Vec<String> {
+    pointer
+        .split('/')
+        .skip(1)
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .collect()
+}
+
+fn format(path: &[String]) -> String {
+    path.iter()
+        .map(|token| format!("/{}", token.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(operations: Value) -> Patch {
+        serde_json::from_value(operations).unwrap()
+    }
+
+    // Rebases the patches one after another, the ones without a conflict are applied.
+    fn rebased(base: &Value, applied: Value, patches: Vec<Value>) -> Vec<Option<Patch>> {
+        let mut rebaser = Rebaser::new(base, patch(applied).0);
+
+        patches
+            .into_iter()
+            .map(|operations| {
+                let patch = patch(operations);
+                match rebaser.rebase(&patch) {
+                    Some(rebased) => {
+                        let rebased_patch = rebased.patch.clone();
+                        rebaser.apply(rebased);
+
+                        Some(rebased_patch)
+                    }
+                    None => {
+                        rebaser.reject(&patch);
+
+                        None
+                    }
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shifts_indices_after_insert() {
+        let base = json!({ "a": [1, 2, 3] });
+        let applied = json!([{ "op": "add", "path": "/a/0", "value": 0 }]);
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/a/1", "value": 20 }]),
+                json!([{ "op": "add", "path": "/a/3", "value": 4 }]),
+                json!([{ "op": "add", "path": "/a/0", "value": -1 }]),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            vec![
+                Some(patch(json!([{ "op": "replace", "path": "/a/2", "value": 20 }]))),
+                Some(patch(json!([{ "op": "add", "path": "/a/4", "value": 4 }]))),
+                // Concurrent insertions at the same index go after the applied one.
+                Some(patch(json!([{ "op": "add", "path": "/a/1", "value": -1 }]))),
+            ]
+        );
+    }
+
+    #[test]
+    fn shifts_indices_after_remove() {
+        let base = json!({ "a": [1, 2, 3] });
+        let applied = json!([{ "op": "remove", "path": "/a/0" }]);
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/a/2", "value": 30 }]),
+                json!([{ "op": "replace", "path": "/a/0", "value": 10 }]),
+                json!([{ "op": "remove", "path": "/a/0" }]),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            vec![
+                Some(patch(json!([{ "op": "replace", "path": "/a/1", "value": 30 }]))),
+                // The value has been removed.
+                None,
+                // It has been removed already.
+                Some(patch(json!([]))),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejected_patch_is_undone_from_next_patches() {
+        let base = json!({ "a": [1, 2, 3] });
+        let mut rebaser = Rebaser::new(&base, patch(json!([{ "op": "remove", "path": "/a/2" }])).0);
+
+        // The insertion is rebased, but it isn't applied.
+        let rejected = patch(json!([{ "op": "add", "path": "/a/0", "value": 0 }]));
+        assert!(rebaser.rebase(&rejected).is_some());
+        rebaser.reject(&rejected);
+
+        // The next patch has been made after the insertion, it removes 1.
+        let rebased = rebaser.rebase(&patch(json!([{ "op": "remove", "path": "/a/1" }]))).unwrap();
+
+        assert_eq!(rebased.patch, patch(json!([{ "op": "remove", "path": "/a/0" }])));
+    }
+
+    #[test]
+    fn replacement_of_ancestor_conflicts() {
+        let base = json!({ "a": { "b": 1 }, "c": 2 });
+        let applied = json!([{ "op": "replace", "path": "/a", "value": { "b": 3 } }]);
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/a/b", "value": 4 }]),
+                json!([{ "op": "replace", "path": "/a", "value": {} }]),
+                json!([{ "op": "replace", "path": "/c", "value": 5 }]),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            vec![None, None, Some(patch(json!([{ "op": "replace", "path": "/c", "value": 5 }])))]
+        );
+    }
+
+    #[test]
+    fn replacement_of_whole_document_conflicts() {
+        let base = json!({ "a": 1 });
+        let applied = json!([{ "op": "replace", "path": "", "value": { "a": 2 } }]);
+
+        let result = rebased(&base, applied, vec![json!([{ "op": "add", "path": "/b", "value": 1 }])]);
+
+        assert_eq!(result, vec![None]);
+    }
+
+    #[test]
+    fn addition_to_object_member_overwrites_its_value() {
+        let base = json!({ "a": { "b": { "c": 1 } } });
+        let applied = json!([{ "op": "add", "path": "/a/b", "value": { "c": 2 } }]);
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/a/b/c", "value": 3 }]),
+                json!([{ "op": "add", "path": "/a/d", "value": 4 }]),
+            ],
+        );
+
+        assert_eq!(result, vec![None, Some(patch(json!([{ "op": "add", "path": "/a/d", "value": 4 }])))]);
+    }
+
+    #[test]
+    fn moves_paths_with_moved_value() {
+        let base = json!({ "a": [{ "x": 1 }, { "x": 2 }], "b": null });
+        let applied = json!([{ "op": "move", "from": "/a/0", "path": "/c" }]);
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/a/0/x", "value": 10 }]),
+                json!([{ "op": "replace", "path": "/a/1/x", "value": 20 }]),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            vec![
+                Some(patch(json!([{ "op": "replace", "path": "/c/x", "value": 10 }]))),
+                Some(patch(json!([{ "op": "replace", "path": "/a/0/x", "value": 20 }]))),
+            ]
+        );
+    }
+
+    #[test]
+    fn merges_non_overlapping_edits() {
+        let base = json!({ "name": "a", "title": "b", "items": [1, 2, 3] });
+        let applied = json!([
+            { "op": "replace", "path": "/name", "value": "A" },
+            { "op": "remove", "path": "/items/0" },
+        ]);
+        let mut document = base.clone();
+        json_patch::patch(&mut document, &patch(applied.clone())).unwrap();
+
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "replace", "path": "/title", "value": "B" }]),
+                json!([
+                    { "op": "add", "path": "/items/-", "value": 4 },
+                    { "op": "replace", "path": "/items/2", "value": 30 },
+                ]),
+            ],
+        );
+
+        for patch in result {
+            json_patch::patch(&mut document, &patch.expect("Patch conflicts")).unwrap();
+        }
+        assert_eq!(document, json!({ "name": "A", "title": "B", "items": [2, 30, 4] }));
+    }
+
+    #[test]
+    fn later_patches_are_rebased_over_earlier_ones() {
+        let base = json!({ "items": [1, 2] });
+        let applied = json!([{ "op": "add", "path": "/items/0", "value": 0 }]);
+
+        // The second patch has been made after the first one.
+        let result = rebased(
+            &base,
+            applied,
+            vec![
+                json!([{ "op": "remove", "path": "/items/0" }]),
+                json!([{ "op": "replace", "path": "/items/0", "value": 20 }]),
+            ],
+        );
+
+        assert_eq!(
+            result,
+            vec![
+                Some(patch(json!([{ "op": "remove", "path": "/items/1" }]))),
+                Some(patch(json!([{ "op": "replace", "path": "/items/1", "value": 20 }]))),
+            ]
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{errors::AppError, models::Result};
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::{
+    decode, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, OctetKeyPairParameters,
+        OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::digest::{digest, SHA256};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use uuid::Uuid;
+
+use std::env;
+
+const DEFAULT_SESSION_EXPIRES_IN: i64 = 3600;
+/// Seconds an expired session token could still be refreshed for, a week.
+const DEFAULT_SESSION_REFRESH_WINDOW: i64 = 7 * 24 * 3600;
+/// Seconds a design connection ticket could be used for.
+const TICKET_EXPIRES_IN: i64 = 30;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub iss: String,
+    pub iat: i64,
+    pub exp: i64,
+    /// Audience of a ticket, session tokens have none.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
+}
+
+/// Claims of a ticket to connect to the collaborative editing of a design.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TicketClaims {
+    pub sub: Uuid,
+    pub iss: String,
+    pub aud: String,
+    pub exp: i64,
+    /// Expiration of the session token the ticket has been issued for.
+    pub session_exp: i64,
+}
+
+pub struct SessionToken {
+    pub token: String,
+    pub expires_in: i64,
+}
+
+/// Keys used to sign and verify session tokens issued by this server.
+///
+/// The Ed25519 key pair is read from `SESSION_ED25519_KEY` as hex encoded PKCS#8 document,
+/// the issuer is read from `AUTHORITY`. Expired tokens are refreshed within
+/// `SESSION_REFRESH_WINDOW` seconds after they expire.
+pub struct SessionKeys {
+    kid: String,
+    issuer: String,
+    expires_in: i64,
+    refresh_window: i64,
+    public_key: Vec<u8>,
+    encoding_key: EncodingKey,
+    decoding_key: DecodingKey,
+}
+
+impl SessionKeys {
+    pub fn new() -> Self {
+        let key_string =
+            env::var("SESSION_ED25519_KEY").expect("SESSION_ED25519_KEY must be available.");
+        let pkcs8 = hex::decode(key_string).expect("Expected to decode key from hex");
+        let issuer = env::var("AUTHORITY").expect("AUTHORITY must be set");
+        let expires_in = env::var("SESSION_TOKEN_EXPIRES_IN")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_SESSION_EXPIRES_IN);
+        let refresh_window = env::var("SESSION_REFRESH_WINDOW")
+            .ok()
+            .and_then(|v| v.parse::<i64>().ok())
+            .unwrap_or(DEFAULT_SESSION_REFRESH_WINDOW);
+
+        let key_pair = Ed25519KeyPair::from_pkcs8(&pkcs8)
+            .expect("SESSION_ED25519_KEY must be a valid Ed25519 PKCS#8 document");
+        let public_key = key_pair.public_key().as_ref().to_vec();
+        let kid = hex::encode(&digest(&SHA256, &public_key).as_ref()[..8]);
+
+        SessionKeys {
+            kid,
+            issuer,
+            expires_in,
+            refresh_window,
+            encoding_key: EncodingKey::from_ed_der(&pkcs8),
+            decoding_key: DecodingKey::from_ed_der(&public_key),
+            public_key,
+        }
+    }
+
+    pub fn issue(&self, user_id: Uuid) -> Result<SessionToken> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = Claims {
+            sub: user_id,
+            iss: self.issuer.clone(),
+            iat: now,
+            exp: now + self.expires_in,
+            aud: None,
+        };
+
+        Ok(SessionToken {
+            token: self.encode(&claims)?,
+            expires_in: self.expires_in,
+        })
+    }
+
+    /// Issues a ticket to connect to the design with, for clients which can't pass
+    /// the session token in the `Authorization` header of the WebSocket handshake.
+    /// The ticket is valid for a few seconds and doesn't outlive the session token.
+    pub fn issue_ticket(&self, session: &Claims, design_id: Uuid) -> Result<SessionToken> {
+        let now = chrono::Utc::now().timestamp();
+        let claims = TicketClaims {
+            sub: session.sub,
+            iss: self.issuer.clone(),
+            aud: ticket_audience(design_id),
+            exp: session.exp.min(now + TICKET_EXPIRES_IN),
+            session_exp: session.exp,
+        };
+
+        Ok(SessionToken {
+            token: self.encode(&claims)?,
+            expires_in: claims.exp - now,
+        })
+    }
+
+    pub fn verify_ticket(&self, token: &str, design_id: Uuid) -> Result<TicketClaims> {
+        let mut validation = self.validation(true);
+        validation.leeway = 0;
+        validation.set_audience(&[ticket_audience(design_id)]);
+
+        decode::<TicketClaims>(token, &self.decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| AppError::AuthError)
+    }
+
+    pub fn verify(&self, token: &str) -> Result<Claims> {
+        self.decode(token, true)
+    }
+
+    // Expired tokens are still accepted here within the refresh window,
+    // it is used only to identify the user during the refresh of the tokens.
+    pub fn verify_allow_expired(&self, token: &str) -> Result<Claims> {
+        let claims = self.decode(token, false)?;
+
+        if claims.exp + self.refresh_window < chrono::Utc::now().timestamp() {
+            return Err(AppError::AuthError);
+        }
+
+        Ok(claims)
+    }
+
+    pub fn jwks(&self) -> JwkSet {
+        JwkSet {
+            keys: vec![Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    algorithm: Some(Algorithm::EdDSA),
+                    key_id: Some(self.kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x: general_purpose::URL_SAFE_NO_PAD.encode(&self.public_key),
+                }),
+            }],
+        }
+    }
+
+    fn encode<T: serde::Serialize>(&self, claims: &T) -> Result<String> {
+        let mut header = Header::new(Algorithm::EdDSA);
+        header.kid = Some(self.kid.clone());
+
+        encode(&header, claims, &self.encoding_key).map_err(|e| AppError::CryptoError(e.to_string()))
+    }
+
+    fn decode(&self, token: &str, validate_exp: bool) -> Result<Claims> {
+        let claims = decode::<Claims>(token, &self.decoding_key, &self.validation(validate_exp))
+            .map(|data| data.claims)
+            .map_err(|_| AppError::AuthError)?;
+
+        // Tickets are signed with the same key, but they aren't session tokens.
+        match claims.aud {
+            Some(_) => Err(AppError::AuthError),
+            None => Ok(claims),
+        }
+    }
+
+    fn validation(&self, validate_exp: bool) -> Validation {
+        let mut validation = Validation::new(Algorithm::EdDSA);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_required_spec_claims(&["exp", "iss", "sub"]);
+        validation.validate_exp = validate_exp;
+
+        validation
+    }
+}
+
+fn ticket_audience(design_id: Uuid) -> String {
+    format!("design:{design_id}")
+}
@@ -0,0 +1,270 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_path_to_error::Segment;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+
+/// Root of the design document model shared with the editor.
+type DesignModel = unielit_core::Design;
+
+/// Violations reported per document at most, the rest of the document isn't checked.
+const MAX_VIOLATIONS: usize = 50;
+
+#[derive(Serialize, ToSchema, Clone, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Violation {
+    /// JSON pointer (RFC 6901) to the invalid value of the design.
+    pub pointer: String,
+    pub message: String,
+}
+
+/// Checks that the design document could be read as the design_core model.
+pub fn validate_design(design: &Value) -> Result<(), Vec<Violation>> {
+    if !design.is_object() {
+        return Err(vec![Violation {
+            pointer: String::new(),
+            message: "design must be a JSON object".to_string(),
+        }]);
+    }
+
+    match violations::<DesignModel>(design) {
+        violations if violations.is_empty() => Ok(()),
+        violations => Err(violations),
+    }
+}
+
+// Deserialization stops at the first invalid value. To report the other ones,
+// the array element containing it is removed and the document is read again,
+// so every invalid element of the arrays is reported once. Invalid values outside
+// of arrays stop the validation, the rest of the document couldn't be read without them.
+fn violations<T: DeserializeOwned>(design: &Value) -> Vec<Violation> {
+    let mut document = design.clone();
+    // Original indices of the removed elements by the original pointer of their array.
+    let mut removed: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut violations = Vec::new();
+
+    while violations.len() < MAX_VIOLATIONS {
+        let error = match serde_path_to_error::deserialize::<_, T>(&document) {
+            Ok(_) => break,
+            Err(error) => error,
+        };
+        let Some(tokens) = path_tokens(error.path()) else {
+            break;
+        };
+
+        let (pointer, element) = original_pointer(&tokens, &removed);
+        violations.push(Violation {
+            pointer,
+            message: error.inner().to_string(),
+        });
+
+        let Some((array_pointer, index, original_index)) = element else {
+            break;
+        };
+        let array_tokens = &tokens[..tokens.len() - element_depth(&tokens)];
+        match array_at(&mut document, array_tokens) {
+            Some(array) if index < array.len() => {
+                array.remove(index);
+            }
+            _ => break,
+        }
+
+        let indices = removed.entry(array_pointer).or_default();
+        indices.push(original_index);
+        indices.sort_unstable();
+    }
+
+    violations
+}
+
+enum Token {
+    Key(String),
+    Index(usize),
+}
+
+// Tokens of the path, `None` when a segment couldn't be located in the document.
+fn path_tokens(path: &serde_path_to_error::Path) -> Option<Vec<Token>> {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Seq { index } => Some(Token::Index(*index)),
+            Segment::Map { key } => Some(Token::Key(key.to_owned())),
+            Segment::Enum { variant } => Some(Token::Key(variant.to_owned())),
+            Segment::Unknown => None,
+        })
+        .collect()
+}
+
+// Number of the tokens from the deepest array index to the end of the path, with the index.
+fn element_depth(tokens: &[Token]) -> usize {
+    tokens
+        .iter()
+        .rev()
+        .position(|token| matches!(token, Token::Index(_)))
+        .map_or(0, |position| position + 1)
+}
+
+// Pointer of the path in the original document, and of the deepest array element
+// of the path: the original pointer of the array, the index in the read document
+// and the index in the original one.
+fn original_pointer(
+    tokens: &[Token],
+    removed: &HashMap<String, Vec<usize>>,
+) -> (String, Option<(String, usize, usize)>) {
+    let mut pointer = String::new();
+    let mut element = None;
+
+    for token in tokens {
+        match token {
+            Token::Key(key) => pointer.push_str(&format!("/{}", escape(key))),
+            Token::Index(index) => {
+                let original = original_index(*index, removed.get(&pointer));
+                element = Some((pointer.clone(), *index, original));
+                pointer.push_str(&format!("/{original}"));
+            }
+        }
+    }
+
+    (pointer, element)
+}
+
+// Removed indices are sorted, each of them before the index shifts it.
+fn original_index(index: usize, removed: Option<&Vec<usize>>) -> usize {
+    removed
+        .into_iter()
+        .flatten()
+        .fold(index, |original, removed| match *removed <= original {
+            true => original + 1,
+            false => original,
+        })
+}
+
+fn array_at<'a>(document: &'a mut Value, tokens: &[Token]) -> Option<&'a mut Vec<Value>> {
+    tokens
+        .iter()
+        .try_fold(document, |value, token| match token {
+            Token::Key(key) => value.get_mut(key),
+            Token::Index(index) => value.get_mut(*index),
+        })?
+        .as_array_mut()
+}
+
+fn escape(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Document {
+        name: String,
+        entities: Vec<Entity>,
+    }
+
+    #[derive(Deserialize)]
+    #[serde(deny_unknown_fields)]
+    #[allow(dead_code)]
+    struct Entity {
+        id: String,
+        size: u32,
+        #[serde(default)]
+        children: Vec<Entity>,
+    }
+
+    fn pointers(design: Value) -> Vec<String> {
+        violations::<Document>(&design)
+            .into_iter()
+            .map(|violation| violation.pointer)
+            .collect()
+    }
+
+    #[test]
+    fn valid_document_has_no_violations() {
+        let design = json!({ "name": "design", "entities": [{ "id": "a", "size": 1 }] });
+
+        assert!(pointers(design).is_empty());
+    }
+
+    #[test]
+    fn reports_every_invalid_element() {
+        let design = json!({
+            "name": "design",
+            "entities": [
+                { "id": "a", "size": "large" },
+                { "id": "b", "size": 1 },
+                { "id": "c", "size": -1 },
+                { "id": "d", "size": 2, "color": "red" },
+            ],
+        });
+
+        assert_eq!(
+            pointers(design),
+            vec!["/entities/0/size", "/entities/2/size", "/entities/3/color"]
+        );
+    }
+
+    #[test]
+    fn reports_original_indices_of_nested_elements() {
+        let design = json!({
+            "name": "design",
+            "entities": [
+                { "id": "a", "size": "large" },
+                {
+                    "id": "b",
+                    "size": 1,
+                    "children": [
+                        { "id": "b1" },
+                        { "id": "b2", "size": 1 },
+                        { "id": 3, "size": 1 },
+                    ],
+                },
+            ],
+        });
+
+        assert_eq!(
+            pointers(design),
+            vec![
+                "/entities/0/size",
+                "/entities/1/children/0",
+                "/entities/1/children/2/id",
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_at_invalid_value_outside_of_arrays() {
+        // Keys are read in their order, the entities before the name.
+        let design = json!({ "name": 1, "entities": { "id": "a", "size": "large" } });
+
+        assert_eq!(pointers(design), vec!["/entities"]);
+    }
+
+    #[test]
+    fn escapes_pointer_tokens() {
+        let design = json!({ "name": "design", "entities": [], "a/b~c": 1 });
+
+        assert_eq!(pointers(design), vec!["/a~1b~0c"]);
+    }
+
+    #[test]
+    fn limits_number_of_violations() {
+        let entities: Vec<Value> = (0..MAX_VIOLATIONS + 10)
+            .map(|i| json!({ "id": i, "size": 1 }))
+            .collect();
+        let design = json!({ "name": "design", "entities": entities });
+
+        let pointers = pointers(design);
+
+        assert_eq!(pointers.len(), MAX_VIOLATIONS);
+        assert_eq!(pointers[MAX_VIOLATIONS - 1], format!("/entities/{}/id", MAX_VIOLATIONS - 1));
+    }
+
+    #[test]
+    fn rejects_non_object_design() {
+        assert_eq!(validate_design(&json!([])).unwrap_err()[0].pointer, "");
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{
+    errors::AppError,
+    models::{
+        repositories::{Provider, Repository, RepositoryOwner},
+        users::{TokenData, User},
+        Result,
+    },
+    services::{github::GitHubAPI, gitlab::GitLabAPI, local_git::LocalGit},
+};
+use async_trait::async_trait;
+
+/// File of a repository with decoded content.
+#[derive(Debug)]
+pub struct RepoFile {
+    pub sha: String,
+    pub content: Vec<u8>,
+}
+
+/// File committed to a repository.
+#[derive(Debug)]
+pub struct CommittedFile {
+    pub sha: String,
+    /// SHA of the commit with the file.
+    pub commit_sha: String,
+}
+
+/// Account of the user on the provider.
+#[derive(Debug)]
+pub struct VcsUser {
+    /// Id of the account, it does not change with the login.
+    pub id: String,
+    pub login: String,
+    pub name: Option<String>,
+}
+
+/// Token for the operations on a repository on behalf of a user.
+pub struct RepoAccess {
+    pub token: String,
+    /// Name and email of the user when the token is not the user's own.
+    co_author: Option<(String, String)>,
+}
+
+impl RepoAccess {
+    /// Commit message crediting the user as a co-author of commits made by the app.
+    pub fn commit_message(&self, message: &str) -> String {
+        match &self.co_author {
+            Some((name, email)) => format!("{}\n\nCo-authored-by: {name} <{email}>", message.trim_end()),
+            None => message.to_owned(),
+        }
+    }
+}
+
+/// Hosting service of repositories, the users sign in with its OAuth application.
+///
+/// SHAs of files are SHAs of their git blobs, so they could be compared
+/// with `github::blob_sha` of the content whatever the provider is.
+#[async_trait(?Send)]
+pub trait VcsProvider {
+    fn provider(&self) -> Provider;
+
+    /// Exchanges the code of the OAuth authorization for the tokens of the user.
+    async fn generate_access_token(&self, code: String) -> Result<TokenData>;
+
+    async fn refresh_access_token(&self, refresh_token: String) -> Result<TokenData>;
+
+    /// Account of the user the token is issued to.
+    async fn get_user(&self, token: &str) -> Result<VcsUser>;
+
+    async fn get_primary_email(&self, token: &str) -> Result<String>;
+
+    /// Creates the repository with the provider specific parameters,
+    /// returns the URL of its web page.
+    async fn create_repo(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        params: serde_json::Value,
+    ) -> Result<String>;
+
+    /// Archives the repository, it becomes read-only on the provider.
+    async fn archive_repo(&self, token: &str, repo_owner: RepositoryOwner) -> Result<()>;
+
+    /// Returns `None` if there is no such file in the repository.
+    /// The file is read from the default branch unless `reference` is set.
+    async fn get_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        reference: Option<&str>,
+    ) -> Result<Option<RepoFile>>;
+
+    /// Commits the file to the branch, the default branch when `None`.
+    /// `sha` is the SHA of the file being replaced, `None` when it is created,
+    /// the commit fails with `RepoFileConflict` when the file has another SHA.
+    /// The author is the user the commit is made for, providers with tokens
+    /// attribute the commit to the owner of the token instead.
+    #[allow(clippy::too_many_arguments)]
+    async fn commit_file(
+        &self,
+        token: &str,
+        repo_owner: RepositoryOwner,
+        path: &str,
+        message: &str,
+        content: &[u8],
+        sha: Option<&str>,
+        branch: Option<&str>,
+        author: &User,
+    ) -> Result<CommittedFile>;
+}
+
+/// Clients of the providers, shared by the workers.
+pub struct VcsProviders {
+    pub github: GitHubAPI,
+    pub gitlab: GitLabAPI,
+    pub local: LocalGit,
+}
+
+impl VcsProviders {
+    pub fn new() -> Result<Self> {
+        Ok(VcsProviders {
+            github: GitHubAPI::new()?,
+            gitlab: GitLabAPI::new()?,
+            local: LocalGit::new(),
+        })
+    }
+
+    pub fn get(&self, provider: Provider) -> &dyn VcsProvider {
+        match provider {
+            Provider::GitHub => &self.github,
+            Provider::GitLab => &self.gitlab,
+            Provider::Local => &self.local,
+        }
+    }
+
+    /// Installation token of the GitHub App when it is installed on the owner
+    /// of the repository, so the operations don't depend on the user's access
+    /// to the repository, or the user's token otherwise. The user's token
+    /// is only required without the installation token, its error is returned then.
+    pub async fn repo_access(&self, repo: &Repository, user: &User, user_token: Result<&str>) -> Result<RepoAccess> {
+        let app_token = match repo.provider {
+            Provider::GitHub => {
+                self.github
+                    .installation_token(&repo.owner, repo.is_organization)
+                    .await?
+            }
+            Provider::GitLab | Provider::Local => None,
+        };
+
+        Ok(match app_token {
+            Some(token) => RepoAccess {
+                token,
+                co_author: Some((user.name.clone(), user.email.clone())),
+            },
+            None => RepoAccess {
+                token: user_token?.to_owned(),
+                co_author: None,
+            },
+        })
+    }
+
+    /// Client of GitHub for features only GitHub repositories have,
+    /// e.g. pull requests and the split layout.
+    pub fn github_only(&self, repo: &Repository, feature: &str) -> Result<&GitHubAPI> {
+        match repo.provider {
+            Provider::GitHub => Ok(&self.github),
+            provider => Err(AppError::InvalidInput(format!(
+                "{feature} is not supported for {} repositories",
+                provider.as_str()
+            ))),
+        }
+    }
+}
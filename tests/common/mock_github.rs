@@ -0,0 +1,1139 @@
+//! In-memory GitHub serving the OAuth and REST API endpoints the server uses.
+//!
+//! It serves both the web (`GITHUB_URL`) and the API (`GITHUB_API_URL`) base URL.
+//! Users are added with `add_user`, which returns the OAuth code to sign in with.
+//! Repositories and their files live in memory, file updates are checked against
+//! the blob SHA of the current file the way GitHub does. Other branches than the default
+//! one have their own files, pull requests between the branches are only recorded.
+//! The GitHub App is installed on accounts with `install_app`, its JWTs are verified
+//! with the public key of `github_app_key.pem`.
+//! Files are served with their ETag and revalidated with `If-None-Match`. Responses of
+//! the contents API could be replaced with errors with `fail_next`, its requests are
+//! counted by path. Emails of the users are listed in pages of `EMAILS_PAGE_SIZE`.
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use base64::{engine::general_purpose, Engine as _};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use ring::digest::{digest, SHA1_FOR_LEGACY_USE_ONLY};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const DEFAULT_BRANCH: &str = "main";
+pub const APP_ID: &str = "4242";
+/// Size of the files the contents API returns without their content, they are read from blobs.
+pub const LARGE_FILE_SIZE: usize = 1024 * 1024;
+/// Login of the GitHub App, the author of the commits made with installation tokens.
+pub const APP_LOGIN: &str = "unielit-test[bot]";
+/// Emails listed per page, whatever page size is requested.
+pub const EMAILS_PAGE_SIZE: usize = 2;
+const APP_PUBLIC_KEY: &str = include_str!("github_app_key.pub.pem");
+
+#[derive(Clone)]
+pub struct MockGitHub {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: i64,
+    /// Users by OAuth code.
+    codes: HashMap<String, MockUser>,
+    /// Users by access and refresh tokens.
+    tokens: HashMap<String, MockUser>,
+    /// Repositories by `owner/name`.
+    repos: HashMap<String, MockRepo>,
+    /// Installations of the GitHub App by account.
+    installations: HashMap<String, i64>,
+    /// Emails of the users besides the primary one, by login.
+    emails: HashMap<String, Vec<String>>,
+    /// Responses replacing the next responses by request path.
+    faults: HashMap<String, VecDeque<MockFault>>,
+    requests: HashMap<String, MockRequests>,
+}
+
+struct MockFault {
+    status: u16,
+    headers: Vec<(String, String)>,
+    message: String,
+}
+
+/// Requests received for a path.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct MockRequests {
+    pub received: usize,
+    /// Requests answered with 304 Not Modified.
+    pub not_modified: usize,
+}
+
+#[derive(Clone)]
+pub struct MockUser {
+    pub id: i64,
+    pub login: String,
+    pub name: String,
+    pub email: String,
+}
+
+struct MockRepo {
+    owner: String,
+    name: String,
+    is_organization: bool,
+    /// Content of the files on the default branch by path.
+    files: HashMap<String, Vec<u8>>,
+    /// Files of the other branches by branch.
+    branches: HashMap<String, HashMap<String, Vec<u8>>>,
+    pulls: Vec<MockPull>,
+    commits: Vec<MockCommit>,
+    archived: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct MockPull {
+    pub number: i32,
+    pub title: String,
+    pub head: String,
+    pub base: String,
+}
+
+impl MockRepo {
+    // Files of the branch, the default branch when `None`.
+    fn branch_files(&self, branch: Option<&str>) -> Option<&HashMap<String, Vec<u8>>> {
+        match branch {
+            None | Some(DEFAULT_BRANCH) => Some(&self.files),
+            Some(branch) => self.branches.get(branch),
+        }
+    }
+
+    fn branch_files_mut(&mut self, branch: Option<&str>) -> Option<&mut HashMap<String, Vec<u8>>> {
+        match branch {
+            None | Some(DEFAULT_BRANCH) => Some(&mut self.files),
+            Some(branch) => self.branches.get_mut(branch),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MockCommit {
+    /// Login of the user, or of the app, the commit is made with.
+    pub author: String,
+    pub message: String,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenParams {
+    code: Option<String>,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateRepo {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct UpdateRepo {
+    archived: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct PutContent {
+    message: String,
+    content: String,
+    sha: Option<String>,
+    branch: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    page: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ContentQuery {
+    #[serde(rename = "ref")]
+    reference: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateRef {
+    #[serde(rename = "ref")]
+    reference: String,
+}
+
+#[derive(Deserialize)]
+struct PullsQuery {
+    head: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreatePull {
+    title: String,
+    head: String,
+    base: String,
+}
+
+#[derive(Deserialize)]
+struct UpdatePull {
+    title: Option<String>,
+}
+
+impl MockGitHub {
+    /// Starts the server on a free local port, it runs until the test process exits.
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            next_id: first_id(),
+            ..State::default()
+        }));
+        let app_state = web::Data::from(state.clone());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(app_state.clone())
+                        .route("/login/oauth/access_token", web::post().to(access_token))
+                        .route("/user", web::get().to(get_user))
+                        .route("/user/emails", web::get().to(get_emails))
+                        .route("/users/{account}/installation", web::get().to(get_installation))
+                        .route("/orgs/{account}/installation", web::get().to(get_installation))
+                        .route(
+                            "/app/installations/{id}/access_tokens",
+                            web::post().to(create_installation_token),
+                        )
+                        .route("/user/repos", web::post().to(create_user_repo))
+                        .route("/orgs/{org}/repos", web::post().to(create_org_repo))
+                        .route("/repos/{owner}/{name}", web::get().to(get_repo))
+                        .route("/repos/{owner}/{name}", web::patch().to(update_repo))
+                        .route("/repos/{owner}/{name}/contents/{path:.*}", web::get().to(get_content))
+                        .route("/repos/{owner}/{name}/contents/{path:.*}", web::put().to(put_content))
+                        .route("/repos/{owner}/{name}/git/blobs/{sha}", web::get().to(get_blob))
+                        .route("/repos/{owner}/{name}/git/ref/heads/{branch:.*}", web::get().to(get_branch))
+                        .route("/repos/{owner}/{name}/git/refs", web::post().to(create_ref))
+                        .route("/repos/{owner}/{name}/pulls", web::get().to(get_pulls))
+                        .route("/repos/{owner}/{name}/pulls", web::post().to(create_pull))
+                        .route("/repos/{owner}/{name}/pulls/{number}", web::patch().to(update_pull))
+                })
+                .workers(1)
+                .bind(("127.0.0.1", 0))
+                .expect("Failed to bind mock GitHub server");
+
+                sender
+                    .send(server.addrs()[0].port())
+                    .expect("Failed to report mock GitHub server port");
+                server.run().await
+            })
+        });
+
+        let port = receiver.recv().expect("Mock GitHub server failed to start");
+
+        MockGitHub {
+            url: format!("http://127.0.0.1:{port}"),
+            state,
+        }
+    }
+
+    /// Adds a user, returns the OAuth code which is exchanged for the access token of the user.
+    pub fn add_user(&self, login: &str, email: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+
+        let code = format!("code-{login}");
+        let user = MockUser {
+            id: state.next_id,
+            login: login.to_string(),
+            name: format!("User {login}"),
+            email: email.to_string(),
+        };
+        state.codes.insert(code.clone(), user);
+
+        code
+    }
+
+    /// Adds a user and returns an access token of the user, as if it has been issued outside of the server.
+    pub fn add_user_token(&self, login: &str, email: &str) -> String {
+        let code = self.add_user(login, email);
+        let mut state = self.state.lock().unwrap();
+        let user = state.codes.remove(&code).expect("User is not added");
+        state.next_id += 1;
+
+        let token = format!("gho_{login}_{}", state.next_id);
+        state.tokens.insert(token.clone(), user);
+
+        token
+    }
+
+    /// Another access token of the user added with `add_user_token`.
+    pub fn new_token(&self, login: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let user = state
+            .tokens
+            .values()
+            .find(|user| user.login == login)
+            .cloned()
+            .expect("User has no tokens");
+        state.next_id += 1;
+
+        let token = format!("gho_{login}_{}", state.next_id);
+        state.tokens.insert(token.clone(), user);
+
+        token
+    }
+
+    /// OAuth code of the user added with `add_user_token`.
+    pub fn new_code(&self, login: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let user = state
+            .tokens
+            .values()
+            .find(|user| user.login == login)
+            .cloned()
+            .expect("User has no tokens");
+
+        let code = format!("code-{login}");
+        state.codes.insert(code.clone(), user);
+
+        code
+    }
+
+    /// Adds an email to the user, it is listed before the primary email.
+    pub fn add_email(&self, login: &str, email: &str) {
+        let mut state = self.state.lock().unwrap();
+
+        state.emails.entry(login.to_string()).or_default().push(email.to_string());
+    }
+
+    /// Responds to the next request of the contents API path with the status, the headers
+    /// and the message, instead of handling it. Responses are queued by path.
+    pub fn fail_next(&self, path: &str, status: u16, headers: &[(&str, &str)], message: &str) {
+        let mut state = self.state.lock().unwrap();
+        let fault = MockFault {
+            status,
+            headers: headers
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            message: message.to_string(),
+        };
+
+        state.faults.entry(path.to_string()).or_default().push_back(fault);
+    }
+
+    /// Requests of the contents API path received so far.
+    pub fn requests(&self, path: &str) -> MockRequests {
+        let state = self.state.lock().unwrap();
+
+        state.requests.get(path).copied().unwrap_or_default()
+    }
+
+    /// Content of the file on the default branch, `None` if there is no such file.
+    pub fn file(&self, owner: &str, name: &str, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .repos
+            .get(&format!("{owner}/{name}"))
+            .and_then(|repo| repo.files.get(path).cloned())
+    }
+
+    /// Installs the GitHub App on the account.
+    pub fn install_app(&self, account: &str) {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+
+        let id = state.next_id;
+        state.installations.insert(account.to_string(), id);
+    }
+
+    pub fn is_archived(&self, owner: &str, name: &str) -> bool {
+        let state = self.state.lock().unwrap();
+
+        state.repos.get(&format!("{owner}/{name}")).is_some_and(|repo| repo.archived)
+    }
+
+    /// Commits of the repository, from the oldest one.
+    pub fn commits(&self, owner: &str, name: &str) -> Vec<MockCommit> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .repos
+            .get(&format!("{owner}/{name}"))
+            .map(|repo| repo.commits.clone())
+            .unwrap_or_default()
+    }
+
+    /// Changes the file on the default branch, as if it has been pushed.
+    pub fn push_file(&self, owner: &str, name: &str, path: &str, content: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let repo = state
+            .repos
+            .get_mut(&format!("{owner}/{name}"))
+            .expect("Repository is not created");
+
+        repo.files.insert(path.to_string(), content.to_vec());
+    }
+
+    /// Content of the file on the branch, `None` if there is no such file or branch.
+    pub fn branch_file(&self, owner: &str, name: &str, branch: &str, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .repos
+            .get(&format!("{owner}/{name}"))
+            .and_then(|repo| repo.branch_files(Some(branch)))
+            .and_then(|files| files.get(path).cloned())
+    }
+
+    /// Changes the file on the branch, as if it has been pushed. The branch is created
+    /// from the default branch if missing.
+    pub fn push_branch_file(&self, owner: &str, name: &str, branch: &str, path: &str, content: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        let repo = state
+            .repos
+            .get_mut(&format!("{owner}/{name}"))
+            .expect("Repository is not created");
+        let default_files = repo.files.clone();
+
+        repo.branches
+            .entry(branch.to_string())
+            .or_insert(default_files)
+            .insert(path.to_string(), content.to_vec());
+    }
+
+    /// Pull requests of the repository, from the oldest one.
+    pub fn pulls(&self, owner: &str, name: &str) -> Vec<MockPull> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .repos
+            .get(&format!("{owner}/{name}"))
+            .map(|repo| repo.pulls.clone())
+            .unwrap_or_default()
+    }
+
+    /// Deletes the file from the default branch, as if it has been pushed.
+    pub fn remove_file(&self, owner: &str, name: &str, path: &str) {
+        let mut state = self.state.lock().unwrap();
+        let repo = state
+            .repos
+            .get_mut(&format!("{owner}/{name}"))
+            .expect("Repository is not created");
+
+        repo.files.remove(path);
+    }
+}
+
+/// Ids of the accounts are unique across the runs, identities of the users are kept
+/// in the database by them.
+pub fn first_id() -> i64 {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the Unix epoch");
+
+    elapsed.as_micros() as i64
+}
+
+pub fn blob_sha(content: &[u8]) -> String {
+    let mut object = format!("blob {}\0", content.len()).into_bytes();
+    object.extend_from_slice(content);
+
+    hex::encode(digest(&SHA1_FOR_LEGACY_USE_ONLY, &object))
+}
+
+async fn access_token(
+    params: web::Query<AccessTokenParams>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let user = match (&params.code, &params.refresh_token) {
+        (Some(code), _) => state.codes.remove(code),
+        (None, Some(refresh_token)) => state.tokens.get(refresh_token).cloned(),
+        (None, None) => None,
+    };
+
+    // GitHub reports OAuth errors with 200 OK.
+    let Some(user) = user else {
+        return HttpResponse::Ok()
+            .content_type("application/x-www-form-urlencoded")
+            .body("error=bad_verification_code&error_description=The+code+passed+is+incorrect+or+expired.");
+    };
+
+    state.next_id += 1;
+    let access_token = format!("gho_{}_{}", user.login, state.next_id);
+    let refresh_token = format!("ghr_{}_{}", user.login, state.next_id);
+    state.tokens.insert(access_token.clone(), user.clone());
+    state.tokens.insert(refresh_token.clone(), user);
+
+    HttpResponse::Ok()
+        .content_type("application/x-www-form-urlencoded")
+        .body(format!(
+            "access_token={access_token}&expires_in=28800&refresh_token={refresh_token}\
+             &refresh_token_expires_in=15811200&scope=&token_type=bearer"
+        ))
+}
+
+async fn get_user(req: HttpRequest, state: web::Data<Mutex<State>>) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return bad_credentials();
+    };
+
+    HttpResponse::Ok().json(account_json(&user.login, user.id, "User", Some(&user.name)))
+}
+
+async fn get_emails(
+    req: HttpRequest,
+    query: web::Query<PageQuery>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return bad_credentials();
+    };
+
+    let state = state.lock().unwrap();
+    let emails: Vec<Value> = state
+        .emails
+        .get(&user.login)
+        .into_iter()
+        .flatten()
+        .map(|email| (email, false))
+        .chain([(&user.email, true)])
+        .map(|(email, primary)| {
+            json!({
+                "email": email,
+                "verified": true,
+                "primary": primary,
+                "visibility": primary.then_some("private"),
+            })
+        })
+        .collect();
+
+    // Pages are numbered from 1, the next one is linked until the last one.
+    let page = query.page.unwrap_or(1).max(1);
+    let start = ((page - 1) * EMAILS_PAGE_SIZE).min(emails.len());
+    let end = (start + EMAILS_PAGE_SIZE).min(emails.len());
+    let mut response = HttpResponse::Ok();
+    if end < emails.len() {
+        let url = format!("http://{}/user/emails?page={}", req.connection_info().host(), page + 1);
+        response.insert_header(("Link", format!("<{url}>; rel=\"next\"")));
+    }
+
+    response.json(&emails[start..end])
+}
+
+async fn create_user_repo(
+    req: HttpRequest,
+    body: web::Json<CreateRepo>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return bad_credentials();
+    };
+
+    create_repo(&state, &user.login, false, &body.name)
+}
+
+async fn create_org_repo(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CreateRepo>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    create_repo(&state, &path.into_inner(), true, &body.name)
+}
+
+fn create_repo(state: &Mutex<State>, owner: &str, is_organization: bool, name: &str) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let key = format!("{owner}/{name}");
+
+    if state.repos.contains_key(&key) {
+        return HttpResponse::UnprocessableEntity().json(json!({
+            "message": "Repository creation failed.",
+            "errors": [{
+                "resource": "Repository",
+                "code": "custom",
+                "field": "name",
+                "message": "name already exists on this account",
+            }],
+        }));
+    }
+
+    let repo = MockRepo {
+        owner: owner.to_string(),
+        name: name.to_string(),
+        is_organization,
+        files: HashMap::new(),
+        branches: HashMap::new(),
+        pulls: Vec::new(),
+        commits: Vec::new(),
+        archived: false,
+    };
+    let body = repo_json(&repo);
+    state.repos.insert(key, repo);
+
+    HttpResponse::Created().json(body)
+}
+
+async fn get_repo(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name) = path.into_inner();
+    let state = state.lock().unwrap();
+
+    match state.repos.get(&format!("{owner}/{name}")) {
+        Some(repo) => HttpResponse::Ok().json(repo_json(repo)),
+        None => not_found(),
+    }
+}
+
+async fn update_repo(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    input: web::Json<UpdateRepo>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name) = path.into_inner();
+    let mut state = state.lock().unwrap();
+
+    match state.repos.get_mut(&format!("{owner}/{name}")) {
+        Some(repo) => {
+            if let Some(archived) = input.archived {
+                repo.archived = archived;
+            }
+
+            HttpResponse::Ok().json(repo_json(repo))
+        }
+        None => not_found(),
+    }
+}
+
+async fn get_content(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    query: web::Query<ContentQuery>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let mut state = state.lock().unwrap();
+    if let Some(fault) = intercept(&mut state, req.path()) {
+        return fault;
+    }
+
+    let (owner, name, path) = path.into_inner();
+    let Some(repo) = state.repos.get(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+    // Commits are not kept, refs other than the branches read the default branch.
+    let files = repo
+        .branch_files(query.reference.as_deref())
+        .unwrap_or(&repo.files);
+    let Some(content) = files.get(&path) else {
+        // Directories are listed with their files, without the content.
+        let prefix = format!("{path}/");
+        let entries: Vec<Value> = files
+            .iter()
+            .filter(|(file_path, _)| file_path.starts_with(&prefix))
+            .map(|(file_path, content)| json!({ "type": "file", "path": file_path, "sha": blob_sha(content) }))
+            .collect();
+
+        return match entries.is_empty() {
+            true => not_found(),
+            false => HttpResponse::Ok().json(entries),
+        };
+    };
+
+    let sha = blob_sha(content);
+    let etag = format!("\"{sha}\"");
+    let revalidated = req
+        .headers()
+        .get("If-None-Match")
+        .is_some_and(|value| value.as_bytes() == etag.as_bytes());
+    if revalidated {
+        state.requests.entry(req.path().to_string()).or_default().not_modified += 1;
+
+        return HttpResponse::NotModified().insert_header(("ETag", etag)).finish();
+    }
+
+    let (encoding, encoded) = match content.len() > LARGE_FILE_SIZE {
+        true => ("none", String::new()),
+        false => ("base64", general_purpose::STANDARD.encode(content)),
+    };
+
+    HttpResponse::Ok().insert_header(("ETag", etag)).json(json!({
+        "type": "file",
+        "name": path.rsplit('/').next(),
+        "path": path,
+        "sha": sha,
+        "size": content.len(),
+        "encoding": encoding,
+        "content": encoded,
+    }))
+}
+
+async fn get_blob(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name, sha) = path.into_inner();
+    let state = state.lock().unwrap();
+    let Some(content) = state
+        .repos
+        .get(&format!("{owner}/{name}"))
+        .and_then(|repo| {
+            repo.branches
+                .values()
+                .chain([&repo.files])
+                .flat_map(|files| files.values())
+                .find(|content| blob_sha(content) == sha)
+        })
+    else {
+        return not_found();
+    };
+
+    HttpResponse::Ok().json(json!({
+        "sha": sha,
+        "size": content.len(),
+        "encoding": "base64",
+        "content": general_purpose::STANDARD.encode(content),
+    }))
+}
+
+async fn put_content(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    body: web::Json<PutContent>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return bad_credentials();
+    };
+
+    let (owner, name, path) = path.into_inner();
+    let mut state = state.lock().unwrap();
+    let Some(repo) = state.repos.get_mut(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+
+    let Some(files) = repo.branch_files_mut(body.branch.as_deref()) else {
+        return not_found();
+    };
+    let current_sha = files.get(&path).map(|content| blob_sha(content));
+    match (&current_sha, &body.sha) {
+        (Some(_), None) => {
+            return HttpResponse::UnprocessableEntity()
+                .json(json!({ "message": "Invalid request.\n\n\"sha\" wasn't supplied." }));
+        }
+        (current, Some(sha)) if current.as_ref() != Some(sha) => {
+            return HttpResponse::Conflict()
+                .json(json!({ "message": format!("{path} does not match {sha}") }));
+        }
+        _ => {}
+    }
+
+    let Ok(content) = general_purpose::STANDARD.decode(&body.content) else {
+        return HttpResponse::UnprocessableEntity()
+            .json(json!({ "message": "content is not valid Base64" }));
+    };
+    let sha = blob_sha(&content);
+    let size = content.len();
+    files.insert(path.clone(), content);
+    repo.commits.push(MockCommit {
+        author: user.login.clone(),
+        message: body.message.clone(),
+    });
+
+    let repo_url = format!("https://api.github.test/repos/{owner}/{name}");
+    let html_url = format!("https://github.test/{owner}/{name}");
+    let agent = json!({
+        "date": "2026-10-18T12:00:00Z",
+        "name": user.name,
+        "email": user.email,
+    });
+    let mut response = match current_sha {
+        Some(_) => HttpResponse::Ok(),
+        None => HttpResponse::Created(),
+    };
+
+    response.json(json!({
+        "content": {
+            "name": path.rsplit('/').next(),
+            "path": path,
+            "sha": sha,
+            "size": size,
+            "url": format!("{repo_url}/contents/{path}"),
+            "html_url": format!("{html_url}/blob/{DEFAULT_BRANCH}/{path}"),
+            "git_url": format!("{repo_url}/git/blobs/{sha}"),
+            "download_url": format!("https://raw.github.test/{owner}/{name}/{DEFAULT_BRANCH}/{path}"),
+            "type": "file",
+            "_links": {
+                "self": format!("{repo_url}/contents/{path}"),
+                "git": format!("{repo_url}/git/blobs/{sha}"),
+                "html": format!("{html_url}/blob/{DEFAULT_BRANCH}/{path}"),
+            },
+        },
+        "commit": {
+            "sha": blob_sha(body.message.as_bytes()),
+            "node_id": "C_mock",
+            "url": format!("{repo_url}/git/commits/mock"),
+            "html_url": format!("{html_url}/commit/mock"),
+            "author": agent,
+            "committer": agent,
+            "message": body.message,
+            "tree": {
+                "url": format!("{repo_url}/git/trees/mock"),
+                "sha": sha,
+            },
+            "parents": [],
+            "verification": {
+                "verified": false,
+                "reason": "unsigned",
+                "signature": null,
+                "payload": null,
+            },
+        },
+    }))
+}
+
+async fn get_branch(
+    req: HttpRequest,
+    path: web::Path<(String, String, String)>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name, branch) = path.into_inner();
+    let state = state.lock().unwrap();
+    let Some(files) = state
+        .repos
+        .get(&format!("{owner}/{name}"))
+        .and_then(|repo| repo.branch_files(Some(&branch)))
+    else {
+        return not_found();
+    };
+
+    HttpResponse::Ok().json(json!({
+        "ref": format!("refs/heads/{branch}"),
+        "object": { "type": "commit", "sha": head_sha(files) },
+    }))
+}
+
+async fn create_ref(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Json<CreateRef>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name) = path.into_inner();
+    let mut state = state.lock().unwrap();
+    let Some(repo) = state.repos.get_mut(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+    let Some(branch) = body.reference.strip_prefix("refs/heads/") else {
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "Reference name is invalid" }));
+    };
+    if repo.branch_files(Some(branch)).is_some() {
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "Reference already exists" }));
+    }
+
+    // The branch starts from the default branch, whatever commit is requested.
+    let files = repo.files.clone();
+    let sha = head_sha(&files);
+    repo.branches.insert(branch.to_string(), files);
+
+    HttpResponse::Created().json(json!({
+        "ref": body.reference,
+        "object": { "type": "commit", "sha": sha },
+    }))
+}
+
+async fn get_pulls(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<PullsQuery>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name) = path.into_inner();
+    let state = state.lock().unwrap();
+    let Some(repo) = state.repos.get(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+    // Pull requests are never closed, `state` is ignored.
+    let pulls: Vec<Value> = repo
+        .pulls
+        .iter()
+        .filter(|pull| {
+            query
+                .head
+                .as_ref()
+                .is_none_or(|head| *head == format!("{owner}:{}", pull.head))
+        })
+        .map(|pull| pull_json(repo, pull))
+        .collect();
+
+    HttpResponse::Ok().json(pulls)
+}
+
+async fn create_pull(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    body: web::Json<CreatePull>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name) = path.into_inner();
+    let mut state = state.lock().unwrap();
+    let Some(repo) = state.repos.get_mut(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+    if repo.branch_files(Some(&body.head)).is_none() || repo.branch_files(Some(&body.base)).is_none() {
+        return HttpResponse::UnprocessableEntity().json(json!({ "message": "Validation Failed" }));
+    }
+    if repo.pulls.iter().any(|pull| pull.head == body.head) {
+        return HttpResponse::UnprocessableEntity()
+            .json(json!({ "message": format!("A pull request already exists for {owner}:{}.", body.head) }));
+    }
+
+    let pull = MockPull {
+        number: repo.pulls.len() as i32 + 1,
+        title: body.title.clone(),
+        head: body.head.clone(),
+        base: body.base.clone(),
+    };
+    let response = pull_json(repo, &pull);
+    repo.pulls.push(pull);
+
+    HttpResponse::Created().json(response)
+}
+
+async fn update_pull(
+    req: HttpRequest,
+    path: web::Path<(String, String, i32)>,
+    body: web::Json<UpdatePull>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return bad_credentials();
+    }
+
+    let (owner, name, number) = path.into_inner();
+    let mut state = state.lock().unwrap();
+    let Some(repo) = state.repos.get_mut(&format!("{owner}/{name}")) else {
+        return not_found();
+    };
+    let Some(index) = repo.pulls.iter().position(|pull| pull.number == number) else {
+        return not_found();
+    };
+    if let Some(title) = &body.title {
+        repo.pulls[index].title = title.clone();
+    }
+
+    HttpResponse::Ok().json(pull_json(repo, &repo.pulls[index]))
+}
+
+fn pull_json(repo: &MockRepo, pull: &MockPull) -> Value {
+    json!({
+        "number": pull.number,
+        "state": "open",
+        "title": pull.title,
+        "html_url": format!("https://github.test/{}/{}/pull/{}", repo.owner, repo.name, pull.number),
+        "head": { "ref": pull.head },
+        "base": { "ref": pull.base },
+    })
+}
+
+// Commits are not kept, the head of a branch is identified by its files.
+fn head_sha(files: &HashMap<String, Vec<u8>>) -> String {
+    let mut paths: Vec<_> = files.keys().collect();
+    paths.sort();
+    let tree: Vec<u8> = paths
+        .into_iter()
+        .flat_map(|path| [path.as_bytes(), &files[path]].concat())
+        .collect();
+
+    blob_sha(&tree)
+}
+
+async fn get_installation(
+    req: HttpRequest,
+    path: web::Path<String>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if !app_authenticated(&req) {
+        return bad_credentials();
+    }
+
+    let state = state.lock().unwrap();
+
+    match state.installations.get(&path.into_inner()) {
+        Some(id) => HttpResponse::Ok().json(json!({ "id": id, "app_id": APP_ID })),
+        None => not_found(),
+    }
+}
+
+async fn create_installation_token(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if !app_authenticated(&req) {
+        return bad_credentials();
+    }
+
+    let id = path.into_inner();
+    let mut state = state.lock().unwrap();
+    if !state.installations.values().any(|installation| *installation == id) {
+        return not_found();
+    }
+
+    state.next_id += 1;
+    let token = format!("ghs_{id}_{}", state.next_id);
+    let app = MockUser {
+        id,
+        login: APP_LOGIN.to_string(),
+        name: APP_LOGIN.to_string(),
+        email: format!("{id}+{APP_LOGIN}@users.noreply.github.test"),
+    };
+    state.tokens.insert(token.clone(), app);
+
+    HttpResponse::Created().json(json!({
+        "token": token,
+        "expires_at": (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339(),
+        "permissions": { "contents": "write" },
+        "repository_selection": "all",
+    }))
+}
+
+// Counts the request of the path, and returns the response replacing it if there is one.
+fn intercept(state: &mut State, path: &str) -> Option<HttpResponse> {
+    state.requests.entry(path.to_string()).or_default().received += 1;
+    let fault = state.faults.get_mut(path)?.pop_front()?;
+
+    let status = actix_web::http::StatusCode::from_u16(fault.status).expect("Invalid status");
+    let mut response = HttpResponse::build(status);
+    for header in fault.headers {
+        response.insert_header(header);
+    }
+
+    Some(response.json(json!({ "message": fault.message })))
+}
+
+fn authenticated(req: &HttpRequest, state: &Mutex<State>) -> Option<MockUser> {
+    let token = bearer_token(req)?;
+
+    state.lock().unwrap().tokens.get(token).cloned()
+}
+
+// Whether the request has a JWT of the app, signed with its private key.
+fn app_authenticated(req: &HttpRequest) -> bool {
+    let Some(token) = bearer_token(req) else {
+        return false;
+    };
+    let key = DecodingKey::from_rsa_pem(APP_PUBLIC_KEY.as_bytes()).expect("Invalid app public key");
+    let mut validation = Validation::new(Algorithm::RS256);
+    validation.set_issuer(&[APP_ID]);
+
+    decode::<Value>(token, &key, &validation).is_ok()
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+fn repo_json(repo: &MockRepo) -> Value {
+    let kind = match repo.is_organization {
+        true => "Organization",
+        false => "User",
+    };
+
+    json!({
+        "name": repo.name,
+        "full_name": format!("{}/{}", repo.owner, repo.name),
+        "owner": account_json(&repo.owner, 1, kind, None),
+        "private": true,
+        "html_url": format!("https://github.test/{}/{}", repo.owner, repo.name),
+        "default_branch": DEFAULT_BRANCH,
+        "archived": repo.archived,
+        "permissions": { "admin": true, "push": true, "pull": true },
+    })
+}
+
+fn account_json(login: &str, id: i64, kind: &str, name: Option<&str>) -> Value {
+    let url = format!("https://api.github.test/users/{login}");
+
+    json!({
+        "login": login,
+        "id": id,
+        "node_id": format!("U_{id}"),
+        "name": name,
+        "avatar_url": format!("https://avatars.github.test/u/{id}"),
+        "gravatar_id": "",
+        "url": url,
+        "html_url": format!("https://github.test/{login}"),
+        "followers_url": format!("{url}/followers"),
+        "following_url": format!("{url}/following{{/other_user}}"),
+        "gists_url": format!("{url}/gists{{/gist_id}}"),
+        "starred_url": format!("{url}/starred{{/owner}}{{/repo}}"),
+        "subscriptions_url": format!("{url}/subscriptions"),
+        "organizations_url": format!("{url}/orgs"),
+        "repos_url": format!("{url}/repos"),
+        "events_url": format!("{url}/events{{/privacy}}"),
+        "received_events_url": format!("{url}/received_events"),
+        "type": kind,
+        "site_admin": false,
+        "public_repos": 0,
+        "public_gists": 0,
+        "followers": 0,
+        "following": 0,
+        "created_at": "2026-10-18T12:00:00Z",
+        "updated_at": "2026-10-18T12:00:00Z",
+    })
+}
+
+fn bad_credentials() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({ "message": "Bad credentials" }))
+}
+
+fn not_found() -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "message": "Not Found" }))
+}
@@ -0,0 +1,410 @@
+//! In-memory GitLab serving the OAuth and REST API endpoints the server uses (`GITLAB_URL`).
+//!
+//! Users are added with `add_user`, which returns the OAuth code to sign in with.
+//! Projects and their files on the default branch live in memory, projects are
+//! addressed by their full path the way GitLab does.
+
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use base64::{engine::general_purpose, Engine as _};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use super::mock_github::{blob_sha, first_id, MockCommit, MockUser, DEFAULT_BRANCH};
+
+#[derive(Clone)]
+pub struct MockGitLab {
+    pub url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    next_id: i64,
+    /// Users by OAuth code.
+    codes: HashMap<String, MockUser>,
+    /// Users by access and refresh tokens.
+    tokens: HashMap<String, MockUser>,
+    /// Projects by full path.
+    projects: HashMap<String, MockProject>,
+}
+
+struct MockProject {
+    path: String,
+    /// Content of the files on the default branch by path, with the commit which has changed them.
+    files: HashMap<String, (Vec<u8>, String)>,
+    commits: Vec<MockCommit>,
+    archived: bool,
+}
+
+#[derive(Deserialize)]
+struct AccessTokenParams {
+    code: Option<String>,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CreateProject {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    #[serde(rename = "ref")]
+    reference: String,
+}
+
+#[derive(Deserialize)]
+struct CreateCommit {
+    branch: String,
+    commit_message: String,
+    actions: Vec<CommitAction>,
+}
+
+#[derive(Deserialize)]
+struct CommitAction {
+    action: String,
+    file_path: String,
+    content: String,
+    last_commit_id: Option<String>,
+}
+
+impl MockGitLab {
+    /// Starts the server on a free local port, it runs until the test process exits.
+    pub fn start() -> Self {
+        let state = Arc::new(Mutex::new(State {
+            next_id: first_id(),
+            ..State::default()
+        }));
+        let app_state = web::Data::from(state.clone());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            actix_web::rt::System::new().block_on(async move {
+                let server = HttpServer::new(move || {
+                    App::new()
+                        .app_data(app_state.clone())
+                        .route("/oauth/token", web::post().to(access_token))
+                        .route("/api/v4/user", web::get().to(get_user))
+                        .route("/api/v4/projects", web::post().to(create_project))
+                        .route("/api/v4/projects/{id}", web::get().to(get_project))
+                        .route("/api/v4/projects/{id}/archive", web::post().to(archive_project))
+                        .route("/api/v4/projects/{id}/repository/files/{path}", web::get().to(get_file))
+                        .route("/api/v4/projects/{id}/repository/commits", web::post().to(create_commit))
+                })
+                .workers(1)
+                .bind(("127.0.0.1", 0))
+                .expect("Failed to bind mock GitLab server");
+
+                sender
+                    .send(server.addrs()[0].port())
+                    .expect("Failed to report mock GitLab server port");
+                server.run().await
+            })
+        });
+
+        let port = receiver.recv().expect("Mock GitLab server failed to start");
+
+        MockGitLab {
+            url: format!("http://127.0.0.1:{port}"),
+            state,
+        }
+    }
+
+    /// Adds a user, returns the OAuth code which is exchanged for the access token of the user.
+    pub fn add_user(&self, login: &str, email: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+
+        let code = format!("code-{login}-{}", state.next_id);
+        let user = MockUser {
+            id: state.next_id,
+            login: login.to_string(),
+            name: format!("User {login}"),
+            email: email.to_string(),
+        };
+        state.codes.insert(code.clone(), user);
+
+        code
+    }
+
+    /// Another OAuth code of the user added with `add_user`.
+    pub fn new_code(&self, login: &str) -> String {
+        let mut state = self.state.lock().unwrap();
+        let user = state
+            .tokens
+            .values()
+            .chain(state.codes.values())
+            .find(|user| user.login == login)
+            .cloned()
+            .expect("User is not added");
+        state.next_id += 1;
+
+        let code = format!("code-{login}-{}", state.next_id);
+        state.codes.insert(code.clone(), user);
+
+        code
+    }
+
+    /// Content of the file on the default branch, `None` if there is no such file.
+    pub fn file(&self, project: &str, path: &str) -> Option<Vec<u8>> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .projects
+            .get(project)
+            .and_then(|project| project.files.get(path).map(|(content, _)| content.clone()))
+    }
+
+    /// Commits of the project, from the oldest one.
+    pub fn commits(&self, project: &str) -> Vec<MockCommit> {
+        let state = self.state.lock().unwrap();
+
+        state
+            .projects
+            .get(project)
+            .map(|project| project.commits.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn is_archived(&self, project: &str) -> bool {
+        let state = self.state.lock().unwrap();
+
+        state.projects.get(project).is_some_and(|project| project.archived)
+    }
+
+    /// Changes the file on the default branch, as if it has been pushed.
+    pub fn push_file(&self, project: &str, path: &str, content: &[u8]) {
+        let mut state = self.state.lock().unwrap();
+        state.next_id += 1;
+
+        let commit_id = blob_sha(state.next_id.to_string().as_bytes());
+        let project = state.projects.get_mut(project).expect("Project is not created");
+        project.files.insert(path.to_string(), (content.to_vec(), commit_id));
+    }
+}
+
+async fn access_token(params: web::Form<AccessTokenParams>, state: web::Data<Mutex<State>>) -> HttpResponse {
+    let mut state = state.lock().unwrap();
+    let user = match (&params.code, &params.refresh_token) {
+        (Some(code), _) => state.codes.remove(code),
+        (None, Some(refresh_token)) => state.tokens.remove(refresh_token),
+        (None, None) => None,
+    };
+
+    let Some(user) = user else {
+        return HttpResponse::BadRequest().json(json!({
+            "error": "invalid_grant",
+            "error_description": "The provided authorization grant is invalid, expired or revoked.",
+        }));
+    };
+
+    state.next_id += 1;
+    let access_token = format!("glpat_{}_{}", user.login, state.next_id);
+    let refresh_token = format!("glrt_{}_{}", user.login, state.next_id);
+    state.tokens.insert(access_token.clone(), user.clone());
+    state.tokens.insert(refresh_token.clone(), user);
+
+    HttpResponse::Ok().json(json!({
+        "access_token": access_token,
+        "token_type": "Bearer",
+        "expires_in": 7200,
+        "refresh_token": refresh_token,
+        "scope": "api read_user",
+        "created_at": 1760788800,
+    }))
+}
+
+async fn get_user(req: HttpRequest, state: web::Data<Mutex<State>>) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return unauthorized();
+    };
+
+    HttpResponse::Ok().json(json!({
+        "id": user.id,
+        "username": user.login,
+        "name": user.name,
+        "email": user.email,
+        "state": "active",
+    }))
+}
+
+async fn create_project(
+    req: HttpRequest,
+    body: web::Json<CreateProject>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return unauthorized();
+    };
+
+    let path = format!("{}/{}", user.login, body.path);
+    let mut state = state.lock().unwrap();
+
+    if state.projects.contains_key(&path) {
+        return HttpResponse::BadRequest().json(json!({
+            "message": { "path": ["has already been taken"] },
+        }));
+    }
+
+    let project = MockProject {
+        path: path.clone(),
+        files: HashMap::new(),
+        commits: Vec::new(),
+        archived: false,
+    };
+    let body = project_json(&project);
+    state.projects.insert(path, project);
+
+    HttpResponse::Created().json(body)
+}
+
+async fn get_project(req: HttpRequest, path: web::Path<String>, state: web::Data<Mutex<State>>) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return unauthorized();
+    }
+
+    let state = state.lock().unwrap();
+
+    match state.projects.get(&path.into_inner()) {
+        Some(project) => HttpResponse::Ok().json(project_json(project)),
+        None => not_found("404 Project Not Found"),
+    }
+}
+
+async fn archive_project(req: HttpRequest, path: web::Path<String>, state: web::Data<Mutex<State>>) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return unauthorized();
+    }
+
+    let mut state = state.lock().unwrap();
+
+    match state.projects.get_mut(&path.into_inner()) {
+        Some(project) => {
+            project.archived = true;
+            HttpResponse::Created().json(project_json(project))
+        }
+        None => not_found("404 Project Not Found"),
+    }
+}
+
+async fn get_file(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    query: web::Query<FileQuery>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    if authenticated(&req, &state).is_none() {
+        return unauthorized();
+    }
+
+    let (id, file_path) = path.into_inner();
+    let state = state.lock().unwrap();
+    let Some(project) = state.projects.get(&id) else {
+        return not_found("404 Project Not Found");
+    };
+    if query.reference != DEFAULT_BRANCH {
+        return not_found("404 Commit Not Found");
+    }
+    let Some((content, last_commit_id)) = project.files.get(&file_path) else {
+        return not_found("404 File Not Found");
+    };
+
+    HttpResponse::Ok().json(json!({
+        "file_name": file_path.rsplit('/').next(),
+        "file_path": file_path,
+        "size": content.len(),
+        "encoding": "base64",
+        "content": general_purpose::STANDARD.encode(content),
+        "ref": query.reference,
+        "blob_id": blob_sha(content),
+        "commit_id": last_commit_id,
+        "last_commit_id": last_commit_id,
+    }))
+}
+
+async fn create_commit(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Json<CreateCommit>,
+    state: web::Data<Mutex<State>>,
+) -> HttpResponse {
+    let Some(user) = authenticated(&req, &state) else {
+        return unauthorized();
+    };
+
+    let mut state = state.lock().unwrap();
+    state.next_id += 1;
+    let commit_id = blob_sha(state.next_id.to_string().as_bytes());
+    let Some(project) = state.projects.get_mut(&path.into_inner()) else {
+        return not_found("404 Project Not Found");
+    };
+    if body.branch != DEFAULT_BRANCH {
+        return not_found("404 Branch Not Found");
+    }
+
+    for action in &body.actions {
+        let current = project.files.get(&action.file_path).map(|(_, commit)| commit);
+        let valid = match action.action.as_str() {
+            "create" => current.is_none(),
+            "update" => current.is_some() && current == action.last_commit_id.as_ref(),
+            _ => false,
+        };
+        if !valid {
+            return HttpResponse::BadRequest()
+                .json(json!({ "message": format!("A file with this name doesn't exist or has changed: {}", action.file_path) }));
+        }
+
+        let Ok(content) = general_purpose::STANDARD.decode(&action.content) else {
+            return HttpResponse::BadRequest().json(json!({ "message": "content is not valid Base64" }));
+        };
+        project.files.insert(action.file_path.clone(), (content, commit_id.clone()));
+    }
+    project.commits.push(MockCommit {
+        author: user.login,
+        message: body.commit_message.clone(),
+    });
+
+    HttpResponse::Created().json(json!({
+        "id": commit_id,
+        "short_id": &commit_id[..8],
+        "title": body.commit_message,
+        "message": body.commit_message,
+        "author_name": user.name,
+        "author_email": user.email,
+    }))
+}
+
+fn authenticated(req: &HttpRequest, state: &Mutex<State>) -> Option<MockUser> {
+    let token = req
+        .headers()
+        .get("Authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")?;
+
+    state.lock().unwrap().tokens.get(token).cloned()
+}
+
+fn project_json(project: &MockProject) -> Value {
+    let default_branch = match project.files.is_empty() {
+        true => None,
+        false => Some(DEFAULT_BRANCH),
+    };
+
+    json!({
+        "path_with_namespace": project.path,
+        "web_url": format!("https://gitlab.test/{}", project.path),
+        "default_branch": default_branch,
+        "archived": project.archived,
+    })
+}
+
+fn unauthorized() -> HttpResponse {
+    HttpResponse::Unauthorized().json(json!({ "message": "401 Unauthorized" }))
+}
+
+fn not_found(message: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(json!({ "message": message }))
+}
@@ -0,0 +1,285 @@
+//! Server running against the mock GitHub, the mock GitLab and the database from `TEST_DATABASE_URL`.
+//! With `spawn_app` the server authenticates as the GitHub App on the accounts the app
+//! is installed on, `spawn_app_with` configures the server without it.
+//!
+//! The server reads its settings from the environment, so one server and its mocks
+//! are shared by all tests of a test binary, which spawn it with the same integrations.
+//! Tests fail when `TEST_DATABASE_URL` is not set, unless `SKIP_DATABASE_TESTS` is set
+//! to skip them. Migrations are run on the database when the server starts.
+
+// Each test binary uses a part of the helpers.
+#![allow(dead_code)]
+
+pub mod mock_github;
+pub mod mock_gitlab;
+pub mod ws;
+
+use mock_github::MockGitHub;
+use mock_gitlab::MockGitLab;
+use reqwest::{Client, RequestBuilder};
+use ring::rand::SystemRandom;
+use ring::signature::Ed25519KeyPair;
+use serde_json::{json, Value};
+use std::env;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process;
+use std::sync::OnceLock;
+use std::thread;
+use std::time::Duration;
+
+/// Design revisions kept by the server, older ones are pruned.
+pub const DESIGN_REVISIONS_KEEP: usize = 5;
+
+pub struct TestApp {
+    pub url: String,
+    pub github: MockGitHub,
+    pub gitlab: MockGitLab,
+    pub client: Client,
+}
+
+/// Signed in user of the server.
+pub struct TestUser {
+    pub login: String,
+    pub access_token: String,
+}
+
+/// Optional integrations the server is configured with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Integrations {
+    /// GitHub App, without it repositories are accessed with the OAuth tokens of the users.
+    pub github_app: bool,
+}
+
+impl Default for Integrations {
+    fn default() -> Self {
+        Integrations { github_app: true }
+    }
+}
+
+static APP: OnceLock<Option<(Integrations, String, MockGitHub, MockGitLab)>> = OnceLock::new();
+
+/// Server shared by the tests with all integrations,
+/// `None` when the tests are skipped with `SKIP_DATABASE_TESTS`.
+pub fn spawn_app() -> Option<TestApp> {
+    spawn_app_with(Integrations::default())
+}
+
+/// Server shared by the tests with the integrations.
+pub fn spawn_app_with(integrations: Integrations) -> Option<TestApp> {
+    let Some((configured, url, github, gitlab)) = APP.get_or_init(|| start_app(integrations)) else {
+        eprintln!("SKIP_DATABASE_TESTS is set, skipping the test");
+        return None;
+    };
+
+    assert_eq!(
+        *configured, integrations,
+        "tests of a test binary must spawn the server with the same integrations"
+    );
+
+    Some(TestApp {
+        url: url.to_owned(),
+        github: github.to_owned(),
+        gitlab: gitlab.to_owned(),
+        client: Client::new(),
+    })
+}
+
+fn start_app(integrations: Integrations) -> Option<(Integrations, String, MockGitHub, MockGitLab)> {
+    let Ok(database_url) = env::var("TEST_DATABASE_URL") else {
+        if env::var_os("SKIP_DATABASE_TESTS").is_some() {
+            return None;
+        }
+
+        panic!("TEST_DATABASE_URL must be set to a disposable database, or SKIP_DATABASE_TESTS to skip the test");
+    };
+    let github = MockGitHub::start();
+    let gitlab = MockGitLab::start();
+    let random = SystemRandom::new();
+    let session_key = Ed25519KeyPair::generate_pkcs8(&random).expect("Failed to generate session key");
+
+    env::set_var("GITHUB_URL", &github.url);
+    env::set_var("GITHUB_API_URL", &github.url);
+    env::set_var("GITHUB_CLIENT_ID", "test-client-id");
+    env::set_var("GITHUB_CLIENT_SECRET", "test-client-secret");
+    if integrations.github_app {
+        env::set_var("GITHUB_APP_ID", mock_github::APP_ID);
+        env::set_var("GITHUB_APP_PRIVATE_KEY", include_str!("github_app_key.pem"));
+    } else {
+        env::remove_var("GITHUB_APP_ID");
+        env::remove_var("GITHUB_APP_PRIVATE_KEY");
+    }
+    env::set_var("GITLAB_URL", &gitlab.url);
+    env::set_var("GITLAB_CLIENT_ID", "test-client-id");
+    env::set_var("GITLAB_CLIENT_SECRET", "test-client-secret");
+    env::set_var("GITLAB_REDIRECT_URI", "http://localhost/auth/gitlab/callback");
+    env::set_var("GITHUB_WEBHOOK_SECRET", "test-webhook-secret");
+    env::set_var("SESSION_ED25519_KEY", hex::encode(session_key.as_ref()));
+    env::set_var("AES_256_GCM_KEY", hex::encode([7u8; 32]));
+    env::set_var("AUTHORITY", "http://localhost");
+    env::set_var("LOCAL_REPOS_DIR", local_repos_dir());
+    env::set_var("DESIGN_REVISIONS_KEEP", DESIGN_REVISIONS_KEEP.to_string());
+
+    // The port is released right away, the server binds it again.
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("Failed to find a free port")
+        .port();
+
+    thread::spawn(move || {
+        actix_web::rt::System::new()
+            .block_on(server::Server::new(port).run(database_url))
+            .expect("Failed to run the server");
+    });
+
+    // Migrations run before the server starts listening.
+    for _ in 0..300 {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            return Some((integrations, format!("http://127.0.0.1:{port}"), github, gitlab));
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    panic!("Server has not started on port {port}");
+}
+
+impl TestApp {
+    pub fn get(&self, path: &str, user: &TestUser) -> RequestBuilder {
+        self.client
+            .get(format!("{}{path}", self.url))
+            .bearer_auth(&user.access_token)
+    }
+
+    pub fn post(&self, path: &str, user: &TestUser) -> RequestBuilder {
+        self.client
+            .post(format!("{}{path}", self.url))
+            .bearer_auth(&user.access_token)
+    }
+
+    pub fn put(&self, path: &str, user: &TestUser) -> RequestBuilder {
+        self.client
+            .put(format!("{}{path}", self.url))
+            .bearer_auth(&user.access_token)
+    }
+
+    pub fn patch(&self, path: &str, user: &TestUser) -> RequestBuilder {
+        self.client
+            .patch(format!("{}{path}", self.url))
+            .bearer_auth(&user.access_token)
+    }
+
+    pub fn delete(&self, path: &str, user: &TestUser) -> RequestBuilder {
+        self.client
+            .delete(format!("{}{path}", self.url))
+            .bearer_auth(&user.access_token)
+    }
+
+    /// Signs in a new GitHub user with the OAuth code flow.
+    pub async fn sign_in(&self) -> TestUser {
+        let login = format!("user-{}", uuid::Uuid::new_v4().simple());
+        let code = self.github.add_user(&login, &format!("{login}@example.com"));
+
+        let response = self
+            .client
+            .post(format!("{}/auth/github/access_token", self.url))
+            .query(&[("code", code)])
+            .send()
+            .await
+            .expect("Failed to request access token");
+        let body = json(response, 200).await;
+
+        TestUser {
+            login,
+            access_token: body["accessToken"]
+                .as_str()
+                .expect("Access token is missing")
+                .to_string(),
+        }
+    }
+}
+
+impl TestApp {
+    /// Creates a GitHub repository of the user.
+    pub async fn create_repo(&self, user: &TestUser, name: &str) -> Value {
+        let response = self.post("/repos", user).json(&repo_input(user, name)).send().await.unwrap();
+
+        json(response, 200).await
+    }
+
+    /// Creates a local repository of the user.
+    pub async fn create_local_repo(&self, user: &TestUser, name: &str) -> Value {
+        let response = self
+            .post("/repos", user)
+            .json(&local_repo_input(user, name))
+            .send()
+            .await
+            .unwrap();
+
+        json(response, 200).await
+    }
+
+    /// Creates a project of the user linked to the repository.
+    pub async fn create_project(&self, user: &TestUser, repo_id: &str) -> Value {
+        let response = self
+            .post("/projects", user)
+            .json(&json!({ "name": format!("{}-project", user.login), "repoId": repo_id }))
+            .send()
+            .await
+            .unwrap();
+
+        json(response, 200).await
+    }
+
+    pub async fn get_project(&self, user: &TestUser, project_id: &str) -> Value {
+        let response = self.get(&format!("/projects/{project_id}"), user).send().await.unwrap();
+
+        json(response, 200).await
+    }
+
+    /// Saves the design to the design branch of the repository, checking the status.
+    pub async fn save_design(&self, user: &TestUser, repo_id: &str, design: &Value, status: u16) -> Value {
+        let response = self
+            .put(&format!("/repos/{repo_id}/save_design"), user)
+            .json(&json!({ "message": "Update design", "content": design }))
+            .send()
+            .await
+            .unwrap();
+
+        json(response, status).await
+    }
+}
+
+pub fn repo_input(user: &TestUser, name: &str) -> Value {
+    json!({
+        "name": name,
+        "owner": user.login,
+        "isOrganization": false,
+        "params": { "name": name, "private": true },
+    })
+}
+
+pub fn local_repo_input(user: &TestUser, name: &str) -> Value {
+    json!({
+        "name": name,
+        "owner": user.login,
+        "isOrganization": false,
+        "params": {},
+        "provider": "local",
+    })
+}
+
+/// Directory of the local repositories of the server of this test binary.
+pub fn local_repos_dir() -> PathBuf {
+    env::temp_dir().join(format!("server-test-repos-{}", process::id()))
+}
+
+/// JSON body of the response, after checking its status.
+pub async fn json(response: reqwest::Response, status: u16) -> Value {
+    let actual = response.status().as_u16();
+    let body = response.text().await.expect("Failed to read response body");
+
+    assert_eq!(actual, status, "unexpected status, body: {body}");
+
+    serde_json::from_str(&body).unwrap_or(Value::String(body))
+}
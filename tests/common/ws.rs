@@ -0,0 +1,126 @@
+//! Blocking WebSocket client for the collaborative editing connections of the server,
+//! with text and close frames only. The server runs on its own thread, so tests block on it.
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+pub struct WsClient {
+    reader: BufReader<TcpStream>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum WsMessage {
+    Text(Value),
+    /// Close frame with its status code.
+    Close(u16),
+}
+
+impl WsClient {
+    /// Opens the connection to the path of the server, returns the status of the response
+    /// when the connection is not upgraded.
+    pub fn connect(url: &str, path: &str, access_token: Option<&str>) -> Result<Self, u16> {
+        let host = url.trim_start_matches("http://");
+        let mut stream = TcpStream::connect(host).expect("Failed to connect to the server");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .expect("Failed to set read timeout");
+
+        let authorization = access_token
+            .map(|token| format!("Authorization: Bearer {token}\r\n"))
+            .unwrap_or_default();
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n{authorization}\r\n"
+        )
+        .expect("Failed to send the handshake");
+
+        let mut reader = BufReader::new(stream);
+        let mut status_line = String::new();
+        reader.read_line(&mut status_line).expect("Failed to read the handshake response");
+        let status = status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|status| status.parse().ok())
+            .expect("Invalid status line");
+
+        // Headers of the response, the body of a failed handshake isn't read.
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).expect("Failed to read the handshake response");
+            if line == "\r\n" || line.is_empty() {
+                break;
+            }
+        }
+
+        match status {
+            101 => Ok(WsClient { reader }),
+            status => Err(status),
+        }
+    }
+
+    pub fn send(&mut self, message: &Value) {
+        let payload = message.to_string().into_bytes();
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0x81];
+
+        // Client frames are masked.
+        match payload.len() {
+            len if len < 126 => frame.push(0x80 | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(0x80 | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, byte)| byte ^ mask[i % 4]));
+
+        self.reader
+            .get_mut()
+            .write_all(&frame)
+            .expect("Failed to send the frame");
+    }
+
+    /// Next text or close message, control frames are skipped.
+    pub fn recv(&mut self) -> WsMessage {
+        loop {
+            let mut header = [0; 2];
+            self.reader.read_exact(&mut header).expect("Failed to read the frame");
+
+            let len = match header[1] & 0x7f {
+                126 => u16::from_be_bytes(self.read_array()) as usize,
+                127 => u64::from_be_bytes(self.read_array()) as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0; len];
+            self.reader.read_exact(&mut payload).expect("Failed to read the frame");
+
+            match header[0] & 0x0f {
+                0x1 => return WsMessage::Text(serde_json::from_slice(&payload).expect("Message is not JSON")),
+                0x8 => return WsMessage::Close(payload.get(..2).map_or(1005, |code| u16::from_be_bytes([code[0], code[1]]))),
+                _ => continue,
+            }
+        }
+    }
+
+    /// Next text message, fails on other messages.
+    pub fn recv_json(&mut self) -> Value {
+        match self.recv() {
+            WsMessage::Text(value) => value,
+            message => panic!("Expected a text message, received {message:?}"),
+        }
+    }
+
+    fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes).expect("Failed to read the frame");
+
+        bytes
+    }
+}